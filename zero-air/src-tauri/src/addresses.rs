@@ -0,0 +1,236 @@
+/// Recipients — every address of a message, by role, in the `email_addresses` table.
+/// `sender`, `sender_email` and `to_email` on `emails` only hold the first From and
+/// To address; this is the full list, for reply-all, search by participant and contacts.
+use tauri::{AppHandle, Manager};
+use crate::db::{DbState, Email};
+use async_imap::imap_proto::Envelope;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct EmailAddress {
+    /// "from", "reply_to", "to", "cc" or "bcc"
+    pub role: String,
+    pub name: Option<String>,
+    pub address: String,
+}
+
+/// Someone the account has exchanged mail with
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
+pub struct Contact {
+    pub address: String,
+    pub name: Option<String>,
+    /// Messages the address appears on, in any role
+    pub message_count: i64,
+    pub last_seen: Option<String>,
+}
+
+/// The addresses of an ENVELOPE. Servers fill in a missing Reply-To with From,
+/// so `reply_to` is always the address to answer to.
+pub fn from_envelope(envelope: &Envelope) -> Vec<EmailAddress> {
+    [
+        ("from", &envelope.from),
+        ("reply_to", &envelope.reply_to),
+        ("to", &envelope.to),
+        ("cc", &envelope.cc),
+        ("bcc", &envelope.bcc),
+    ]
+    .into_iter()
+    .flat_map(|(role, addrs)| {
+        crate::imap::envelope_addresses(addrs.as_deref().unwrap_or_default())
+            .into_iter()
+            .map(move |(name, address)| EmailAddress { role: role.to_string(), name, address })
+    })
+    .collect()
+}
+
+/// The addresses of a header value typed by the user, e.g. the To field of a draft
+pub fn from_header_value(value: &str, role: &str) -> Vec<EmailAddress> {
+    let Ok(list) = mailparse::addrparse(value) else { return Vec::new() };
+    list.iter()
+        .flat_map(|addr| match addr {
+            mailparse::MailAddr::Single(single) => vec![single.clone()],
+            mailparse::MailAddr::Group(group) => group.addrs.clone(),
+        })
+        // mailparse keeps the separator on an address that follows a group: "g: a;, b"
+        .map(|single| (single.display_name, single.addr.trim_start_matches(|c: char| c == ',' || c.is_whitespace()).to_string()))
+        .filter(|(_, address)| !address.is_empty())
+        .map(|(name, address)| EmailAddress {
+            role: role.to_string(),
+            name: name.filter(|n| !n.is_empty()),
+            address,
+        })
+        .collect()
+}
+
+/// The addresses of a message written here: the account as From, then the To field
+pub fn outgoing(from_name: &str, from_email: &str, to: &str) -> Vec<EmailAddress> {
+    let from = EmailAddress {
+        role: "from".to_string(),
+        name: Some(from_name.trim().to_string()).filter(|n| !n.is_empty() && n != from_email),
+        address: from_email.to_string(),
+    };
+    std::iter::once(from).chain(from_header_value(to, "to")).collect()
+}
+
+/// Replace the address rows of one message
+pub async fn store_addresses(pool: &SqlitePool, email_id: &str, addresses: &[EmailAddress]) -> Result<(), String> {
+    sqlx::query("DELETE FROM email_addresses WHERE email_id = $1")
+        .bind(email_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    for (position, addr) in addresses.iter().enumerate() {
+        sqlx::query(
+            "INSERT OR REPLACE INTO email_addresses (email_id, position, role, name, address) VALUES ($1, $2, $3, $4, $5)"
+        )
+        .bind(email_id)
+        .bind(position as i64)
+        .bind(&addr.role)
+        .bind(&addr.name)
+        .bind(&addr.address)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Fill in `addresses` on rows loaded from `emails`
+pub async fn load_addresses(pool: &SqlitePool, emails: &mut [Email]) -> Result<(), String> {
+    let mut by_email: HashMap<String, Vec<EmailAddress>> = HashMap::new();
+    for (chunk, placeholders) in crate::db::in_chunks(emails, 1) {
+        let sql = format!(
+            "SELECT email_id, role, name, address FROM email_addresses WHERE email_id IN ({}) ORDER BY email_id, position",
+            placeholders
+        );
+        let mut query = sqlx::query_as::<_, (String, String, Option<String>, String)>(&sql);
+        for email in chunk {
+            query = query.bind(&email.id);
+        }
+        let rows = query.fetch_all(pool).await.map_err(|e| format!("DB error: {}", e))?;
+        for (email_id, role, name, address) in rows {
+            by_email.entry(email_id).or_default().push(EmailAddress { role, name, address });
+        }
+    }
+    for email in emails.iter_mut() {
+        email.addresses = by_email.remove(&email.id).unwrap_or_default();
+    }
+    Ok(())
+}
+
+/// `%text%` for LIKE, with the wildcards in `text` escaped
+fn like_pattern(text: &str) -> String {
+    format!("%{}%", text.trim().replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"))
+}
+
+/// Tauri command: addresses the account has mail from or to, most frequent first,
+/// optionally matching `query` in the address or name (for recipient suggestions)
+#[tauri::command]
+pub async fn get_contacts(app: AppHandle, account_id: String, query: Option<String>, limit: Option<i64>) -> Result<Vec<Contact>, String> {
+    let state = app.state::<DbState>();
+    sqlx::query_as::<_, Contact>(
+        r#"SELECT LOWER(a.address) AS address, MAX(a.name) AS name, COUNT(DISTINCT a.email_id) AS message_count, MAX(e.date) AS last_seen
+           FROM email_addresses a JOIN emails e ON e.id = a.email_id
+           WHERE e.account_id = $1
+             AND LOWER(a.address) != COALESCE((SELECT LOWER(email) FROM accounts WHERE id = $1), '')
+             AND ($2 IS NULL OR a.address LIKE $2 ESCAPE '\' OR a.name LIKE $2 ESCAPE '\')
+           GROUP BY LOWER(a.address)
+           ORDER BY message_count DESC, last_seen DESC
+           LIMIT $3"#
+    )
+    .bind(&account_id)
+    .bind(query.as_deref().filter(|q| !q.trim().is_empty()).map(like_pattern))
+    .bind(limit.unwrap_or(20))
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))
+}
+
+/// Tauri command: emails of the account with an address or name matching `address`,
+/// in any role or only in `role` ("from", "to", ...), newest first
+#[tauri::command]
+pub async fn get_emails_by_address(app: AppHandle, account_id: String, address: String, role: Option<String>) -> Result<Vec<Email>, String> {
+    let state = app.state::<DbState>();
+    let mut emails = sqlx::query_as::<_, Email>(
+        r#"SELECT id, uid, account_id, folder, subject, sender, sender_email, to_email, date, snippet, body, body_cached, read, flagged, answered, ai_priority, ai_labels, ai_summary, gm_msgid, gm_thrid, message_id, thread_id
+           FROM emails
+           WHERE account_id = $1 AND id IN (
+               SELECT email_id FROM email_addresses
+               WHERE (address LIKE $2 ESCAPE '\' OR name LIKE $2 ESCAPE '\') AND ($3 IS NULL OR role = $3)
+           )
+           ORDER BY date DESC
+           LIMIT 500"#
+    )
+    .bind(&account_id)
+    .bind(like_pattern(&address))
+    .bind(&role)
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+    load_addresses(&state.pool, &mut emails).await?;
+    Ok(emails)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(addresses: &[EmailAddress]) -> Vec<(&str, Option<&str>, &str)> {
+        addresses.iter().map(|a| (a.role.as_str(), a.name.as_deref(), a.address.as_str())).collect()
+    }
+
+    #[test]
+    fn splits_on_commas_outside_quotes() {
+        let to = r#""Doe, Jane" <jane@example.com>, bob@example.com, "O'Brien, P." <pat@example.org>"#;
+        assert_eq!(
+            pairs(&from_header_value(to, "to")),
+            vec![
+                ("to", Some("Doe, Jane"), "jane@example.com"),
+                ("to", None, "bob@example.com"),
+                ("to", Some("O'Brien, P."), "pat@example.org"),
+            ]
+        );
+    }
+
+    #[test]
+    fn flattens_groups() {
+        let cc = "Team: ann@example.com, \"Lee, Max\" <max@example.com>;, carl@example.com";
+        assert_eq!(
+            pairs(&from_header_value(cc, "cc")),
+            vec![
+                ("cc", None, "ann@example.com"),
+                ("cc", Some("Lee, Max"), "max@example.com"),
+                ("cc", None, "carl@example.com"),
+            ]
+        );
+        assert!(from_header_value("undisclosed-recipients:;", "to").is_empty());
+    }
+
+    #[test]
+    fn drops_empty_display_names() {
+        assert_eq!(pairs(&from_header_value(r#""" <a@example.com>"#, "to")), vec![("to", None, "a@example.com")]);
+    }
+
+    #[test]
+    fn puts_the_account_first_on_outgoing_mail() {
+        assert_eq!(
+            pairs(&outgoing("Me", "me@example.com", r#""Doe, Jane" <jane@example.com>"#)),
+            vec![("from", Some("Me"), "me@example.com"), ("to", Some("Doe, Jane"), "jane@example.com")]
+        );
+        // A name that is just the address, or none at all, isn't kept
+        assert_eq!(pairs(&outgoing("me@example.com", "me@example.com", "a@example.com"))[0], ("from", None, "me@example.com"));
+        assert_eq!(pairs(&outgoing("", "me@example.com", "a@example.com"))[0], ("from", None, "me@example.com"));
+    }
+
+    #[test]
+    fn outgoing_without_recipients_is_only_from() {
+        assert_eq!(pairs(&outgoing("Me", "me@example.com", "")), vec![("from", Some("Me"), "me@example.com")]);
+        assert_eq!(pairs(&outgoing("Me", "me@example.com", "   ")), vec![("from", Some("Me"), "me@example.com")]);
+    }
+
+    #[test]
+    fn escapes_like_wildcards() {
+        assert_eq!(like_pattern(" 100%_off\\ "), "%100\\%\\_off\\\\%");
+    }
+}
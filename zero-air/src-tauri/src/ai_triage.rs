@@ -361,8 +361,14 @@ pub async fn run_triage_on_new_emails(app: &AppHandle, account_id: &str) {
 
     // Process up to 10 emails per run to avoid overwhelming AI
     let max_per_run = 10;
+    // At shutdown, stop between emails; the rest is picked up on the next run
+    let shutdown = crate::tasks::token();
 
     loop {
+        if shutdown.is_cancelled() {
+            log::info!("[TRIAGE] Shutting down, stopping after {} emails.", processed);
+            break;
+        }
         if processed >= max_per_run {
             log::info!("[TRIAGE] Reached max {} per run, remaining will be processed next trigger.", max_per_run);
             break;
@@ -374,7 +380,10 @@ pub async fn run_triage_on_new_emails(app: &AppHandle, account_id: &str) {
         if now - last_call < MIN_AI_INTERVAL_SECS {
             let wait = (MIN_AI_INTERVAL_SECS - (now - last_call)) as u64;
             log::info!("[TRIAGE] Rate limit: waiting {}s before next call", wait);
-            tokio::time::sleep(tokio::time::Duration::from_secs(wait)).await;
+            tokio::select! {
+                _ = tokio::time::sleep(tokio::time::Duration::from_secs(wait)) => {}
+                _ = shutdown.cancelled() => continue,
+            }
         }
 
         // Get the next unprocessed email
@@ -397,6 +406,19 @@ pub async fn run_triage_on_new_emails(app: &AppHandle, account_id: &str) {
         };
 
         let (email_id, sender, sender_email, subject, snippet) = email;
+
+        // Header-first sync leaves the body for later — the classifier needs the content
+        let snippet = if snippet.is_empty() {
+            match crate::imap_body::ensure_body_cached(app, &email_id).await {
+                Ok(body) => crate::imap::strip_html_tags(&body).chars().take(150).collect(),
+                Err(e) => {
+                    log::warn!("[TRIAGE] Could not fetch body for {}: {}", email_id, e);
+                    snippet
+                }
+            }
+        } else {
+            snippet
+        };
         log::info!("[TRIAGE] [{}/{}] Classifying: \"{}\" from {} <{}>", processed + 1, unprocessed_count.min(max_per_run), subject, sender, sender_email);
 
         // Emit progress to frontend
@@ -461,7 +483,7 @@ pub async fn run_triage_on_new_emails(app: &AppHandle, account_id: &str) {
             let draft_subject = subject.clone();
             let draft_snippet = snippet.to_string();
             let draft_email_id = email_id.clone();
-            tokio::spawn(async move {
+            crate::tasks::spawn("proactive-draft", async move {
                 match proactive_draft(
                     draft_app.clone(),
                     draft_email_id.clone(),
@@ -502,7 +524,7 @@ pub async fn run_triage_on_new_emails(app: &AppHandle, account_id: &str) {
 #[tauri::command]
 pub async fn trigger_triage(app: AppHandle, account_id: String) -> Result<(), String> {
     let app_clone = app.clone();
-    tokio::spawn(async move {
+    crate::tasks::spawn("triage", async move {
         run_triage_on_new_emails(&app_clone, &account_id).await;
     });
     Ok(())
@@ -0,0 +1,229 @@
+/// Attachments — every non-body leaf of a message's BODYSTRUCTURE is indexed in the
+/// `attachments` table when the message is stored. Contents are downloaded on demand
+/// through `imap_body::load_part_bytes`, which caches them in `email_parts`.
+use tauri::{AppHandle, Manager};
+use crate::db::DbState;
+use crate::imap_body::MimePart;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use sqlx::SqlitePool;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
+pub struct Attachment {
+    pub email_id: String,
+    pub part_path: String,
+    pub filename: String,
+    pub mime_type: String,
+    /// Decoded size in bytes, estimated from the encoded size
+    pub size: Option<i64>,
+    pub content_id: Option<String>,
+    /// Shown within the body (e.g. an image referenced by `cid:`) rather than listed
+    pub inline: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AttachmentData {
+    #[serde(flatten)]
+    pub attachment: Attachment,
+    /// Base64 of the decoded content
+    pub data: String,
+}
+
+/// Leaves that aren't the displayed text body: anything marked as an attachment,
+/// anything with a filename or Content-ID, and non-text parts
+pub fn attachment_parts(parts: &[MimePart]) -> Vec<&MimePart> {
+    let (html, plain) = crate::imap_body::displayable_parts(parts);
+    let body_paths: Vec<String> = [html, plain].into_iter().flatten().map(|p| p.path).collect();
+    parts
+        .iter()
+        .filter(|p| !body_paths.contains(&p.path))
+        .filter(|p| p.is_attachment() || p.filename.is_some() || p.content_id.is_some() || !p.mime_type.starts_with("text/"))
+        .collect()
+}
+
+/// A name that is safe to use as a file name, never a path
+fn safe_filename(name: Option<&str>, part: &MimePart) -> String {
+    let cleaned: String = name
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_control() || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') { '_' } else { c })
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.').to_string();
+    if !cleaned.is_empty() {
+        return cleaned;
+    }
+    let extension: String = match part.mime_type.as_str() {
+        "message/rfc822" => "eml".to_string(),
+        mime => mime.rsplit('/').next().unwrap_or_default().chars().filter(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-')).collect(),
+    };
+    let extension = if extension.is_empty() { "bin" } else { &extension };
+    format!("part-{}.{}", part.path, extension)
+}
+
+/// Size of the decoded content; base64 takes 4 bytes for every 3
+fn decoded_size(part: &MimePart) -> Option<i64> {
+    let size = part.size? as i64;
+    Some(if part.encoding.as_deref() == Some("base64") { size * 3 / 4 } else { size })
+}
+
+/// Replace the attachment rows of one message with those found in `parts`
+pub async fn index_attachments(pool: &SqlitePool, email_id: &str, parts: &[MimePart]) -> Result<(), String> {
+    sqlx::query("DELETE FROM attachments WHERE email_id = $1")
+        .bind(email_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    for part in attachment_parts(parts) {
+        sqlx::query(
+            "INSERT OR REPLACE INTO attachments (email_id, part_path, filename, mime_type, size, content_id, inline) VALUES ($1, $2, $3, $4, $5, $6, $7)"
+        )
+        .bind(email_id)
+        .bind(&part.path)
+        .bind(safe_filename(part.filename.as_deref(), part))
+        .bind(&part.mime_type)
+        .bind(decoded_size(part))
+        .bind(&part.content_id)
+        .bind(!part.is_attachment() && part.content_id.is_some())
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Attachments of a message. Server messages stored without a BODYSTRUCTURE
+/// get it from the server first.
+pub async fn load_attachments(app: &AppHandle, email_id: &str) -> Result<Vec<Attachment>, String> {
+    let state = app.state::<DbState>();
+    let pool = state.pool.clone();
+
+    let stored = crate::imap_body::load_stored_message(app, email_id).await?;
+    let on_server = email_id == crate::imap::local_email_id(&stored.account.id, &stored.folder, stored.uid);
+    if stored.parts.is_empty() && on_server {
+        let mut session = crate::imap_session::acquire(&pool, &stored.account).await?;
+        let result = async {
+            crate::imap::select_mailbox(&mut session, &stored.mailbox).await?;
+            let messages = crate::imap::uid_fetch_all(&mut session, &stored.uid.to_string(), "(UID BODYSTRUCTURE)").await?;
+            Ok(messages.first()
+                .and_then(|m| m.bodystructure())
+                .map(crate::imap_body::flatten_bodystructure)
+                .unwrap_or_default())
+        }
+        .await;
+        let parts = session.finish(result)?;
+
+        sqlx::query("UPDATE emails SET body_structure = $1 WHERE id = $2")
+            .bind(serde_json::to_string(&parts).unwrap_or_default())
+            .bind(email_id)
+            .execute(&pool)
+            .await
+            .map_err(|e| format!("DB error: {}", e))?;
+        index_attachments(&pool, email_id, &parts).await?;
+    }
+
+    sqlx::query_as::<_, Attachment>(
+        "SELECT email_id, part_path, filename, mime_type, size, content_id, inline FROM attachments WHERE email_id = $1 ORDER BY part_path"
+    )
+    .bind(email_id)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))
+}
+
+async fn find_attachment(app: &AppHandle, email_id: &str, part: &str) -> Result<Attachment, String> {
+    load_attachments(app, email_id)
+        .await?
+        .into_iter()
+        .find(|a| a.part_path == part)
+        .ok_or_else(|| format!("Unknown attachment: {}", part))
+}
+
+/// Tauri command: attachments of an email (filenames, types, sizes)
+#[tauri::command]
+pub async fn get_attachments(app: AppHandle, email_id: String) -> Result<Vec<Attachment>, String> {
+    load_attachments(&app, &email_id).await
+}
+
+/// Tauri command: the content of one attachment, from the cache or the server
+#[tauri::command]
+pub async fn download_attachment(app: AppHandle, email_id: String, part: String) -> Result<AttachmentData, String> {
+    let attachment = find_attachment(&app, &email_id, &part).await?;
+    let (_, data) = crate::imap_body::load_part_bytes(&app, &email_id, &part).await?;
+    Ok(AttachmentData { attachment, data: STANDARD.encode(data) })
+}
+
+/// Tauri command: write an attachment to `path`. A directory gets the attachment's
+/// own file name inside it. Returns the path written.
+#[tauri::command]
+pub async fn save_attachment(app: AppHandle, email_id: String, part: String, path: String) -> Result<String, String> {
+    let attachment = find_attachment(&app, &email_id, &part).await?;
+    let (_, data) = crate::imap_body::load_part_bytes(&app, &email_id, &part).await?;
+
+    let mut target = PathBuf::from(&path);
+    if target.is_dir() {
+        target.push(&attachment.filename);
+    }
+    if let Some(dir) = target.parent().filter(|d| !d.as_os_str().is_empty()) {
+        if !dir.is_dir() {
+            return Err(format!("Folder does not exist: {}", dir.display()));
+        }
+    }
+    tokio::fs::write(&target, &data)
+        .await
+        .map_err(|e| format!("Failed to save {}: {}", target.display(), e))?;
+
+    log::info!("[ATTACH] Saved {} ({} bytes) to {}", attachment.filename, data.len(), target.display());
+    Ok(target.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(mime_type: &str, encoding: Option<&str>, size: Option<u32>) -> MimePart {
+        MimePart {
+            path: "2.1".to_string(),
+            mime_type: mime_type.to_string(),
+            charset: None,
+            encoding: encoding.map(str::to_string),
+            size,
+            disposition: Some("attachment".to_string()),
+            filename: None,
+            content_id: None,
+        }
+    }
+
+    #[test]
+    fn keeps_filenames_out_of_other_folders() {
+        let pdf = part("application/pdf", None, None);
+        assert_eq!(safe_filename(Some("report.pdf"), &pdf), "report.pdf");
+        assert_eq!(safe_filename(Some("../../etc/passwd"), &pdf), "_.._etc_passwd");
+        assert_eq!(safe_filename(Some("..\\..\\boot.ini"), &pdf), "_.._boot.ini");
+        assert_eq!(safe_filename(Some("/etc/passwd"), &pdf), "_etc_passwd");
+        assert_eq!(safe_filename(Some("C:\\Windows\\evil.dll"), &pdf), "C__Windows_evil.dll");
+        assert_eq!(safe_filename(Some(".bashrc"), &pdf), "bashrc");
+        assert_eq!(safe_filename(Some("a\0b\r\n.txt"), &pdf), "a_b__.txt");
+        assert_eq!(safe_filename(Some("what? \"yes\" <no> | *.txt"), &pdf), "what_ _yes_ _no_ _ _.txt");
+    }
+
+    #[test]
+    fn names_unnamed_parts_after_their_path() {
+        assert_eq!(safe_filename(None, &part("image/png", None, None)), "part-2.1.png");
+        assert_eq!(safe_filename(Some(" .. "), &part("image/svg+xml", None, None)), "part-2.1.svg+xml");
+        assert_eq!(safe_filename(Some("\0"), &part("message/rfc822", None, None)), "_");
+        assert_eq!(safe_filename(Some(""), &part("message/rfc822", None, None)), "part-2.1.eml");
+        assert_eq!(safe_filename(None, &part("application/..", None, None)), "part-2.1.bin");
+        assert_eq!(safe_filename(None, &part("x/a\\..\\b", None, None)), "part-2.1.ab");
+    }
+
+    #[test]
+    fn estimates_decoded_size() {
+        assert_eq!(decoded_size(&part("image/png", Some("base64"), Some(4000))), Some(3000));
+        assert_eq!(decoded_size(&part("image/png", Some("base64"), Some(6))), Some(4));
+        assert_eq!(decoded_size(&part("text/plain", Some("7bit"), Some(123))), Some(123));
+        assert_eq!(decoded_size(&part("text/plain", None, Some(u32::MAX))), Some(u32::MAX as i64));
+        assert_eq!(decoded_size(&part("image/png", Some("base64"), Some(u32::MAX))), Some(u32::MAX as i64 * 3 / 4));
+        assert_eq!(decoded_size(&part("image/png", Some("base64"), None)), None);
+    }
+}
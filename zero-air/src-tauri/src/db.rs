@@ -8,7 +8,19 @@ pub struct DbState {
     pub pool: SqlitePool,
 }
 
-#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+/// Rows per `IN (...)` list, well below SQLite's bound parameter limit
+const ID_CHUNK: usize = 500;
+
+/// `values` split for `IN (...)` lists, each chunk with its placeholders numbered
+/// from `$first`; the parameters before that are the caller's
+pub fn in_chunks<T>(values: &[T], first: usize) -> impl Iterator<Item = (&[T], String)> {
+    values.chunks(ID_CHUNK).map(move |chunk| {
+        let placeholders: Vec<String> = (first..first + chunk.len()).map(|i| format!("${}", i)).collect();
+        (chunk, placeholders.join(", "))
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
 pub struct Account {
     pub id: String,
     pub full_name: Option<String>,
@@ -18,6 +30,25 @@ pub struct Account {
     pub imap_port: Option<i32>,
     pub smtp_host: Option<String>,
     pub smtp_port: Option<i32>,
+    /// "tls" (default), "starttls" or "none"; see `tls::Security`
+    #[serde(default)]
+    #[sqlx(default)]
+    pub imap_security: Option<String>,
+    #[serde(default)]
+    #[sqlx(default)]
+    pub smtp_security: Option<String>,
+    /// Extra trusted CA certificates (PEM) for self-hosted servers
+    #[serde(default)]
+    #[sqlx(default)]
+    pub ca_cert: Option<String>,
+    /// Trust-on-first-use pinning of the server certificates
+    #[serde(default)]
+    #[sqlx(default)]
+    pub tls_pinning: Option<bool>,
+    /// "password" (default) or "oauth2"; set by the OAuth2 sign-in, not by `save_account`
+    #[serde(default)]
+    #[sqlx(default)]
+    pub auth_method: Option<String>,
 }
 
 #[tauri::command]
@@ -28,15 +59,19 @@ pub async fn save_account(
     let state = app.state::<DbState>();
     sqlx::query(
         r#"
-        INSERT INTO accounts (id, email, password, imap_host, imap_port, smtp_host, smtp_port, full_name)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        INSERT INTO accounts (id, email, password, imap_host, imap_port, smtp_host, smtp_port, full_name, imap_security, smtp_security, ca_cert, tls_pinning)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
         ON CONFLICT(email) DO UPDATE SET
             password = excluded.password,
             imap_host = excluded.imap_host,
             imap_port = excluded.imap_port,
             smtp_host = excluded.smtp_host,
             smtp_port = excluded.smtp_port,
-            full_name = excluded.full_name
+            full_name = excluded.full_name,
+            imap_security = excluded.imap_security,
+            smtp_security = excluded.smtp_security,
+            ca_cert = excluded.ca_cert,
+            tls_pinning = excluded.tls_pinning
         "#
     )
     .bind(&account.id)
@@ -47,10 +82,16 @@ pub async fn save_account(
     .bind(&account.smtp_host)
     .bind(&account.smtp_port)
     .bind(&account.full_name)
+    .bind(&account.imap_security)
+    .bind(&account.smtp_security)
+    .bind(&account.ca_cert)
+    .bind(account.tls_pinning)
     .execute(&state.pool)
     .await
     .map_err(|e| e.to_string())?;
 
+    // New or changed account: (re)start its IDLE watchers
+    crate::imap_idle::refresh_watchers();
     Ok(())
 }
 
@@ -60,7 +101,7 @@ pub async fn get_accounts(
 ) -> Result<Vec<Account>, String> {
     let state = app.state::<DbState>();
     let accounts = sqlx::query_as::<_, Account>(
-        "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port FROM accounts"
+        "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port, imap_security, smtp_security, ca_cert, tls_pinning, auth_method FROM accounts"
     )
     .fetch_all(&state.pool)
     .await
@@ -69,7 +110,7 @@ pub async fn get_accounts(
     Ok(accounts)
 }
 
-#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+#[derive(Serialize, Deserialize, Debug, Default, sqlx::FromRow)]
 pub struct Email {
     pub id: String,
     pub uid: i64,
@@ -82,10 +123,35 @@ pub struct Email {
     pub date: Option<String>,
     pub snippet: Option<String>,
     pub body: Option<String>,
+    pub body_cached: Option<bool>,
     pub read: Option<bool>,
+    pub flagged: Option<bool>,
+    pub answered: Option<bool>,
     pub ai_priority: Option<String>,
     pub ai_labels: Option<String>,
     pub ai_summary: Option<String>,
+    /// Gmail message and thread ids (X-GM-MSGID, X-GM-THRID)
+    pub gm_msgid: Option<i64>,
+    pub gm_thrid: Option<i64>,
+    pub message_id: Option<String>,
+    /// Conversation, see `threads`
+    pub thread_id: Option<String>,
+    /// Every From, Reply-To, To, Cc and Bcc address (`addresses::load_addresses`)
+    #[serde(default)]
+    #[sqlx(skip)]
+    pub addresses: Vec<crate::addresses::EmailAddress>,
+}
+
+impl Email {
+    /// Identifies the message across its copies: the X-GM-MSGID on Gmail, which is
+    /// the same in All Mail and every label folder, else the Message-ID header
+    pub fn message_key(&self) -> String {
+        match (self.gm_msgid, &self.message_id) {
+            (Some(msgid), _) => format!("gm:{}", msgid),
+            (None, Some(message_id)) => message_id.clone(),
+            (None, None) => self.id.clone(),
+        }
+    }
 }
 
 #[tauri::command]
@@ -115,14 +181,18 @@ pub async fn get_emails(
     folder: String,
 ) -> Result<Vec<Email>, String> {
     let state = app.state::<DbState>();
-    let emails = sqlx::query_as::<_, Email>(
-        "SELECT id, uid, account_id, folder, subject, sender, sender_email, to_email, date, snippet, body, read, ai_priority, ai_labels, ai_summary FROM emails WHERE account_id = $1 AND folder = $2 ORDER BY uid DESC"
+    let mut emails = sqlx::query_as::<_, Email>(
+        "SELECT id, uid, account_id, folder, subject, sender, sender_email, to_email, date, snippet, body, body_cached, read, flagged, answered, ai_priority, ai_labels, ai_summary, gm_msgid, gm_thrid, message_id, thread_id FROM emails WHERE account_id = $1 AND folder = $2 ORDER BY uid DESC"
     )
     .bind(&account_id)
     .bind(&folder)
     .fetch_all(&state.pool)
     .await
     .map_err(|e: sqlx::Error| e.to_string())?;
+    // A Gmail message shows up once, even if a move left a second copy behind
+    let mut seen = std::collections::HashSet::new();
+    emails.retain(|e| e.gm_msgid.map_or(true, |msgid| seen.insert(msgid)));
+    crate::addresses::load_addresses(&state.pool, &mut emails).await?;
 
     Ok(emails)
 }
@@ -160,6 +230,10 @@ pub async fn delete_email(
         .execute(&state.pool)
         .await
         .map_err(|e| e.to_string())?;
+    let _ = sqlx::query("DELETE FROM email_addresses WHERE email_id = $1")
+        .bind(&email_id)
+        .execute(&state.pool)
+        .await;
     Ok(())
 }
 
@@ -234,7 +308,14 @@ pub async fn init_db(app: &AppHandle) -> Result<SqlitePool, sqlx::Error> {
             imap_host TEXT,
             imap_port INTEGER,
             smtp_host TEXT,
-            smtp_port INTEGER
+            smtp_port INTEGER,
+            imap_security TEXT,
+            smtp_security TEXT,
+            ca_cert TEXT,
+            tls_pinning BOOLEAN DEFAULT 0,
+            auth_method TEXT,
+            idle_folders TEXT,
+            poll_interval INTEGER
         );
         CREATE TABLE IF NOT EXISTS emails (
             id TEXT PRIMARY KEY,
@@ -248,13 +329,23 @@ pub async fn init_db(app: &AppHandle) -> Result<SqlitePool, sqlx::Error> {
             snippet TEXT,
             body TEXT,
             read BOOLEAN DEFAULT 0,
+            flagged BOOLEAN DEFAULT 0,
+            answered BOOLEAN DEFAULT 0,
+            size INTEGER,
+            body_structure TEXT,            -- JSON list of MIME leaves (imap_body::MimePart)
+            body_cached BOOLEAN DEFAULT 0,
             ai_priority TEXT,
             ai_labels TEXT,
             ai_summary TEXT,
+            message_id TEXT,
+            in_reply_to TEXT,
+            refs TEXT,                      -- References, space separated, oldest first
+            thread_id TEXT,                 -- Message-ID at the top of the conversation (threads.rs)
             FOREIGN KEY(account_id) REFERENCES accounts(id)
         );
         CREATE INDEX IF NOT EXISTS idx_emails_date ON emails(date DESC);
         CREATE INDEX IF NOT EXISTS idx_emails_folder ON emails(folder);
+        CREATE INDEX IF NOT EXISTS idx_emails_thread ON emails(account_id, thread_id);
         
         CREATE TABLE IF NOT EXISTS ai_prompt_history (
             id TEXT PRIMARY KEY,
@@ -310,6 +401,114 @@ pub async fn init_db(app: &AppHandle) -> Result<SqlitePool, sqlx::Error> {
             created_at TEXT NOT NULL,
             updated_at TEXT NOT NULL
         );
+
+        -- Lazily fetched MIME parts, transfer-decoded
+        CREATE TABLE IF NOT EXISTS email_parts (
+            email_id TEXT NOT NULL,
+            part_path TEXT NOT NULL,
+            data BLOB NOT NULL,
+            PRIMARY KEY (email_id, part_path)
+        );
+
+        -- Attachments found in each message's BODYSTRUCTURE; filenames are decoded (RFC 2231/2047)
+        CREATE TABLE IF NOT EXISTS attachments (
+            email_id TEXT NOT NULL,
+            part_path TEXT NOT NULL,
+            filename TEXT NOT NULL,
+            mime_type TEXT NOT NULL,
+            size INTEGER,
+            content_id TEXT,
+            inline BOOLEAN NOT NULL DEFAULT 0,
+            PRIMARY KEY (email_id, part_path)
+        );
+
+        -- Per-folder IMAP sync cursor: a UIDVALIDITY change invalidates every stored UID
+        CREATE TABLE IF NOT EXISTS folder_sync_state (
+            account_id TEXT NOT NULL,
+            folder TEXT NOT NULL,
+            uid_validity INTEGER NOT NULL,
+            uid_next INTEGER,
+            highest_uid INTEGER NOT NULL DEFAULT 0,
+            highest_modseq INTEGER,
+            lowest_uid INTEGER,
+            backfill_done BOOLEAN NOT NULL DEFAULT 0,
+            last_sync_at TEXT,
+            PRIMARY KEY (account_id, folder)
+        );
+
+        -- Server folder tree (LIST/LSUB), role = special-use mapping (Sent, Trash, ...)
+        CREATE TABLE IF NOT EXISTS folders (
+            account_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            delimiter TEXT,
+            parent TEXT,
+            display_name TEXT NOT NULL,
+            role TEXT,
+            attributes TEXT NOT NULL DEFAULT '',
+            selectable BOOLEAN NOT NULL DEFAULT 1,
+            subscribed BOOLEAN NOT NULL DEFAULT 0,
+            PRIMARY KEY (account_id, name)
+        );
+
+        -- Trust-on-first-use certificate pins, SHA-256 of the server certificate
+        CREATE TABLE IF NOT EXISTS tls_pins (
+            host TEXT NOT NULL,
+            port INTEGER NOT NULL,
+            fingerprint TEXT NOT NULL,
+            pinned_at TEXT NOT NULL,
+            PRIMARY KEY (host, port)
+        );
+
+        -- CAPABILITY of each account's IMAP server, refreshed on every new connection
+        CREATE TABLE IF NOT EXISTS imap_capabilities (
+            account_id TEXT PRIMARY KEY,
+            capabilities TEXT NOT NULL,
+            checked_at TEXT NOT NULL
+        );
+
+        -- OAuth2 client settings and tokens of accounts signing in with OAuth2
+        CREATE TABLE IF NOT EXISTS oauth_accounts (
+            account_id TEXT PRIMARY KEY,
+            auth_url TEXT NOT NULL,
+            token_url TEXT NOT NULL,
+            client_id TEXT NOT NULL,
+            client_secret TEXT,
+            scope TEXT NOT NULL,
+            mechanism TEXT NOT NULL DEFAULT 'XOAUTH2',
+            access_token TEXT,
+            refresh_token TEXT,
+            expires_at INTEGER
+        );
+
+        -- Gmail labels (X-GM-LABELS), once per message however many folders show it
+        CREATE TABLE IF NOT EXISTS gmail_labels (
+            account_id TEXT NOT NULL,
+            gm_msgid INTEGER NOT NULL,
+            label TEXT NOT NULL,
+            PRIMARY KEY (account_id, gm_msgid, label)
+        );
+
+        -- Every address of each message by role ('from', 'reply_to', 'to', 'cc', 'bcc'), in header order
+        CREATE TABLE IF NOT EXISTS email_addresses (
+            email_id TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            role TEXT NOT NULL,
+            name TEXT,
+            address TEXT NOT NULL,
+            PRIMARY KEY (email_id, position)
+        );
+        CREATE INDEX IF NOT EXISTS idx_email_addresses_address ON email_addresses(address COLLATE NOCASE);
+
+        -- Server operations applied locally while offline, replayed in id order (payload is JSON)
+        CREATE TABLE IF NOT EXISTS pending_operations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            account_id TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT,
+            created_at TEXT NOT NULL
+        );
         "#
     ).execute(&pool).await?;
     
@@ -320,7 +519,66 @@ pub async fn init_db(app: &AppHandle) -> Result<SqlitePool, sqlx::Error> {
     let _ = sqlx::query("ALTER TABLE emails ADD COLUMN ai_labels TEXT").execute(&pool).await;
     let _ = sqlx::query("ALTER TABLE emails ADD COLUMN ai_summary TEXT").execute(&pool).await;
     let _ = sqlx::query("ALTER TABLE emails ADD COLUMN to_email TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE emails ADD COLUMN flagged BOOLEAN DEFAULT 0").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE emails ADD COLUMN answered BOOLEAN DEFAULT 0").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE emails ADD COLUMN size INTEGER").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE emails ADD COLUMN body_structure TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE emails ADD COLUMN body_cached BOOLEAN DEFAULT 0").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE folder_sync_state ADD COLUMN highest_modseq INTEGER").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE folder_sync_state ADD COLUMN lowest_uid INTEGER").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE folder_sync_state ADD COLUMN backfill_done BOOLEAN NOT NULL DEFAULT 0").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE accounts ADD COLUMN imap_security TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE accounts ADD COLUMN smtp_security TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE accounts ADD COLUMN ca_cert TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE accounts ADD COLUMN tls_pinning BOOLEAN DEFAULT 0").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE accounts ADD COLUMN auth_method TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE accounts ADD COLUMN idle_folders TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE accounts ADD COLUMN poll_interval INTEGER").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE emails ADD COLUMN gm_msgid INTEGER").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE emails ADD COLUMN gm_thrid INTEGER").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE emails ADD COLUMN message_id TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE emails ADD COLUMN in_reply_to TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE emails ADD COLUMN refs TEXT").execute(&pool).await;
+    let _ = sqlx::query("ALTER TABLE emails ADD COLUMN thread_id TEXT").execute(&pool).await;
+    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_emails_thread ON emails(account_id, thread_id)").execute(&pool).await;
+    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_emails_gm_msgid ON emails(account_id, gm_msgid)").execute(&pool).await;
     
+    // Email ids used to be "{account}_{uid}", which collides across folders.
+    // Move them to "{account}_{folder}_{uid}" (see imap::local_email_id).
+    let _ = sqlx::query(
+        r#"UPDATE ai_triage_log SET email_id = (
+               SELECT e.account_id || '_' || e.folder || '_' || e.uid FROM emails e WHERE e.id = ai_triage_log.email_id
+           )
+           WHERE email_id IN (SELECT id FROM emails WHERE id = account_id || '_' || uid)"#
+    ).execute(&pool).await;
+    let _ = sqlx::query(
+        "UPDATE OR IGNORE emails SET id = account_id || '_' || folder || '_' || uid WHERE id = account_id || '_' || uid"
+    ).execute(&pool).await;
+
+    // Rows synced before header-first sync already carry their full body
+    let _ = sqlx::query("UPDATE emails SET body_cached = 1 WHERE COALESCE(body_cached, 0) = 0 AND body IS NOT NULL AND body != ''").execute(&pool).await;
+
+    // One-time: rows stored before email_addresses existed get their first From and
+    // To address; the full lists arrive when the headers are fetched again
+    let seeded = sqlx::query_scalar::<_, String>("SELECT value FROM ai_config WHERE key = 'email_addresses_seeded'")
+        .fetch_optional(&pool).await.unwrap_or(None);
+    if seeded.is_none() {
+        let from = sqlx::query(
+            r#"INSERT OR IGNORE INTO email_addresses (email_id, position, role, name, address)
+               SELECT id, 0, 'from', NULLIF(sender, sender_email), sender_email FROM emails
+               WHERE COALESCE(sender_email, '') != '' AND id NOT LIKE 'sent_%' AND id NOT IN (SELECT email_id FROM email_addresses)"#
+        ).execute(&pool).await;
+        let to = sqlx::query(
+            r#"INSERT OR IGNORE INTO email_addresses (email_id, position, role, name, address)
+               SELECT id, 1, 'to', NULL, to_email FROM emails
+               WHERE COALESCE(to_email, '') != '' AND id NOT IN (SELECT email_id FROM email_addresses WHERE position != 0)"#
+        ).execute(&pool).await;
+        if from.is_ok() && to.is_ok() {
+            let _ = sqlx::query("INSERT INTO ai_config (key, value) VALUES ('email_addresses_seeded', '1') ON CONFLICT(key) DO NOTHING")
+                .execute(&pool).await;
+        }
+    }
+
     // Clean up failed triage entries so they get retried
     let _ = sqlx::query("DELETE FROM ai_triage_log WHERE reason = 'AI no disponible' OR reason = 'No se pudo clasificar'").execute(&pool).await;
 
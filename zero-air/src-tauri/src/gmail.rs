@@ -0,0 +1,237 @@
+/// Gmail IMAP extensions (X-GM-EXT-1) — message ids, thread ids and labels.
+/// Gmail shows one message in "[Gmail]/All Mail" and in a folder per label, each copy
+/// with its own UID. X-GM-MSGID ties the copies together: labels are stored once per
+/// message in `gmail_labels`, and a body or AI classification stored for one copy is
+/// reused by the others instead of being downloaded or computed again.
+use tauri::{AppHandle, Manager};
+use crate::db::DbState;
+use crate::imap::ImapSession;
+use async_imap::imap_proto::{AttributeValue, Response};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+
+pub const CAPABILITY: &str = "X-GM-EXT-1";
+
+/// Gmail attributes of one message
+#[derive(Debug, Clone, Default)]
+pub struct GmailMeta {
+    pub msgid: u64,
+    pub thrid: u64,
+    pub labels: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
+pub struct GmailLabel {
+    pub label: String,
+    /// Locally known messages carrying the label
+    pub count: i64,
+}
+
+/// Whether the server speaks the Gmail extensions
+pub fn supported(capabilities: &[String]) -> bool {
+    crate::imap::has_capability(capabilities, CAPABILITY)
+}
+
+/// Labels come back as astrings in modified UTF-7; system labels ("\\Inbox") as-is
+fn label_name(raw: &str) -> String {
+    if raw.starts_with('\\') {
+        raw.to_string()
+    } else {
+        crate::imap_folders::decode_mailbox_name(raw)
+    }
+}
+
+/// A label as a STORE argument
+fn label_argument(label: &str) -> String {
+    if label.starts_with('\\') {
+        label.to_string()
+    } else {
+        crate::imap::quote_mailbox(&crate::imap_folders::encode_mailbox_name(label))
+    }
+}
+
+/// X-GM-MSGID, X-GM-THRID and X-GM-LABELS of messages in the selected folder, by UID.
+/// The typed FETCH API doesn't expose these attributes, so the command is run raw.
+pub async fn fetch_meta(session: &mut ImapSession, uid_set: &str) -> Result<HashMap<u32, GmailMeta>, String> {
+    let mut meta = HashMap::new();
+    let command = format!("UID FETCH {} (UID X-GM-MSGID X-GM-THRID X-GM-LABELS)", uid_set);
+    crate::imap::run_raw(session, &command, |response| {
+        let Response::Fetch(_, attributes) = response else { return };
+        let mut uid = None;
+        let mut entry = GmailMeta::default();
+        for attribute in attributes {
+            match attribute {
+                AttributeValue::Uid(u) => uid = Some(*u),
+                AttributeValue::GmailMsgId(id) => entry.msgid = *id,
+                AttributeValue::GmailThrId(id) => entry.thrid = *id,
+                AttributeValue::GmailLabels(labels) => entry.labels = labels.iter().map(|l| label_name(l)).collect(),
+                _ => {}
+            }
+        }
+        if let Some(uid) = uid.filter(|_| entry.msgid != 0) {
+            meta.insert(uid, entry);
+        }
+    }).await?;
+    Ok(meta)
+}
+
+/// Add `gm_msgid`, `gm_thrid` and `gm_labels` to rows from `parse_fetched_message`
+/// when the server is Gmail; other servers are left alone
+pub async fn annotate(session: &mut ImapSession, capabilities: &[String], emails: &mut [serde_json::Value]) -> Result<(), String> {
+    if emails.is_empty() || !supported(capabilities) {
+        return Ok(());
+    }
+    let uids: Vec<u32> = emails.iter().filter_map(|e| e["uid"].as_u64()).map(|u| u as u32).collect();
+    let meta = fetch_meta(session, &crate::imap::format_uid_set(&uids)).await?;
+    for email in emails.iter_mut() {
+        let Some(m) = email["uid"].as_u64().and_then(|uid| meta.get(&(uid as u32))) else { continue };
+        email["gm_msgid"] = serde_json::json!(m.msgid as i64);
+        email["gm_thrid"] = serde_json::json!(m.thrid as i64);
+        email["gm_labels"] = serde_json::json!(m.labels);
+    }
+    Ok(())
+}
+
+/// Replace the stored labels of one message
+pub async fn store_labels(pool: &SqlitePool, account_id: &str, msgid: i64, labels: &[String]) -> Result<(), String> {
+    sqlx::query("DELETE FROM gmail_labels WHERE account_id = $1 AND gm_msgid = $2")
+        .bind(account_id)
+        .bind(msgid)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    for label in labels {
+        sqlx::query("INSERT OR IGNORE INTO gmail_labels (account_id, gm_msgid, label) VALUES ($1, $2, $3)")
+            .bind(account_id)
+            .bind(msgid)
+            .bind(label)
+            .execute(pool)
+            .await
+            .map_err(|e| format!("DB error: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Fill in what another copy of the same Gmail message already has: the cached
+/// body and the AI classification. Rows without an X-GM-MSGID are untouched.
+pub async fn inherit_from_copies(pool: &SqlitePool, email_id: &str) -> Result<(), String> {
+    sqlx::query(
+        r#"UPDATE emails SET body = c.body, snippet = c.snippet, body_cached = 1
+           FROM (SELECT o.body, o.snippet FROM emails o JOIN emails e
+                   ON o.account_id = e.account_id AND o.gm_msgid = e.gm_msgid AND o.id != e.id
+                 WHERE e.id = $1 AND o.body_cached = 1 LIMIT 1) AS c
+           WHERE emails.id = $1 AND COALESCE(emails.body_cached, 0) = 0"#
+    )
+    .bind(email_id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+
+    sqlx::query(
+        r#"UPDATE emails SET ai_priority = c.ai_priority, ai_labels = c.ai_labels, ai_summary = c.ai_summary
+           FROM (SELECT o.ai_priority, o.ai_labels, o.ai_summary FROM emails o JOIN emails e
+                   ON o.account_id = e.account_id AND o.gm_msgid = e.gm_msgid AND o.id != e.id
+                 WHERE e.id = $1 AND o.ai_priority IS NOT NULL LIMIT 1) AS c
+           WHERE emails.id = $1 AND emails.ai_priority IS NULL"#
+    )
+    .bind(email_id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+    Ok(())
+}
+
+/// Tauri command: every label of the account with the number of messages carrying it
+#[tauri::command]
+pub async fn get_gmail_labels(app: AppHandle, account_id: String) -> Result<Vec<GmailLabel>, String> {
+    let state = app.state::<DbState>();
+    sqlx::query_as::<_, GmailLabel>(
+        "SELECT label, COUNT(*) AS count FROM gmail_labels WHERE account_id = $1 GROUP BY label ORDER BY label"
+    )
+    .bind(&account_id)
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))
+}
+
+/// Tauri command: labels of one email (empty for non-Gmail accounts)
+#[tauri::command]
+pub async fn get_email_labels(app: AppHandle, email_id: String) -> Result<Vec<String>, String> {
+    let state = app.state::<DbState>();
+    sqlx::query_scalar(
+        r#"SELECT l.label FROM gmail_labels l JOIN emails e
+             ON l.account_id = e.account_id AND l.gm_msgid = e.gm_msgid
+           WHERE e.id = $1 ORDER BY l.label"#
+    )
+    .bind(&email_id)
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))
+}
+
+/// STORE +/-X-GM-LABELS on the server, then mirror the change in `gmail_labels`
+async fn change_label(app: &AppHandle, account_id: &str, email_ids: &[String], label: &str, add: bool) -> Result<(), String> {
+    let label = label.trim();
+    if label.is_empty() {
+        return Err("Label name is empty".to_string());
+    }
+    let state = app.state::<DbState>();
+    let pool = state.pool.clone();
+    let account = crate::imap_folders::load_account(&pool, account_id).await?;
+    let groups = crate::imap_actions::locate_emails(&pool, account_id, email_ids).await?;
+    if groups.is_empty() {
+        return Ok(());
+    }
+
+    let mut session = crate::imap_session::acquire(&pool, &account).await?;
+    let result = async {
+        if !supported(&session.capabilities) {
+            return Err("The server does not support Gmail labels".to_string());
+        }
+        let sign = if add { '+' } else { '-' };
+        for (folder, emails) in &groups {
+            let mailbox = crate::imap_folders::resolve_mailbox(&pool, &account, folder).await?;
+            crate::imap::select_mailbox(&mut session, &mailbox).await?;
+            let uids: Vec<u32> = emails.iter().map(|e| e.uid).collect();
+            let command = format!("UID STORE {} {}X-GM-LABELS ({})", crate::imap::format_uid_set(&uids), sign, label_argument(label));
+            crate::imap::run_raw(&mut session, &command, |_| {}).await?;
+        }
+        Ok(())
+    }.await;
+    session.finish(result)?;
+
+    for id in groups.values().flatten().map(|e| &e.id) {
+        let msgid: Option<i64> = sqlx::query_scalar("SELECT gm_msgid FROM emails WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&pool)
+            .await
+            .map_err(|e| format!("DB error: {}", e))?
+            .flatten();
+        let Some(msgid) = msgid else { continue };
+        let query = if add {
+            "INSERT OR IGNORE INTO gmail_labels (account_id, gm_msgid, label) VALUES ($1, $2, $3)"
+        } else {
+            "DELETE FROM gmail_labels WHERE account_id = $1 AND gm_msgid = $2 AND label = $3"
+        };
+        sqlx::query(query)
+            .bind(account_id)
+            .bind(msgid)
+            .bind(label)
+            .execute(&pool)
+            .await
+            .map_err(|e| format!("DB error: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Tauri command: add a Gmail label to emails
+#[tauri::command]
+pub async fn add_gmail_label(app: AppHandle, account_id: String, email_ids: Vec<String>, label: String) -> Result<(), String> {
+    change_label(&app, &account_id, &email_ids, &label, true).await
+}
+
+/// Tauri command: remove a Gmail label from emails
+#[tauri::command]
+pub async fn remove_gmail_label(app: AppHandle, account_id: String, email_ids: Vec<String>, label: String) -> Result<(), String> {
+    change_label(&app, &account_id, &email_ids, &label, false).await
+}
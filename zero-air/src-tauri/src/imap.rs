@@ -1,192 +1,637 @@
 use tauri::{AppHandle, Manager};
 use crate::db::DbState;
 use serde_json;
-use mailparse::parse_mail;
+use mailparse::{parse_headers, parse_mail, MailHeaderMap};
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::time::Duration;
+use async_imap::imap_proto::{Address, AttributeValue, MailboxDatum, Response, ResponseCode, Status};
+use async_imap::types::{Capability, Fetch, Flag};
+use futures::TryStreamExt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use crate::imap_search::EsearchCompat;
+use crate::imap_session::ImapCredentials;
+use crate::oauth::AuthMethod;
+use crate::tls::Security;
+
+/// Number of newest messages pulled the first time a folder is synced.
+const INITIAL_SYNC_WINDOW: usize = 200;
+
+/// Give up on a server that doesn't accept the TCP connection within this time
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Attributes fetched for message lists
+pub const LIST_FETCH_QUERY: &str = "(UID FLAGS ENVELOPE RFC822.SIZE BODYSTRUCTURE BODY.PEEK[HEADER.FIELDS (MESSAGE-ID IN-REPLY-TO REFERENCES)])";
+
+/// Per-folder sync cursor persisted in `folder_sync_state`
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct FolderSyncState {
+    pub uid_validity: i64,
+    pub uid_next: Option<i64>,
+    pub highest_uid: i64,
+    pub highest_modseq: Option<i64>,
+    /// Oldest UID stored locally; the backfill walks down from here
+    pub lowest_uid: Option<i64>,
+    pub backfill_done: bool,
+}
 
-#[tauri::command]
-pub async fn sync_emails(app: AppHandle, account_id: String, folder: Option<String>) -> Result<Vec<serde_json::Value>, String> {
-    let state = app.state::<DbState>();
-    let pool = state.pool.clone();
+/// How flag changes on already-synced messages are pulled from the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlagSyncMode {
+    /// RFC 7162 QRESYNC: CHANGEDSINCE plus VANISHED
+    Qresync,
+    /// RFC 7162 CONDSTORE: CHANGEDSINCE against the stored MODSEQ
+    Condstore,
+    /// No extension: re-read FLAGS for every synced UID
+    Full,
+}
 
-    // 1. Load account credentials from DB
-    let account = sqlx::query_as::<_, crate::db::Account>(
-        "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port FROM accounts WHERE id = $1"
-    )
-    .bind(&account_id)
-    .fetch_optional(&pool)
-    .await
-    .map_err(|e| format!("DB error: {}", e))?
-    .ok_or_else(|| "Account not found".to_string())?;
+impl FlagSyncMode {
+    pub fn detect(capabilities: &[String]) -> Self {
+        if has_capability(capabilities, "QRESYNC") {
+            FlagSyncMode::Qresync
+        } else if has_capability(capabilities, "CONDSTORE") {
+            FlagSyncMode::Condstore
+        } else {
+            FlagSyncMode::Full
+        }
+    }
 
-    let imap_host = account.imap_host.clone().ok_or("IMAP host not configured")?;
-    let imap_port = account.imap_port.unwrap_or(993) as u16;
-    let email_addr = account.email.clone();
-    let password = account.password.clone().ok_or("Password not configured")?;
-    let acct_id = account_id.clone();
+    fn uses_modseq(self) -> bool {
+        self != FlagSyncMode::Full
+    }
+}
 
-    let target_folder = folder.unwrap_or_else(|| "INBOX".to_string());
-    let folder_for_thread = target_folder.clone();
-    let folder_for_db = target_folder.clone();
+/// Server-side flag state of one message
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlagUpdate {
+    pub uid: u32,
+    pub seen: bool,
+    pub flagged: bool,
+    pub answered: bool,
+}
 
-    log::info!("Connecting to IMAP {}:{} for {} (folder: {})", imap_host, imap_port, email_addr, target_folder);
-
-    // 2. Run sync IMAP in a blocking thread
-    let fetched_emails = tokio::task::spawn_blocking(move || -> Result<Vec<serde_json::Value>, String> {
-        let tls = native_tls::TlsConnector::builder()
-            .build()
-            .map_err(|e| format!("TLS error: {}", e))?;
-
-        let client = imap::connect(
-            (imap_host.as_str(), imap_port),
-            &imap_host,
-            &tls,
-        ).map_err(|e| format!("IMAP connect error: {}", e))?;
-
-        let mut session = client
-            .login(&email_addr, &password)
-            .map_err(|e| format!("IMAP login error: {}", e.0))?;
-
-        // Map UI folder names to IMAP folder names
-        let imap_folders: Vec<String> = match folder_for_thread.as_str() {
-            "INBOX" => vec!["INBOX".to_string()],
-            "Drafts" => vec!["Drafts", "INBOX.Drafts", "Draft", "INBOX.Draft", "[Gmail]/Drafts"]
-                .into_iter().map(|s| s.to_string()).collect(),
-            "Sent" => vec!["Sent", "INBOX.Sent", "Sent Messages", "[Gmail]/Sent Mail"]
-                .into_iter().map(|s| s.to_string()).collect(),
-            "Archive" => vec!["Archive", "INBOX.Archive", "[Gmail]/All Mail"]
-                .into_iter().map(|s| s.to_string()).collect(),
-            "Trash" => vec!["Trash", "INBOX.Trash", "[Gmail]/Trash"]
-                .into_iter().map(|s| s.to_string()).collect(),
-            other => vec![other.to_string()],
-        };
-
-        // Try each possible folder name
-        let mut mailbox = None;
-        for f in &imap_folders {
-            match session.select(f) {
-                Ok(mb) => { mailbox = Some(mb); break; }
-                Err(_) => continue,
+impl FlagUpdate {
+    fn from_flag_names<'a>(uid: u32, names: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut update = FlagUpdate { uid, ..Default::default() };
+        for name in names {
+            match name.to_ascii_lowercase().as_str() {
+                "\\seen" => update.seen = true,
+                "\\flagged" => update.flagged = true,
+                "\\answered" => update.answered = true,
+                _ => {}
             }
         }
-        let mailbox = mailbox.ok_or_else(|| format!("Could not find folder: {}", folder_for_thread))?;
-        let total = mailbox.exists;
+        update
+    }
 
-        if total == 0 {
-            session.logout().ok();
-            return Ok(vec![]);
+    fn from_flags<'a>(uid: u32, flags: impl IntoIterator<Item = Flag<'a>>) -> Self {
+        let mut update = FlagUpdate { uid, ..Default::default() };
+        for flag in flags {
+            match flag {
+                Flag::Seen => update.seen = true,
+                Flag::Flagged => update.flagged = true,
+                Flag::Answered => update.answered = true,
+                _ => {}
+            }
         }
+        update
+    }
 
-        // Fetch last 200 messages (was 50 — increased for full history)
-        let range = if total > 200 {
-            format!("{}:{}", total - 199, total)
-        } else {
-            format!("1:{}", total)
-        };
-
-        // Fetch full RFC822 message
-        let messages = session
-            .fetch(&range, "(UID ENVELOPE RFC822)")
-            .map_err(|e| format!("Fetch error: {}", e))?;
-
-        let mut emails: Vec<serde_json::Value> = Vec::new();
-
-        for msg in messages.iter() {
-            let uid = msg.uid.unwrap_or(0);
-            let envelope = msg.envelope();
-
-            let subject = envelope
-                .and_then(|env| env.subject.as_ref())
-                .map(|s| String::from_utf8_lossy(s).to_string())
-                .unwrap_or_default();
-
-            // Extract sender
-            let (sender, sender_email) = {
-                let mut name_str = "Unknown".to_string();
-                let mut email_str = String::new();
-                if let Some(env) = envelope {
-                    if let Some(ref addrs) = env.from {
-                        if let Some(addr) = addrs.get(0) {
-                            let name = addr.name.as_ref().map(|n| String::from_utf8_lossy(n).to_string());
-                            let mb = addr.mailbox.as_ref().map(|m| String::from_utf8_lossy(m).to_string()).unwrap_or_default();
-                            let host = addr.host.as_ref().map(|h| String::from_utf8_lossy(h).to_string()).unwrap_or_default();
-                            email_str = format!("{}@{}", mb, host);
-                            name_str = name.unwrap_or_else(|| email_str.clone());
-                        }
-                    }
-                }
-                (name_str, email_str)
+    /// Flags of an untagged `* n FETCH (UID u FLAGS (...) ...)` response
+    pub fn from_attributes(attributes: &[AttributeValue<'_>]) -> Option<Self> {
+        let uid = attributes.iter().find_map(|a| match a {
+            AttributeValue::Uid(uid) => Some(*uid),
+            _ => None,
+        })?;
+        FlagUpdate::with_uid(uid, attributes)
+    }
+
+    /// Flags of an untagged FETCH without a UID item (unsolicited, e.g. during IDLE),
+    /// for a UID the caller resolved from the sequence number
+    pub fn with_uid(uid: u32, attributes: &[AttributeValue<'_>]) -> Option<Self> {
+        let flags = attributes.iter().find_map(|a| match a {
+            AttributeValue::Flags(flags) => Some(flags),
+            _ => None,
+        })?;
+        Some(FlagUpdate::from_flag_names(uid, flags.iter().map(|f| f.as_ref())))
+    }
+}
+
+/// The parts of a SELECT response the sync cares about
+#[derive(Default)]
+struct SelectedMailbox {
+    exists: u32,
+    uid_validity: u32,
+    uid_next: Option<u32>,
+    highest_modseq: Option<u64>,
+}
+
+impl SelectedMailbox {
+    fn from_mailbox(mailbox: &async_imap::types::Mailbox) -> Self {
+        SelectedMailbox {
+            exists: mailbox.exists,
+            uid_validity: mailbox.uid_validity.unwrap_or(0),
+            uid_next: mailbox.uid_next,
+            highest_modseq: mailbox.highest_modseq,
+        }
+    }
+}
+
+/// How messages removed on the server are identified
+enum Expunges {
+    /// Every UID in the folder; local rows missing from it are gone
+    Present(HashSet<u32>),
+    /// QRESYNC VANISHED (EARLIER): exactly these UIDs are gone
+    Vanished(HashSet<u32>),
+}
+
+impl Expunges {
+    fn removes(&self, uid: u32) -> bool {
+        match self {
+            Expunges::Present(uids) => !uids.contains(&uid),
+            Expunges::Vanished(uids) => uids.contains(&uid),
+        }
+    }
+}
+
+/// What a single folder sync pass observed on the server
+struct FolderSyncOutcome {
+    uid_validity: u32,
+    uid_next: Option<u32>,
+    highest_modseq: Option<u64>,
+    validity_changed: bool,
+    expunges: Expunges,
+    emails: Vec<serde_json::Value>,
+    flag_updates: Vec<FlagUpdate>,
+}
+
+pub type ImapSession = async_imap::Session<EsearchCompat<ImapStream>>;
+
+/// The transport under an IMAP session: TLS (implicit or after STARTTLS) or plain TCP
+#[derive(Debug)]
+pub enum ImapStream {
+    Tls(async_native_tls::TlsStream<TcpStream>),
+    Plain(TcpStream),
+}
+
+impl AsyncRead for ImapStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ImapStream::Tls(s) => Pin::new(s).poll_read(cx, buf),
+            ImapStream::Plain(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ImapStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            ImapStream::Tls(s) => Pin::new(s).poll_write(cx, buf),
+            ImapStream::Plain(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ImapStream::Tls(s) => Pin::new(s).poll_flush(cx),
+            ImapStream::Plain(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ImapStream::Tls(s) => Pin::new(s).poll_shutdown(cx),
+            ImapStream::Plain(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+/// The server speaks first; the greeting has to be read before anything else
+async fn read_greeting<T>(client: &mut async_imap::Client<T>) -> Result<(), String>
+where
+    T: AsyncRead + AsyncWrite + Unpin + std::fmt::Debug + Send,
+{
+    client
+        .read_response()
+        .await
+        .ok_or_else(|| "IMAP connect error: no greeting from server".to_string())?
+        .map_err(|e| format!("IMAP connect error: {}", e))?;
+    Ok(())
+}
+
+/// TLS handshake on an open connection, checked against the pinned certificate when pinning is on
+async fn handshake(pool: &SqlitePool, credentials: &ImapCredentials, tcp: TcpStream) -> Result<async_native_tls::TlsStream<TcpStream>, String> {
+    let tls = credentials.tls.connector()?
+        .connect(credentials.host.as_str(), tcp)
+        .await
+        .map_err(|e| format!("TLS error: {}", e))?;
+    if credentials.tls.pinning {
+        let der = tls.peer_certificate().ok().flatten().and_then(|c| c.to_der().ok());
+        crate::tls::verify_pin(pool, &credentials.host, credentials.port, der).await?;
+    }
+    Ok(tls)
+}
+
+/// Connect with the account's security mode (implicit TLS, STARTTLS or plaintext) and log in
+pub async fn connect(pool: &SqlitePool, credentials: &ImapCredentials) -> Result<ImapSession, String> {
+    let (host, port) = (credentials.host.as_str(), credentials.port);
+    let tcp = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect((host, port)))
+        .await
+        .map_err(|_| format!("IMAP connect error: {}:{} timed out", host, port))?
+        .map_err(|e| format!("IMAP connect error: {}", e))?;
+
+    let client = match credentials.security {
+        Security::Tls => {
+            let mut client = async_imap::Client::new(EsearchCompat::new(ImapStream::Tls(handshake(pool, credentials, tcp).await?)));
+            read_greeting(&mut client).await?;
+            client
+        }
+        Security::StartTls => {
+            let mut plain = async_imap::Client::new(tcp);
+            read_greeting(&mut plain).await?;
+            plain
+                .run_command_and_check_ok("STARTTLS", None)
+                .await
+                .map_err(|e| format!("STARTTLS error: {}", e))?;
+            // No second greeting after the upgrade
+            async_imap::Client::new(EsearchCompat::new(ImapStream::Tls(handshake(pool, credentials, plain.into_inner()).await?)))
+        }
+        Security::None => {
+            log::warn!("[IMAP] {}:{} is configured without encryption", host, port);
+            let mut client = async_imap::Client::new(EsearchCompat::new(ImapStream::Plain(tcp)));
+            read_greeting(&mut client).await?;
+            client
+        }
+    };
+
+    match &credentials.auth {
+        AuthMethod::Password(password) => client
+            .login(&credentials.email, password)
+            .await
+            .map_err(|e| format!("IMAP login error: {}", e.0)),
+        AuthMethod::OAuth2 { account_id } => {
+            let token = crate::oauth::access_token(pool, account_id).await?;
+            let payload = if token.mechanism == "OAUTHBEARER" {
+                crate::oauth::oauthbearer_payload(&credentials.email, host, port, &token.token)
+            } else {
+                crate::oauth::xoauth2_payload(&credentials.email, &token.token)
             };
+            client
+                .authenticate(&token.mechanism, SaslInitialResponse(Some(payload)))
+                .await
+                .map_err(|e| format!("IMAP login error: {}", e.0))
+        }
+    }
+}
+
+/// Sends a prepared SASL response to the first challenge. Later challenges only
+/// carry error details (XOAUTH2 and OAUTHBEARER), answered with an empty line.
+struct SaslInitialResponse(Option<String>);
+
+impl async_imap::Authenticator for SaslInitialResponse {
+    type Response = String;
+
+    fn process(&mut self, _challenge: &[u8]) -> String {
+        self.0.take().unwrap_or_default()
+    }
+}
+
+/// CAPABILITY of a logged-in session as atoms ("IDLE", "AUTH=PLAIN", ...),
+/// remembered per account in `imap_capabilities`
+pub async fn refresh_capabilities(pool: &SqlitePool, session: &mut ImapSession, account_id: &str) -> Result<Vec<String>, String> {
+    let capabilities = session.capabilities().await.map_err(|e| format!("IMAP capability error: {}", e))?;
+    let mut names: Vec<String> = capabilities.iter().map(|c| match c {
+        Capability::Imap4rev1 => "IMAP4rev1".to_string(),
+        Capability::Auth(mechanism) => format!("AUTH={}", mechanism),
+        Capability::Atom(atom) => atom.clone(),
+    }).collect();
+    names.sort();
+
+    sqlx::query("INSERT OR REPLACE INTO imap_capabilities (account_id, capabilities, checked_at) VALUES ($1, $2, $3)")
+        .bind(account_id)
+        .bind(names.join(" "))
+        .bind(chrono::Utc::now().to_rfc3339())
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    Ok(names)
+}
+
+/// Whether `name` is among the capabilities from `refresh_capabilities`
+pub fn has_capability(capabilities: &[String], name: &str) -> bool {
+    capabilities.iter().any(|c| c.eq_ignore_ascii_case(name))
+}
+
+/// SELECT a mailbox (see `imap_folders::resolve_mailbox`) on a borrowed session
+pub async fn select_mailbox(session: &mut ImapSession, mailbox: &str) -> Result<async_imap::types::Mailbox, String> {
+    session.select(mailbox).await.map_err(|e| format!("Could not select folder {}: {}", mailbox, e))
+}
+
+/// Run a command the typed API doesn't cover. Every response up to and including
+/// the tagged completion is passed to `on_response`, so response codes such as
+/// COPYUID on the tagged OK are visible. Fails when the command ends in NO or BAD.
+pub async fn run_raw<F>(session: &mut ImapSession, command: &str, on_response: F) -> Result<(), String>
+where
+    F: FnMut(&Response<'_>),
+{
+    let verb = command.split_whitespace().take(2).collect::<Vec<_>>().join(" ");
+    run_with_literal(session, &verb, command, None, on_response).await
+}
+
+/// APPEND `message` to `mailbox`. The message goes out as a literal once the server
+/// sends its `+` go-ahead; a refusal before or after it is reported as in `run_raw`.
+pub async fn append_raw<F>(session: &mut ImapSession, mailbox: &str, message: &str, on_response: F) -> Result<(), String>
+where
+    F: FnMut(&Response<'_>),
+{
+    let command = format!("APPEND {} {{{}}}", quote_mailbox(mailbox), message.len());
+    run_with_literal(session, "APPEND", &command, Some(message), on_response).await
+}
 
-            // Extract recipient (To) — critical for drafts
-            let to_email = {
-                let mut to_str = String::new();
-                if let Some(env) = envelope {
-                    if let Some(ref addrs) = env.to {
-                        if let Some(addr) = addrs.get(0) {
-                            let mb = addr.mailbox.as_ref().map(|m| String::from_utf8_lossy(m).to_string()).unwrap_or_default();
-                            let host = addr.host.as_ref().map(|h| String::from_utf8_lossy(h).to_string()).unwrap_or_default();
-                            to_str = format!("{}@{}", mb, host);
-                        }
-                    }
+/// Whether a response is a completion with `[TRYCREATE]`: the target mailbox is missing
+pub fn is_try_create(response: &Response<'_>) -> bool {
+    matches!(response, Response::Done { code: Some(ResponseCode::TryCreate), .. })
+}
+
+async fn run_with_literal<F>(session: &mut ImapSession, verb: &str, command: &str, mut literal: Option<&str>, mut on_response: F) -> Result<(), String>
+where
+    F: FnMut(&Response<'_>),
+{
+    let id = session.run_command(command).await.map_err(|e| format!("{} error: {}", verb, e))?;
+    loop {
+        let response = session
+            .read_response()
+            .await
+            .ok_or_else(|| format!("{} error: connection lost", verb))?
+            .map_err(|e| format!("{} error: {}", verb, e))?;
+        let parsed = response.parsed();
+        if let (Response::Continue { .. }, Some(data)) = (parsed, literal) {
+            // The literal ends the command line
+            session.run_command_untagged(data).await.map_err(|e| format!("{} error: {}", verb, e))?;
+            literal = None;
+            continue;
+        }
+        on_response(parsed);
+        if let Response::Done { tag, status, information, .. } = parsed {
+            if *tag == id {
+                let reason = information.as_deref().unwrap_or("no reason given");
+                return match status {
+                    Status::Ok => Ok(()),
+                    // Worded like async-imap's errors, see `is_rejection`
+                    Status::No => Err(format!("{} error: no response: {}", verb, reason)),
+                    Status::Bad => Err(format!("{} error: bad response: {}", verb, reason)),
+                    _ => Err(format!("{} failed: {}", verb, reason)),
+                };
+            }
+        }
+    }
+}
+
+/// Whether an error from the helpers here is the server refusing the command (a tagged
+/// NO or BAD), as opposed to the connection failing
+pub fn is_rejection(error: &str) -> bool {
+    error.contains("no response: ") || error.contains("bad response: ")
+}
+
+/// UID FETCH and collect the whole response stream
+pub async fn uid_fetch_all(session: &mut ImapSession, uid_set: &str, query: &str) -> Result<Vec<Fetch>, String> {
+    session
+        .uid_fetch(uid_set, query)
+        .await
+        .map_err(|e| format!("Fetch error: {}", e))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("Fetch error: {}", e))
+}
+
+/// Local primary key for a server message. UIDs are only unique per folder,
+/// so the folder is part of the id.
+pub fn local_email_id(account_id: &str, folder: &str, uid: u32) -> String {
+    format!("{}_{}_{}", account_id, folder, uid)
+}
+
+/// Compress UIDs into an IMAP sequence set, e.g. [1,2,3,7,9,10] → "1:3,7,9:10"
+pub fn format_uid_set(uids: &[u32]) -> String {
+    let mut sorted = uids.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+
+    let mut parts: Vec<String> = Vec::new();
+    let mut iter = sorted.into_iter();
+    let Some(first) = iter.next() else { return String::new() };
+    let (mut start, mut end) = (first, first);
+    for uid in iter {
+        if uid == end + 1 {
+            end = uid;
+            continue;
+        }
+        parts.push(if start == end { start.to_string() } else { format!("{}:{}", start, end) });
+        start = uid;
+        end = uid;
+    }
+    parts.push(if start == end { start.to_string() } else { format!("{}:{}", start, end) });
+    parts.join(",")
+}
+
+/// Expand an IMAP sequence set, e.g. "304,319:320" → [304, 319, 320].
+/// `*` stands for the mailbox's largest UID, which isn't known here, so parts with it are skipped.
+pub fn parse_uid_set(set: &str) -> Vec<u32> {
+    let mut uids = Vec::new();
+    for part in set.split(',') {
+        match part.split_once(':') {
+            Some((a, b)) => {
+                if let (Ok(a), Ok(b)) = (a.parse::<u32>(), b.parse::<u32>()) {
+                    uids.extend(a.min(b)..=a.max(b));
                 }
-                to_str
-            };
+            }
+            None => uids.extend(part.parse::<u32>().ok()),
+        }
+    }
+    uids
+}
 
-            let date_raw = envelope
-                .and_then(|env| env.date.as_ref())
-                .map(|d| String::from_utf8_lossy(d).to_string())
-                .unwrap_or_default();
+/// Pick the UIDs a sync pass has to download: everything above the cursor,
+/// or the newest INITIAL_SYNC_WINDOW messages when the folder was never synced.
+fn uids_to_fetch(server_uids: &HashSet<u32>, last_synced_uid: u32) -> Vec<u32> {
+    let mut uids: Vec<u32> = server_uids.iter().copied().filter(|u| *u > last_synced_uid).collect();
+    uids.sort_unstable();
+    if last_synced_uid == 0 && uids.len() > INITIAL_SYNC_WINDOW {
+        uids.drain(..uids.len() - INITIAL_SYNC_WINDOW);
+    }
+    uids
+}
 
-            // Parse date to ISO format for proper sorting
-            let date_iso = parse_rfc2822_to_iso(&date_raw);
+/// The cursor after a sync pass: the highest UID stored so far, counted from zero
+/// again after a UIDVALIDITY change, and the lowest UID this pass fetched, below
+/// which the backfill continues (`save_sync_state` keeps an earlier one)
+fn advanced_state(previous: Option<&FolderSyncState>, outcome: &FolderSyncOutcome) -> FolderSyncState {
+    let previous_highest = match previous {
+        Some(s) if !outcome.validity_changed => s.highest_uid,
+        _ => 0,
+    };
+    let fetched_uids = outcome.emails.iter().filter_map(|e| e["uid"].as_i64());
+    FolderSyncState {
+        uid_validity: outcome.uid_validity as i64,
+        uid_next: outcome.uid_next.map(|u| u as i64),
+        highest_uid: fetched_uids.clone().fold(previous_highest, i64::max),
+        highest_modseq: outcome.highest_modseq.map(|m| m as i64),
+        lowest_uid: fetched_uids.min(),
+        backfill_done: false,
+    }
+}
 
-            // Parse the full RFC822 body with mailparse
-            let (body_html, body_plain) = msg.body()
-                .map(|raw| extract_bodies(raw))
-                .unwrap_or_default();
+/// Quote a mailbox name as an IMAP quoted string
+pub fn quote_mailbox(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
 
-            // Use HTML for body (to render as sent), plain text for snippet
-            let body = if !body_html.is_empty() { &body_html } else { &body_plain };
-            let snippet_source = if !body_plain.is_empty() { &body_plain } else { &strip_html_tags(&body_html) };
-            let snippet: String = snippet_source.chars().take(150).collect();
+/// Fold one untagged response of a `SELECT ... (QRESYNC ...)` into the sync state
+fn collect_qresync_select(
+    response: &Response<'_>,
+    mailbox: &mut SelectedMailbox,
+    flag_updates: &mut Vec<FlagUpdate>,
+    vanished: &mut HashSet<u32>,
+) {
+    match response {
+        Response::MailboxData(MailboxDatum::Exists(count)) => mailbox.exists = *count,
+        Response::Data { code: Some(code), .. } | Response::Done { code: Some(code), .. } => match code {
+            ResponseCode::UidValidity(v) => mailbox.uid_validity = *v,
+            ResponseCode::UidNext(n) => mailbox.uid_next = Some(*n),
+            ResponseCode::HighestModSeq(m) => mailbox.highest_modseq = Some(*m),
+            _ => {}
+        },
+        Response::Vanished { uids, .. } => {
+            vanished.extend(uids.iter().flat_map(|range| range.clone()));
+        }
+        Response::Fetch(_, attributes) => flag_updates.extend(FlagUpdate::from_attributes(attributes)),
+        _ => {}
+    }
+}
 
-            let email_id = format!("{}_{}", acct_id, uid);
-
-            emails.push(serde_json::json!({
-                "id": email_id,
-                "uid": uid,
-                "subject": subject,
-                "sender": sender,
-                "sender_email": sender_email,
-                "to_email": to_email,
-                "date": date_iso,
-                "snippet": snippet,
-                "body": body,
-                "folder": folder_for_thread,
-                "account_id": acct_id,
-                "is_html": !body_html.is_empty()
-            }));
-        }
-
-        session.logout().ok();
-
-        // Sort by UID descending (highest UID = newest email)
-        emails.sort_by(|a, b| {
-            let uid_b = b["uid"].as_u64().unwrap_or(0);
-            let uid_a = a["uid"].as_u64().unwrap_or(0);
-            uid_b.cmp(&uid_a)
-        });
-
-        Ok(emails)
-    })
+/// SELECT with RFC 7162 QRESYNC. The server answers with the flags changed since
+/// the stored MODSEQ and the UIDs expunged since then, so neither a FLAGS fetch nor
+/// a full UID SEARCH is needed. Returns None for the vanished set if the server
+/// rejected QRESYNC and a plain CONDSTORE select was made instead.
+async fn select_qresync(
+    session: &mut ImapSession,
+    mailbox_name: &str,
+    previous: &FolderSyncState,
+) -> Result<(SelectedMailbox, Vec<FlagUpdate>, Option<HashSet<u32>>), String> {
+    let mut mailbox = SelectedMailbox::default();
+    let mut flag_updates = Vec::new();
+    let mut vanished = HashSet::new();
+
+    let enabled = run_raw(session, "ENABLE QRESYNC", |_| {}).await;
+    let command = format!(
+        "SELECT {} (QRESYNC ({} {} 1:{}))",
+        quote_mailbox(mailbox_name),
+        previous.uid_validity,
+        previous.highest_modseq.unwrap_or(0),
+        previous.highest_uid.max(1),
+    );
+    let selected = match enabled {
+        Ok(()) => run_raw(session, &command, |r| collect_qresync_select(r, &mut mailbox, &mut flag_updates, &mut vanished)).await,
+        Err(e) => Err(e),
+    };
+
+    match selected {
+        Ok(()) => Ok((mailbox, flag_updates, Some(vanished))),
+        Err(e) => {
+            log::warn!("[SYNC] QRESYNC select rejected ({}), falling back to CONDSTORE", e);
+            let mb = session.select_condstore(mailbox_name).await
+                .map_err(|e| format!("Could not select folder {}: {}", mailbox_name, e))?;
+            Ok((SelectedMailbox::from_mailbox(&mb), vec![], None))
+        }
+    }
+}
+
+/// Pull the flags of already-synced messages (UIDs 1..=last_synced_uid).
+/// With CONDSTORE only messages whose MODSEQ moved past the stored cursor come
+/// back; otherwise (or when the server rejects CHANGEDSINCE) FLAGS are re-read for all.
+async fn fetch_flag_changes(
+    session: &mut ImapSession,
+    mode: FlagSyncMode,
+    last_synced_uid: u32,
+    since_modseq: Option<u64>,
+) -> Result<Vec<FlagUpdate>, String> {
+    if last_synced_uid == 0 {
+        return Ok(vec![]);
+    }
+    let range = format!("1:{}", last_synced_uid);
+
+    if let (true, Some(modseq)) = (mode.uses_modseq(), since_modseq) {
+        match uid_fetch_all(session, &range, &format!("(UID FLAGS) (CHANGEDSINCE {})", modseq)).await {
+            Ok(fetches) => return Ok(flag_updates_of(&fetches)),
+            Err(e) => log::warn!("[SYNC] CHANGEDSINCE rejected ({}), falling back to full FLAGS fetch", e),
+        }
+    }
+
+    let fetches = uid_fetch_all(session, &range, "(UID FLAGS)").await?;
+    Ok(flag_updates_of(&fetches))
+}
+
+fn flag_updates_of(fetches: &[Fetch]) -> Vec<FlagUpdate> {
+    fetches
+        .iter()
+        .filter_map(|f| f.uid.map(|uid| FlagUpdate::from_flags(uid, f.flags())))
+        .collect()
+}
+
+pub async fn load_sync_state(pool: &SqlitePool, account_id: &str, folder: &str) -> Result<Option<FolderSyncState>, String> {
+    sqlx::query_as::<_, FolderSyncState>(
+        "SELECT uid_validity, uid_next, highest_uid, highest_modseq, lowest_uid, backfill_done FROM folder_sync_state WHERE account_id = $1 AND folder = $2"
+    )
+    .bind(account_id)
+    .bind(folder)
+    .fetch_optional(pool)
     .await
-    .map_err(|e| format!("Thread error: {}", e))??;
+    .map_err(|e| format!("DB error: {}", e))
+}
 
-    // 3. Save all fetched emails to local SQLite
-    for email in &fetched_emails {
-        let _ = sqlx::query(
-            r#"INSERT INTO emails (id, uid, account_id, folder, subject, sender, sender_email, to_email, date, snippet, body, read)
-               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, 0)
+/// Persist the sync cursors. The backfill cursor is owned by the backfill job and
+/// only overwritten here when UIDVALIDITY changed (or was never set).
+async fn save_sync_state(pool: &SqlitePool, account_id: &str, folder: &str, state: &FolderSyncState) -> Result<(), String> {
+    sqlx::query(
+        r#"INSERT INTO folder_sync_state (account_id, folder, uid_validity, uid_next, highest_uid, highest_modseq, lowest_uid, backfill_done, last_sync_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+           ON CONFLICT(account_id, folder) DO UPDATE SET
+               lowest_uid = CASE WHEN folder_sync_state.uid_validity = excluded.uid_validity
+                   THEN COALESCE(folder_sync_state.lowest_uid, excluded.lowest_uid) ELSE excluded.lowest_uid END,
+               backfill_done = CASE WHEN folder_sync_state.uid_validity = excluded.uid_validity
+                   THEN folder_sync_state.backfill_done ELSE excluded.backfill_done END,
+               uid_validity = excluded.uid_validity,
+               uid_next = excluded.uid_next,
+               highest_uid = excluded.highest_uid,
+               highest_modseq = excluded.highest_modseq,
+               last_sync_at = excluded.last_sync_at"#
+    )
+    .bind(account_id)
+    .bind(folder)
+    .bind(state.uid_validity)
+    .bind(state.uid_next)
+    .bind(state.highest_uid)
+    .bind(state.highest_modseq)
+    .bind(state.lowest_uid)
+    .bind(state.backfill_done)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .execute(pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+    Ok(())
+}
+
+/// Upsert rows produced by `parse_fetched_message`.
+/// A header-only row never wipes a body that was already cached.
+/// Callers rethread the account (`threads::thread_account`) once they are done storing.
+pub async fn store_emails(pool: &SqlitePool, account_id: &str, folder: &str, emails: &[serde_json::Value]) -> Result<(), String> {
+    for email in emails {
+        sqlx::query(
+            r#"INSERT INTO emails (id, uid, account_id, folder, subject, sender, sender_email, to_email, date, snippet, body, read, flagged, answered, size, body_structure, body_cached, gm_msgid, gm_thrid, message_id, in_reply_to, refs)
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22)
                ON CONFLICT(id) DO UPDATE SET
                    uid = excluded.uid,
                    folder = excluded.folder,
@@ -195,30 +640,437 @@ pub async fn sync_emails(app: AppHandle, account_id: String, folder: Option<Stri
                    sender_email = excluded.sender_email,
                    to_email = excluded.to_email,
                    date = excluded.date,
-                   snippet = excluded.snippet,
-                   body = excluded.body"#
+                   snippet = COALESCE(excluded.snippet, emails.snippet),
+                   body = COALESCE(excluded.body, emails.body),
+                   body_cached = MAX(COALESCE(emails.body_cached, 0), excluded.body_cached),
+                   size = excluded.size,
+                   body_structure = excluded.body_structure,
+                   read = excluded.read,
+                   flagged = excluded.flagged,
+                   answered = excluded.answered,
+                   gm_msgid = COALESCE(excluded.gm_msgid, emails.gm_msgid),
+                   gm_thrid = COALESCE(excluded.gm_thrid, emails.gm_thrid),
+                   message_id = COALESCE(excluded.message_id, emails.message_id),
+                   in_reply_to = COALESCE(excluded.in_reply_to, emails.in_reply_to),
+                   refs = COALESCE(excluded.refs, emails.refs)"#
         )
         .bind(email["id"].as_str().unwrap_or(""))
         .bind(email["uid"].as_i64().unwrap_or(0))
-        .bind(&account_id)
-        .bind(&folder_for_db)
+        .bind(account_id)
+        .bind(folder)
         .bind(email["subject"].as_str().unwrap_or(""))
         .bind(email["sender"].as_str().unwrap_or(""))
         .bind(email["sender_email"].as_str().unwrap_or(""))
         .bind(email["to_email"].as_str().unwrap_or(""))
         .bind(email["date"].as_str().unwrap_or(""))
-        .bind(email["snippet"].as_str().unwrap_or(""))
-        .bind(email["body"].as_str().unwrap_or(""))
-        .execute(&pool)
-        .await;
+        .bind(email["snippet"].as_str())
+        .bind(email["body"].as_str())
+        .bind(email["read"].as_bool().unwrap_or(false))
+        .bind(email["flagged"].as_bool().unwrap_or(false))
+        .bind(email["answered"].as_bool().unwrap_or(false))
+        .bind(email["size"].as_i64())
+        .bind(email["body_structure"].as_str())
+        .bind(email["body_cached"].as_bool().unwrap_or(false))
+        .bind(email["gm_msgid"].as_i64())
+        .bind(email["gm_thrid"].as_i64())
+        .bind(email["message_id"].as_str())
+        .bind(email["in_reply_to"].as_str())
+        .bind(email["refs"].as_str())
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+
+        let parts: Vec<crate::imap_body::MimePart> = email["body_structure"].as_str()
+            .and_then(|s| serde_json::from_str(s).ok())
+            .unwrap_or_default();
+        if !parts.is_empty() {
+            crate::attachments::index_attachments(pool, email["id"].as_str().unwrap_or(""), &parts).await?;
+        }
+        if let Ok(addresses) = serde_json::from_value::<Vec<crate::addresses::EmailAddress>>(email["addresses"].clone()) {
+            crate::addresses::store_addresses(pool, email["id"].as_str().unwrap_or(""), &addresses).await?;
+        }
+
+        // Gmail: labels belong to the message, and other copies may already have its body
+        if let Some(msgid) = email["gm_msgid"].as_i64() {
+            let labels: Vec<String> = serde_json::from_value(email["gm_labels"].clone()).unwrap_or_default();
+            crate::gmail::store_labels(pool, account_id, msgid, &labels).await?;
+            crate::gmail::inherit_from_copies(pool, email["id"].as_str().unwrap_or("")).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Write server-side flag state to the local rows
+pub async fn apply_flag_updates(pool: &SqlitePool, account_id: &str, folder: &str, updates: &[FlagUpdate]) -> Result<(), String> {
+    for update in updates {
+        sqlx::query("UPDATE emails SET read = $1, flagged = $2, answered = $3 WHERE id = $4")
+            .bind(update.seen)
+            .bind(update.flagged)
+            .bind(update.answered)
+            .bind(local_email_id(account_id, folder, update.uid))
+            .execute(pool)
+            .await
+            .map_err(|e| format!("DB error: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Delete local rows (and their triage entries) for messages gone from the server
+pub async fn delete_local_emails(pool: &SqlitePool, email_ids: &[String]) -> Result<(), String> {
+    for id in email_ids {
+        sqlx::query("DELETE FROM emails WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await
+            .map_err(|e| format!("DB delete error: {}", e))?;
+        let _ = sqlx::query("DELETE FROM ai_triage_log WHERE email_id = $1")
+            .bind(id)
+            .execute(pool)
+            .await;
+        let _ = sqlx::query("DELETE FROM email_parts WHERE email_id = $1")
+            .bind(id)
+            .execute(pool)
+            .await;
+        let _ = sqlx::query("DELETE FROM attachments WHERE email_id = $1")
+            .bind(id)
+            .execute(pool)
+            .await;
+        let _ = sqlx::query("DELETE FROM email_addresses WHERE email_id = $1")
+            .bind(id)
+            .execute(pool)
+            .await;
+    }
+    Ok(())
+}
+
+/// Point a local row at a new folder/UID, carrying along the rows that reference its id
+pub async fn rekey_local_email(pool: &SqlitePool, old_id: &str, new_id: &str, folder: &str, uid: u32) -> Result<(), String> {
+    if new_id != old_id {
+        let _ = sqlx::query("UPDATE ai_triage_log SET email_id = $1 WHERE email_id = $2")
+            .bind(new_id)
+            .bind(old_id)
+            .execute(pool)
+            .await;
+        let _ = sqlx::query("UPDATE OR REPLACE email_parts SET email_id = $1 WHERE email_id = $2")
+            .bind(new_id)
+            .bind(old_id)
+            .execute(pool)
+            .await;
+        let _ = sqlx::query("UPDATE OR REPLACE attachments SET email_id = $1 WHERE email_id = $2")
+            .bind(new_id)
+            .bind(old_id)
+            .execute(pool)
+            .await;
+        let _ = sqlx::query("UPDATE OR REPLACE email_addresses SET email_id = $1 WHERE email_id = $2")
+            .bind(new_id)
+            .bind(old_id)
+            .execute(pool)
+            .await;
+    }
+    sqlx::query("UPDATE OR REPLACE emails SET id = $1, folder = $2, uid = $3 WHERE id = $4")
+        .bind(new_id)
+        .bind(folder)
+        .bind(uid as i64)
+        .bind(old_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    Ok(())
+}
+
+/// One folder sync pass on a borrowed session: SELECT, flag changes, expunges and
+/// the headers of messages above the stored cursor.
+async fn sync_folder(
+    session: &mut ImapSession,
+    capabilities: &[String],
+    mailbox_name: &str,
+    account_id: &str,
+    folder: &str,
+    previous: Option<&FolderSyncState>,
+) -> Result<FolderSyncOutcome, String> {
+    let mode = FlagSyncMode::detect(capabilities);
+
+    // QRESYNC needs a previous cursor to resync against; the first pass is a CONDSTORE select
+    let resumable = previous.filter(|s| s.uid_validity > 0 && s.highest_modseq.is_some() && s.highest_uid > 0);
+    let (mailbox, qresync_flags, vanished) = match (mode, resumable) {
+        (FlagSyncMode::Qresync, Some(previous)) => select_qresync(session, mailbox_name, previous).await?,
+        (FlagSyncMode::Full, _) => (SelectedMailbox::from_mailbox(&select_mailbox(session, mailbox_name).await?), vec![], None),
+        _ => {
+            let mb = session.select_condstore(mailbox_name).await
+                .map_err(|e| format!("Could not select folder {}: {}", mailbox_name, e))?;
+            (SelectedMailbox::from_mailbox(&mb), vec![], None)
+        }
+    };
+    let uid_validity = mailbox.uid_validity;
+
+    // A new UIDVALIDITY means every UID we stored is meaningless
+    let validity_changed = matches!(previous, Some(s) if s.uid_validity != uid_validity as i64);
+    if validity_changed {
+        log::warn!("[SYNC] UIDVALIDITY changed for {} — resetting local folder", folder);
+    }
+    let (last_synced_uid, since_modseq) = match previous {
+        Some(s) if !validity_changed => (s.highest_uid as u32, s.highest_modseq.map(|m| m as u64)),
+        _ => (0, None),
+    };
+
+    // Flag changes made by other clients, and the messages expunged since the last pass.
+    // A QRESYNC select already reported both; otherwise flags are fetched and expunges
+    // are found by comparing against the full UID list.
+    let (flag_updates, expunges, candidate_uids) = match vanished {
+        Some(vanished) if !validity_changed => {
+            let candidates = if mailbox.exists == 0 {
+                HashSet::new()
+            } else {
+                session.uid_search(format!("UID {}:*", last_synced_uid + 1)).await
+                    .map_err(|e| format!("Search error: {}", e))?
+            };
+            (qresync_flags, Expunges::Vanished(vanished), candidates)
+        }
+        _ => {
+            if mailbox.exists == 0 {
+                (vec![], Expunges::Present(HashSet::new()), HashSet::new())
+            } else {
+                let flag_updates = fetch_flag_changes(session, mode, last_synced_uid, since_modseq).await?;
+                let server_uids = session.uid_search("ALL").await
+                    .map_err(|e| format!("Search error: {}", e))?;
+                (flag_updates, Expunges::Present(server_uids.clone()), server_uids)
+            }
+        }
+    };
+
+    let mut outcome = FolderSyncOutcome {
+        uid_validity,
+        uid_next: mailbox.uid_next,
+        highest_modseq: mailbox.highest_modseq,
+        validity_changed,
+        expunges,
+        emails: vec![],
+        flag_updates,
+    };
+
+    let new_uids = uids_to_fetch(&candidate_uids, last_synced_uid);
+    if new_uids.is_empty() {
+        return Ok(outcome);
+    }
+
+    // Headers and structure only — bodies are fetched when a message is opened
+    let messages = uid_fetch_all(session, &format_uid_set(&new_uids), LIST_FETCH_QUERY).await?;
+    let mut emails: Vec<serde_json::Value> = messages.iter()
+        .map(|msg| parse_fetched_message(msg, account_id, folder))
+        .collect();
+    crate::gmail::annotate(session, capabilities, &mut emails).await?;
+
+    // Sort by UID descending (highest UID = newest email)
+    emails.sort_by(|a, b| {
+        let uid_b = b["uid"].as_u64().unwrap_or(0);
+        let uid_a = a["uid"].as_u64().unwrap_or(0);
+        uid_b.cmp(&uid_a)
+    });
+
+    outcome.emails = emails;
+    Ok(outcome)
+}
+
+/// Incremental UID-based sync of one folder.
+/// Only UIDs above the stored cursor are downloaded; local rows whose UID no longer
+/// exists on the server are removed, and a UIDVALIDITY change resets the folder.
+/// \Seen, \Flagged and \Answered of already-synced messages are refreshed through
+/// QRESYNC or CONDSTORE when available, or a full FLAGS fetch otherwise.
+/// Returns the newly fetched emails.
+#[tauri::command]
+pub async fn sync_emails(app: AppHandle, account_id: String, folder: Option<String>) -> Result<Vec<serde_json::Value>, String> {
+    // Shutdown waits for a running sync so the cursor and rows stay consistent
+    let _job = crate::tasks::begin_job()?;
+    let state = app.state::<DbState>();
+    let pool = state.pool.clone();
+
+    // 1. Load account credentials from DB
+    let account = sqlx::query_as::<_, crate::db::Account>(
+        "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port, imap_security, smtp_security, ca_cert, tls_pinning, auth_method FROM accounts WHERE id = $1"
+    )
+    .bind(&account_id)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?
+    .ok_or_else(|| "Account not found".to_string())?;
+
+    let target_folder = folder.unwrap_or_else(|| "INBOX".to_string());
+    let folder_for_db = target_folder.clone();
+
+    // Queued local changes go to the server first, so the sync doesn't undo them
+    crate::pending_ops::replay_account(&app, &account_id).await;
+
+    // 2. Load the folder's sync cursor
+    let previous_state = load_sync_state(&pool, &account_id, &target_folder).await?;
+    let mailbox_name = crate::imap_folders::resolve_mailbox(&pool, &account, &target_folder).await?;
+
+    log::info!("Syncing {} (IMAP {}) for {}", target_folder, mailbox_name, account.email);
+
+    // 3. Run the sync on a pooled session
+    let mut session = crate::imap_session::acquire(&pool, &account).await?;
+    let capabilities = session.capabilities.clone();
+    let outcome = sync_folder(&mut session, &capabilities, &mailbox_name, &account_id, &target_folder, previous_state.as_ref()).await;
+    let outcome = session.finish(outcome)?;
+
+    // 4. Reconcile local rows against the server
+    let local_rows = sqlx::query_as::<_, (String, i64)>(
+        "SELECT id, uid FROM emails WHERE account_id = $1 AND folder = $2"
+    )
+    .bind(&account_id)
+    .bind(&folder_for_db)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+
+    // Only rows that mirror a server message are reconciled; local placeholders
+    // (draft_*, sent_*) are left alone.
+    let stale_ids: Vec<String> = local_rows
+        .into_iter()
+        .filter(|(id, uid)| *id == local_email_id(&account_id, &folder_for_db, *uid as u32))
+        .filter(|(_, uid)| outcome.validity_changed || outcome.expunges.removes(*uid as u32))
+        .map(|(id, _)| id)
+        .collect();
+
+    if !stale_ids.is_empty() {
+        log::info!("[SYNC] Removing {} messages no longer on the server from {}", stale_ids.len(), folder_for_db);
+        delete_local_emails(&pool, &stale_ids).await?;
     }
 
-    log::info!("Synced {} emails from IMAP for {}", fetched_emails.len(), account.email);
-    Ok(fetched_emails)
+    // 5. Save all fetched emails to local SQLite
+    store_emails(&pool, &account_id, &folder_for_db, &outcome.emails).await?;
+    if !outcome.emails.is_empty() {
+        crate::threads::thread_account(&pool, &account_id).await?;
+    }
+
+    // 6. Apply flag changes made by other clients
+    apply_flag_updates(&pool, &account_id, &folder_for_db, &outcome.flag_updates).await?;
+    if !outcome.flag_updates.is_empty() {
+        log::info!("[SYNC] Applied {} flag updates in {}", outcome.flag_updates.len(), folder_for_db);
+    }
+
+    // 7. Advance the cursors only after the rows are stored
+    let state = advanced_state(previous_state.as_ref(), &outcome);
+    save_sync_state(&pool, &account_id, &folder_for_db, &state).await?;
+
+    log::info!("Synced {} new emails from IMAP for {}", outcome.emails.len(), account.email);
+
+    // 8. Pull older history in the background once the folder has a starting point
+    crate::imap_backfill::spawn_backfill(app.clone(), account_id.clone());
+
+    Ok(outcome.emails)
+}
+
+/// Display names and addresses of an ENVELOPE address list, RFC 2047 decoded.
+/// Group markers (`undisclosed-recipients:;`) are skipped.
+pub fn envelope_addresses(addrs: &[Address]) -> Vec<(Option<String>, String)> {
+    let decode = |b: &Option<std::borrow::Cow<[u8]>>| b.as_deref().map(crate::mime_header::decode_header_bytes);
+    addrs
+        .iter()
+        .filter_map(|addr| {
+            let mailbox = decode(&addr.mailbox)?;
+            let host = decode(&addr.host)?;
+            let name = decode(&addr.name).filter(|n| !n.is_empty());
+            Some((name, format!("{}@{}", mailbox, host)))
+        })
+        .collect()
+}
+
+/// Turn a LIST_FETCH_QUERY fetch into the JSON row shape used by sync.
+/// If the fetch also carried RFC822, the body is extracted and marked cached.
+pub fn parse_fetched_message(msg: &Fetch, account_id: &str, folder: &str) -> serde_json::Value {
+    let uid = msg.uid.unwrap_or(0);
+    let flags = FlagUpdate::from_flags(uid, msg.flags());
+    let envelope = msg.envelope();
+
+    let subject = envelope
+        .and_then(|env| env.subject.as_ref())
+        .map(|s| crate::mime_header::decode_header_bytes(s))
+        .unwrap_or_default();
+
+    // Extract sender
+    let (sender, sender_email) = envelope
+        .and_then(|env| env.from.as_deref())
+        .and_then(|addrs| envelope_addresses(addrs).into_iter().next())
+        .map(|(name, email)| (name.unwrap_or_else(|| email.clone()), email))
+        .unwrap_or_else(|| ("Unknown".to_string(), String::new()));
+
+    // Extract recipient (To) — critical for drafts
+    let to_email = envelope
+        .and_then(|env| env.to.as_deref())
+        .and_then(|addrs| envelope_addresses(addrs).into_iter().next())
+        .map(|(_, email)| email)
+        .unwrap_or_default();
+
+    // Threading headers; ENVELOPE has Message-ID and In-Reply-To but not References
+    let thread_headers = msg.header()
+        .and_then(|raw| parse_headers(raw).ok())
+        .map(|(headers, _)| headers)
+        .unwrap_or_default();
+    let header_ids = |name: &str, fallback: Option<&[u8]>| {
+        let value = thread_headers.get_first_value(name)
+            .or_else(|| fallback.map(|v| String::from_utf8_lossy(v).to_string()))
+            .unwrap_or_default();
+        crate::threads::parse_message_ids(&value)
+    };
+    let message_id = header_ids("Message-ID", envelope.and_then(|e| e.message_id.as_deref())).into_iter().next();
+    let in_reply_to = header_ids("In-Reply-To", envelope.and_then(|e| e.in_reply_to.as_deref())).into_iter().next();
+    let refs = Some(header_ids("References", None).join(" ")).filter(|r| !r.is_empty());
+
+    let date_raw = envelope
+        .and_then(|env| env.date.as_ref())
+        .map(|d| String::from_utf8_lossy(d).to_string())
+        .unwrap_or_default();
+
+    // Parse date to ISO format for proper sorting
+    let date_iso = parse_rfc2822_to_iso(&date_raw);
+
+    let parts = msg.bodystructure()
+        .map(crate::imap_body::flatten_bodystructure)
+        .unwrap_or_default();
+
+    // Parse the full RFC822 body with mailparse, when it was fetched
+    let (body, snippet, is_html) = match msg.body() {
+        Some(raw) => {
+            let (body_html, body_plain) = extract_bodies(raw);
+            // Use HTML for body (to render as sent), plain text for snippet
+            let body = if !body_html.is_empty() { body_html.clone() } else { body_plain.clone() };
+            let snippet_source = if !body_plain.is_empty() { body_plain } else { strip_html_tags(&body_html) };
+            let snippet: String = snippet_source.chars().take(150).collect();
+            (Some(body), Some(snippet), !body_html.is_empty())
+        }
+        None => (None, None, parts.iter().any(|p| p.mime_type == "text/html")),
+    };
+
+    let email_id = local_email_id(account_id, folder, uid);
+
+    serde_json::json!({
+        "id": email_id,
+        "uid": uid,
+        "subject": subject,
+        "sender": sender,
+        "sender_email": sender_email,
+        "to_email": to_email,
+        "message_id": message_id,
+        "in_reply_to": in_reply_to,
+        "refs": refs,
+        "addresses": envelope.map(crate::addresses::from_envelope).unwrap_or_default(),
+        "date": date_iso,
+        "snippet": snippet,
+        "body": body,
+        "body_cached": body.is_some(),
+        "size": msg.size,
+        "body_structure": serde_json::to_string(&parts).unwrap_or_default(),
+        "folder": folder,
+        "account_id": account_id,
+        "is_html": is_html,
+        "read": flags.seen,
+        "flagged": flags.flagged,
+        "answered": flags.answered
+    })
 }
 
 /// Extract both HTML and plain text bodies from a parsed email
-fn extract_bodies(raw: &[u8]) -> (String, String) {
+pub fn extract_bodies(raw: &[u8]) -> (String, String) {
     match parse_mail(raw) {
         Ok(parsed) => {
             let html = find_mime_part(&parsed, "text/html").unwrap_or_default();
@@ -249,7 +1101,7 @@ fn find_mime_part(mail: &mailparse::ParsedMail, target_type: &str) -> Option<Str
 }
 
 /// Strip HTML tags for snippet generation
-fn strip_html_tags(html: &str) -> String {
+pub fn strip_html_tags(html: &str) -> String {
     let mut result = String::with_capacity(html.len());
     let mut in_tag = false;
     let mut in_style = false;
@@ -321,7 +1173,7 @@ pub async fn save_draft(
     let pool = state.pool.clone();
 
     let account = sqlx::query_as::<_, crate::db::Account>(
-        "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port FROM accounts WHERE id = $1"
+        "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port, imap_security, smtp_security, ca_cert, tls_pinning, auth_method FROM accounts WHERE id = $1"
     )
     .bind(&account_id)
     .fetch_optional(&pool)
@@ -329,10 +1181,7 @@ pub async fn save_draft(
     .map_err(|e| format!("DB error: {}", e))?
     .ok_or_else(|| "Account not found".to_string())?;
 
-    let imap_host = account.imap_host.clone().ok_or("IMAP host not configured")?;
-    let imap_port = account.imap_port.unwrap_or(993) as u16;
     let email_addr = account.email.clone();
-    let password = account.password.clone().ok_or("Password not configured")?;
     let from_name = account.full_name.clone().unwrap_or_else(|| email_addr.clone());
 
     // Build RFC822 message
@@ -342,7 +1191,7 @@ pub async fn save_draft(
         from_name, email_addr, to, subject, date_str, body
     );
 
-    log::info!("Saving draft to IMAP Drafts folder for {}", email_addr);
+    log::info!("Saving draft for {}", email_addr);
 
     // 🧹 Dedup: remove existing local drafts with the same subject before creating new one
     let dedup_count = sqlx::query_scalar::<_, i64>(
@@ -365,55 +1214,12 @@ pub async fn save_draft(
         .await;
     }
 
-    tokio::task::spawn_blocking(move || -> Result<String, String> {
-        let tls = native_tls::TlsConnector::builder()
-            .build()
-            .map_err(|e| format!("TLS error: {}", e))?;
-
-        let client = imap::connect(
-            (imap_host.as_str(), imap_port),
-            &imap_host,
-            &tls,
-        ).map_err(|e| format!("IMAP connect error: {}", e))?;
-
-        let mut session = client
-            .login(&email_addr, &password)
-            .map_err(|e| format!("IMAP login error: {}", e.0))?;
-
-        // Try common draft folder names
-        let draft_folders = ["Drafts", "INBOX.Drafts", "Draft", "INBOX.Draft", "[Gmail]/Drafts"];
-        let mut saved = false;
-
-        for folder in &draft_folders {
-            match session.append(folder, message.as_bytes()) {
-                Ok(_) => {
-                    log::info!("Draft saved to folder: {}", folder);
-                    saved = true;
-                    break;
-                }
-                Err(_) => continue,
-            }
-        }
-
-        if !saved {
-            // If no draft folder found, try creating one
-            let _ = session.create("Drafts");
-            session.append("Drafts", message.as_bytes())
-                .map_err(|e| format!("Failed to save draft: {}", e))?;
-        }
-
-        session.logout().ok();
-        Ok("Draft saved".to_string())
-    })
-    .await
-    .map_err(|e| format!("Thread error: {}", e))??;
-
-    // Also save to local DB so it appears immediately! (must be done outside spawn_blocking because it's async)
+    // Also save to local DB so it appears immediately!
     let state = app.state::<crate::db::DbState>();
     let new_id = format!("draft_{}", chrono::Utc::now().timestamp_millis());
     let _ = sqlx::query(
-        r#"INSERT OR IGNORE INTO emails (id, uid, account_id, folder, subject, sender, sender_email, date, snippet, body, read)
-           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, 1)"#
+        r#"INSERT OR IGNORE INTO emails (id, uid, account_id, folder, subject, sender, sender_email, date, snippet, body, read, body_cached)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, 1, 1)"#
     )
     .bind(&new_id)
     .bind(9999999_i64) // High UID for local drafts until synced
@@ -427,115 +1233,40 @@ pub async fn save_draft(
     .bind(&body)
     .execute(&state.pool)
     .await;
+    let _ = crate::addresses::store_addresses(&pool, &new_id, &crate::addresses::outgoing(&from_name, &email_addr, &to)).await;
+    let _ = crate::threads::thread_account(&pool, &account_id).await;
+
+    // The server copy is appended by the operation queue, now or once back online
+    let operation = crate::pending_ops::Operation::AppendDraft { email_id: new_id, message };
+    crate::pending_ops::enqueue(&app, &account_id, &operation).await?;
 
     Ok("Draft saved".to_string())
 }
 
+/// Tauri command: the server capabilities last seen for the account, if it has connected yet
+#[tauri::command]
+pub async fn get_imap_capabilities(app: AppHandle, account_id: String) -> Result<Option<Vec<String>>, String> {
+    let state = app.state::<DbState>();
+    let stored: Option<String> = sqlx::query_scalar("SELECT capabilities FROM imap_capabilities WHERE account_id = $1")
+        .bind(&account_id)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    Ok(stored.map(|caps| caps.split_whitespace().map(str::to_string).collect()))
+}
+
+/// Delete one email: moves it to Trash, or expunges it if it is already there
 #[tauri::command]
 pub async fn imap_delete_email(
     app: AppHandle,
     account_id: String,
     email_id: String,
 ) -> Result<(), String> {
-    let state = app.state::<DbState>();
-
-    // Get email UID and folder from local DB
-    let email = sqlx::query_as::<_, (i64, String)>(
-        "SELECT uid, folder FROM emails WHERE id = $1"
-    )
-    .bind(&email_id)
-    .fetch_optional(&state.pool)
-    .await
-    .map_err(|e| format!("DB error: {}", e))?
-    .ok_or("Email not found")?;
-
-    let (uid, folder) = email;
-
-    // Get account credentials
-    let account = sqlx::query_as::<_, crate::db::Account>(
-        "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port FROM accounts WHERE id = $1"
-    )
-    .bind(&account_id)
-    .fetch_optional(&state.pool)
-    .await
-    .map_err(|e| format!("DB error: {}", e))?
-    .ok_or("Account not found")?;
-
-    let imap_host = account.imap_host.clone().ok_or("IMAP host not configured")?;
-    let imap_port = account.imap_port.unwrap_or(993) as u16;
-    let email_addr = account.email.clone();
-    let password = account.password.clone().ok_or("Password not configured")?;
-    let uid_val = uid as u32;
-    let folder_clone = folder.clone();
-
-    // Delete from IMAP server
-    tokio::task::spawn_blocking(move || -> Result<(), String> {
-        let tls = native_tls::TlsConnector::builder()
-            .build()
-            .map_err(|e| format!("TLS error: {}", e))?;
-
-        let client = imap::connect(
-            (imap_host.as_str(), imap_port),
-            &imap_host,
-            &tls,
-        ).map_err(|e| format!("IMAP connect error: {}", e))?;
-
-        let mut session = client
-            .login(&email_addr, &password)
-            .map_err(|e| format!("IMAP login error: {}", e.0))?;
-
-        // Map folder names
-        let imap_folders: Vec<String> = match folder_clone.as_str() {
-            "INBOX" => vec!["INBOX".to_string()],
-            "Drafts" => vec!["Drafts", "INBOX.Drafts", "Draft", "INBOX.Draft"]
-                .into_iter().map(|s| s.to_string()).collect(),
-            "Sent" => vec!["Sent", "INBOX.Sent", "Sent Messages"]
-                .into_iter().map(|s| s.to_string()).collect(),
-            "Trash" => vec!["Trash", "INBOX.Trash"]
-                .into_iter().map(|s| s.to_string()).collect(),
-            other => vec![other.to_string()],
-        };
-
-        let mut selected = false;
-        for f in &imap_folders {
-            if session.select(f).is_ok() {
-                selected = true;
-                break;
-            }
-        }
-        if !selected {
-            session.logout().ok();
-            return Err(format!("Could not select folder: {}", folder_clone));
-        }
-
-        // Flag as \Deleted and expunge
-        let uid_set = format!("{}", uid_val);
-        session.uid_store(&uid_set, "+FLAGS (\\Deleted)")
-            .map_err(|e| format!("IMAP store error: {}", e))?;
-        session.expunge()
-            .map_err(|e| format!("IMAP expunge error: {}", e))?;
-
-        log::info!("[IMAP] Deleted email UID {} from folder {}", uid_val, folder_clone);
-        session.logout().ok();
-        Ok(())
-    }).await.map_err(|e| format!("Task error: {}", e))??;
-
-    // Also delete from local DB
-    sqlx::query("DELETE FROM emails WHERE id = $1")
-        .bind(&email_id)
-        .execute(&state.pool)
-        .await
-        .map_err(|e| format!("DB delete error: {}", e))?;
-
-    // Clean up triage log for this email
-    let _ = sqlx::query("DELETE FROM ai_triage_log WHERE email_id = $1")
-        .bind(&email_id)
-        .execute(&state.pool)
-        .await;
-
+    crate::imap_actions::delete_emails(&app, &account_id, std::slice::from_ref(&email_id)).await?;
     Ok(())
 }
 
+/// Delete every email of a folder; emptying Trash deletes them for good
 #[tauri::command]
 pub async fn imap_bulk_delete(
     app: AppHandle,
@@ -544,93 +1275,106 @@ pub async fn imap_bulk_delete(
 ) -> Result<i64, String> {
     let state = app.state::<DbState>();
 
-    // Get all UIDs from local DB for this folder
-    let uids = sqlx::query_as::<_, (i64,)>(
-        "SELECT uid FROM emails WHERE account_id = $1 AND folder = $2"
+    let ids: Vec<String> = sqlx::query_as::<_, (String,)>(
+        "SELECT id FROM emails WHERE account_id = $1 AND folder = $2"
     )
     .bind(&account_id)
     .bind(&folder)
     .fetch_all(&state.pool)
     .await
-    .map_err(|e| format!("DB error: {}", e))?;
+    .map_err(|e| format!("DB error: {}", e))?
+    .into_iter()
+    .map(|(id,)| id)
+    .collect();
 
-    if uids.is_empty() {
+    if ids.is_empty() {
         return Ok(0);
     }
 
-    let uid_list: Vec<u32> = uids.iter().map(|(u,)| *u as u32).collect();
-    let count = uid_list.len() as i64;
-    log::info!("[IMAP] Bulk deleting {} emails from {}", count, folder);
+    log::info!("[IMAP] Bulk deleting {} emails from {}", ids.len(), folder);
+    let count = crate::imap_actions::delete_emails(&app, &account_id, &ids).await?;
+    Ok(count as i64)
+}
 
-    let account = sqlx::query_as::<_, crate::db::Account>(
-        "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port FROM accounts WHERE id = $1"
-    )
-    .bind(&account_id)
-    .fetch_optional(&state.pool)
-    .await
-    .map_err(|e| format!("DB error: {}", e))?
-    .ok_or("Account not found")?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let imap_host = account.imap_host.clone().ok_or("IMAP host not configured")?;
-    let imap_port = account.imap_port.unwrap_or(993) as u16;
-    let email_addr = account.email.clone();
-    let password = account.password.clone().ok_or("Password not configured")?;
-    let folder_clone = folder.clone();
-
-    tokio::task::spawn_blocking(move || -> Result<(), String> {
-        let tls = native_tls::TlsConnector::builder()
-            .build()
-            .map_err(|e| format!("TLS error: {}", e))?;
-
-        let client = imap::connect(
-            (imap_host.as_str(), imap_port),
-            &imap_host,
-            &tls,
-        ).map_err(|e| format!("IMAP connect error: {}", e))?;
-
-        let mut session = client
-            .login(&email_addr, &password)
-            .map_err(|e| format!("IMAP login error: {}", e.0))?;
-
-        let imap_folders: Vec<String> = match folder_clone.as_str() {
-            "INBOX" => vec!["INBOX".to_string()],
-            "Drafts" => vec!["Drafts", "INBOX.Drafts", "Draft", "INBOX.Draft"]
-                .into_iter().map(|s| s.to_string()).collect(),
-            "Sent" => vec!["Sent", "INBOX.Sent", "Sent Messages"]
-                .into_iter().map(|s| s.to_string()).collect(),
-            "Trash" => vec!["Trash", "INBOX.Trash"]
-                .into_iter().map(|s| s.to_string()).collect(),
-            other => vec![other.to_string()],
-        };
-
-        let mut selected = false;
-        for f in &imap_folders {
-            if session.select(f).is_ok() { selected = true; break; }
-        }
-        if !selected {
-            session.logout().ok();
-            return Err(format!("Could not select folder: {}", folder_clone));
-        }
-
-        // Flag ALL UIDs as \Deleted in ONE call
-        let uid_set = uid_list.iter().map(|u| u.to_string()).collect::<Vec<_>>().join(",");
-        session.uid_store(&uid_set, "+FLAGS (\\Deleted)")
-            .map_err(|e| format!("IMAP store error: {}", e))?;
-        session.expunge()
-            .map_err(|e| format!("IMAP expunge error: {}", e))?;
-
-        log::info!("[IMAP] ✅ Bulk deleted {} emails from {}", uid_list.len(), folder_clone);
-        session.logout().ok();
-        Ok(())
-    }).await.map_err(|e| format!("Task error: {}", e))??;
-
-    // Bulk delete from local DB
-    sqlx::query("DELETE FROM emails WHERE account_id = $1 AND folder = $2")
-        .bind(&account_id)
-        .bind(&folder)
-        .execute(&state.pool)
-        .await
-        .map_err(|e| format!("DB delete error: {}", e))?;
+    fn state(uid_validity: i64, highest_uid: i64) -> FolderSyncState {
+        FolderSyncState { uid_validity, uid_next: None, highest_uid, highest_modseq: None, lowest_uid: None, backfill_done: false }
+    }
+
+    fn outcome(uid_validity: u32, validity_changed: bool, fetched: &[i64]) -> FolderSyncOutcome {
+        FolderSyncOutcome {
+            uid_validity,
+            uid_next: Some(500),
+            highest_modseq: None,
+            validity_changed,
+            expunges: Expunges::Present(HashSet::new()),
+            emails: fetched.iter().map(|uid| serde_json::json!({ "uid": uid })).collect(),
+            flag_updates: vec![],
+        }
+    }
+
+    #[test]
+    fn formats_uid_sets() {
+        assert_eq!(format_uid_set(&[]), "");
+        assert_eq!(format_uid_set(&[7]), "7");
+        assert_eq!(format_uid_set(&[10, 9, 1, 2, 3, 7, 2]), "1:3,7,9:10");
+        assert_eq!(format_uid_set(&[u32::MAX - 1, u32::MAX]), format!("{}:{}", u32::MAX - 1, u32::MAX));
+    }
 
-    Ok(count)
+    #[test]
+    fn parses_uid_sets() {
+        assert_eq!(parse_uid_set("304,319:320"), vec![304, 319, 320]);
+        // Ranges may be written high to low
+        assert_eq!(parse_uid_set("5:3"), vec![3, 4, 5]);
+        assert_eq!(parse_uid_set(&format_uid_set(&[1, 2, 3, 7, 9, 10])), vec![1, 2, 3, 7, 9, 10]);
+        // `*` can't be expanded; the rest of the set still is
+        assert_eq!(parse_uid_set("4:*,8"), vec![8]);
+        assert_eq!(parse_uid_set("*"), Vec::<u32>::new());
+        assert_eq!(parse_uid_set("x,2"), vec![2]);
+    }
+
+    #[test]
+    fn fetches_what_is_above_the_cursor() {
+        let server: HashSet<u32> = [3, 5, 8, 9].into_iter().collect();
+        assert_eq!(uids_to_fetch(&server, 5), vec![8, 9]);
+        assert!(uids_to_fetch(&server, 9).is_empty());
+        // `UID 10:*` still matches the newest message when there is nothing above 9
+        let star_match: HashSet<u32> = [9].into_iter().collect();
+        assert!(uids_to_fetch(&star_match, 9).is_empty());
+    }
+
+    #[test]
+    fn first_sync_takes_the_newest_window() {
+        let server: HashSet<u32> = (1..=INITIAL_SYNC_WINDOW as u32 + 50).collect();
+        let uids = uids_to_fetch(&server, 0);
+        assert_eq!(uids.len(), INITIAL_SYNC_WINDOW);
+        assert_eq!(uids.first(), Some(&51));
+        assert_eq!(uids.last(), Some(&(INITIAL_SYNC_WINDOW as u32 + 50)));
+        // Small folders are taken whole
+        let small: HashSet<u32> = [4, 2].into_iter().collect();
+        assert_eq!(uids_to_fetch(&small, 0), vec![2, 4]);
+    }
+
+    #[test]
+    fn advances_the_cursor() {
+        let next = advanced_state(Some(&state(7, 100)), &outcome(7, false, &[101, 104]));
+        assert_eq!((next.highest_uid, next.lowest_uid), (104, Some(101)));
+        // Nothing new: the cursor stays, and the stored lowest UID is kept by `save_sync_state`
+        let next = advanced_state(Some(&state(7, 100)), &outcome(7, false, &[]));
+        assert_eq!((next.highest_uid, next.lowest_uid), (100, None));
+        // First sync: the backfill starts below the oldest message of the window
+        let next = advanced_state(None, &outcome(7, false, &[51, 250]));
+        assert_eq!((next.highest_uid, next.lowest_uid, next.uid_next), (250, Some(51), Some(500)));
+    }
+
+    #[test]
+    fn restarts_the_cursor_after_a_uidvalidity_change() {
+        let next = advanced_state(Some(&state(7, 900)), &outcome(8, true, &[3, 12]));
+        assert_eq!((next.uid_validity, next.highest_uid, next.lowest_uid), (8, 12, Some(3)));
+        let next = advanced_state(Some(&state(7, 900)), &outcome(8, true, &[]));
+        assert_eq!(next.highest_uid, 0);
+    }
 }
@@ -0,0 +1,362 @@
+/// Message actions — move and copy between folders, flag changes and delete.
+/// Each command changes the local rows at once and queues the server side in
+/// `pending_ops`, which replays it with the IMAP helpers below. Moved/copied rows
+/// follow the messages using the UIDs reported by COPYUID (RFC 4315); without one,
+/// the target folder's next sync picks the messages up.
+use tauri::{AppHandle, Emitter, Manager};
+use crate::db::DbState;
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use crate::pending_ops::{Location, Operation, TransferItem};
+use async_imap::imap_proto::{Response, ResponseCode, UidSetMember};
+use futures::TryStreamExt;
+use crate::imap::ImapSession;
+
+/// A local email's location on the server
+pub struct EmailLocation {
+    pub id: String,
+    pub uid: u32,
+}
+
+/// Group local email ids by folder. Rows that only exist locally (unsynced drafts) are skipped.
+pub async fn locate_emails(pool: &SqlitePool, account_id: &str, email_ids: &[String]) -> Result<BTreeMap<String, Vec<EmailLocation>>, String> {
+    let mut groups: BTreeMap<String, Vec<EmailLocation>> = BTreeMap::new();
+    for id in email_ids {
+        let (folder, uid) = sqlx::query_as::<_, (String, i64)>(
+            "SELECT folder, uid FROM emails WHERE id = $1 AND account_id = $2"
+        )
+        .bind(id)
+        .bind(account_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?
+        .ok_or_else(|| format!("Email not found: {}", id))?;
+
+        if *id != crate::imap::local_email_id(account_id, &folder, uid as u32) {
+            log::warn!("[IMAP] {} is not on the server yet, skipping", id);
+            continue;
+        }
+        groups.entry(folder).or_default().push(EmailLocation { id: id.clone(), uid: uid as u32 });
+    }
+    Ok(groups)
+}
+
+fn expand_uid_set(members: &[UidSetMember]) -> Vec<u32> {
+    members
+        .iter()
+        .flat_map(|m| match m {
+            UidSetMember::Uid(uid) => *uid..=*uid,
+            UidSetMember::UidRange(range) => range.clone(),
+        })
+        .collect()
+}
+
+/// Record the source UID → destination UID pairs of a `[COPYUID validity src dst]` response code
+fn collect_copyuid(response: &Response<'_>, map: &mut HashMap<u32, u32>) {
+    let code = match response {
+        Response::Data { code: Some(code), .. } | Response::Done { code: Some(code), .. } => code,
+        _ => return,
+    };
+    if let ResponseCode::CopyUid(_validity, src, dst) = code {
+        let src = expand_uid_set(src);
+        let dst = expand_uid_set(dst);
+        if src.len() == dst.len() {
+            map.extend(src.into_iter().zip(dst));
+        }
+    }
+}
+
+/// UID MOVE when the server supports it, otherwise UID COPY (+ expunging the originals for a move)
+pub async fn transfer(session: &mut ImapSession, capabilities: &[String], uids: &[u32], target: &str, is_move: bool) -> Result<HashMap<u32, u32>, String> {
+    let has_move = crate::imap::has_capability(capabilities, "MOVE");
+    let set = crate::imap::format_uid_set(uids);
+    let target = crate::imap::quote_mailbox(target);
+    let mut map = HashMap::new();
+
+    if is_move && has_move {
+        // RFC 6851: COPYUID comes back as an untagged OK before the EXPUNGEs
+        crate::imap::run_raw(session, &format!("UID MOVE {} {}", set, target), |r| collect_copyuid(r, &mut map)).await?;
+        return Ok(map);
+    }
+
+    // RFC 4315: the COPYUID of a COPY is part of the tagged OK
+    crate::imap::run_raw(session, &format!("UID COPY {} {}", set, target), |r| collect_copyuid(r, &mut map)).await?;
+
+    if is_move {
+        expunge_uids(session, capabilities, uids).await?;
+    }
+    Ok(map)
+}
+
+/// UID STORE, draining the (silent) FETCH responses
+pub async fn store_flags(session: &mut ImapSession, set: &str, item: &str) -> Result<(), String> {
+    session
+        .uid_store(set, item)
+        .await
+        .map_err(|e| format!("IMAP store error: {}", e))?
+        .try_collect::<Vec<_>>()
+        .await
+        .map_err(|e| format!("IMAP store error: {}", e))?;
+    Ok(())
+}
+
+/// Duplicate a local row under the copy's folder/UID
+async fn copy_local_email(pool: &SqlitePool, source_id: &str, new_id: &str, folder: &str, uid: u32) -> Result<(), String> {
+    sqlx::query(
+        r#"INSERT OR REPLACE INTO emails (id, uid, account_id, folder, subject, sender, sender_email, to_email, date, snippet, body, read, flagged, answered, size, body_structure, body_cached, ai_priority, ai_labels, ai_summary, gm_msgid, gm_thrid, message_id, in_reply_to, refs, thread_id)
+           SELECT $1, $2, account_id, $3, subject, sender, sender_email, to_email, date, snippet, body, read, flagged, answered, size, body_structure, body_cached, ai_priority, ai_labels, ai_summary, gm_msgid, gm_thrid, message_id, in_reply_to, refs, thread_id
+           FROM emails WHERE id = $4"#
+    )
+    .bind(new_id)
+    .bind(uid as i64)
+    .bind(folder)
+    .bind(source_id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+
+    let _ = sqlx::query(
+        "INSERT OR IGNORE INTO email_parts (email_id, part_path, data) SELECT $1, part_path, data FROM email_parts WHERE email_id = $2"
+    )
+    .bind(new_id)
+    .bind(source_id)
+    .execute(pool)
+    .await;
+    let _ = sqlx::query(
+        "INSERT OR IGNORE INTO attachments (email_id, part_path, filename, mime_type, size, content_id, inline) SELECT $1, part_path, filename, mime_type, size, content_id, inline FROM attachments WHERE email_id = $2"
+    )
+    .bind(new_id)
+    .bind(source_id)
+    .execute(pool)
+    .await;
+    let _ = sqlx::query(
+        "INSERT OR IGNORE INTO email_addresses (email_id, position, role, name, address) SELECT $1, position, role, name, address FROM email_addresses WHERE email_id = $2"
+    )
+    .bind(new_id)
+    .bind(source_id)
+    .execute(pool)
+    .await;
+    Ok(())
+}
+
+/// Move or copy local emails to `target_folder` (a UI folder name or raw mailbox name).
+/// Moved rows switch folder and copies get a row of their own right away, under a
+/// placeholder id until the queued operation learns their UIDs.
+async fn transfer_emails(app: &AppHandle, account_id: &str, email_ids: &[String], target_folder: &str, is_move: bool) -> Result<usize, String> {
+    let state = app.state::<DbState>();
+    let pool = state.pool.clone();
+    // Rows are keyed like sync stores them; the replay resolves the server mailbox
+    let target_folder = crate::imap_folders::folder_key(&pool, account_id, target_folder).await?;
+    let target_folder = target_folder.as_str();
+
+    let mut items = Vec::new();
+    for email in crate::pending_ops::load_local(&pool, account_id, email_ids).await? {
+        if email.folder == target_folder {
+            continue;
+        }
+        let Some(source) = email.location else {
+            log::warn!("[IMAP] {} is not on the server yet, skipping", email.id);
+            continue;
+        };
+        let local_id = crate::pending_ops::placeholder_id();
+        if is_move {
+            crate::imap::rekey_local_email(&pool, &email.id, &local_id, target_folder, email.uid).await?;
+        } else {
+            copy_local_email(&pool, &email.id, &local_id, target_folder, email.uid).await?;
+        }
+        items.push(TransferItem { source, local_id, sent: false });
+    }
+    if items.is_empty() {
+        return Ok(0);
+    }
+
+    let count = items.len();
+    log::info!("[IMAP] {} {} emails → {}", if is_move { "Moving" } else { "Copying" }, count, target_folder);
+    let operation = Operation::Transfer { items, target: target_folder.to_string(), is_move };
+    crate::pending_ops::enqueue(app, account_id, &operation).await?;
+    Ok(count)
+}
+
+/// Tauri command: move emails to another folder. Returns how many were moved.
+#[tauri::command]
+pub async fn imap_move_emails(app: AppHandle, account_id: String, email_ids: Vec<String>, target_folder: String) -> Result<usize, String> {
+    transfer_emails(&app, &account_id, &email_ids, &target_folder, true).await
+}
+
+/// Tauri command: copy emails to another folder. Returns how many were copied.
+#[tauri::command]
+pub async fn imap_copy_emails(app: AppHandle, account_id: String, email_ids: Vec<String>, target_folder: String) -> Result<usize, String> {
+    transfer_emails(&app, &account_id, &email_ids, &target_folder, false).await
+}
+
+/// `+FLAGS`/`-FLAGS` item lists for a flag change. `None` leaves a flag untouched.
+pub fn flag_store_items(read: Option<bool>, flagged: Option<bool>, answered: Option<bool>) -> Vec<String> {
+    let mut add = Vec::new();
+    let mut remove = Vec::new();
+    for (value, flag) in [(read, "\\Seen"), (flagged, "\\Flagged"), (answered, "\\Answered")] {
+        match value {
+            Some(true) => add.push(flag),
+            Some(false) => remove.push(flag),
+            None => {}
+        }
+    }
+    let mut items = Vec::new();
+    if !add.is_empty() {
+        items.push(format!("+FLAGS.SILENT ({})", add.join(" ")));
+    }
+    if !remove.is_empty() {
+        items.push(format!("-FLAGS.SILENT ({})", remove.join(" ")));
+    }
+    items
+}
+
+/// Tauri command: set or clear \Seen, \Flagged and \Answered on a batch of emails.
+/// The local rows change at once and the UID STORE is queued. Returns how many rows were updated.
+#[tauri::command]
+pub async fn set_email_flags(
+    app: AppHandle,
+    account_id: String,
+    email_ids: Vec<String>,
+    read: Option<bool>,
+    flagged: Option<bool>,
+    answered: Option<bool>,
+) -> Result<usize, String> {
+    if flag_store_items(read, flagged, answered).is_empty() || email_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let state = app.state::<DbState>();
+    let pool = state.pool.clone();
+
+    let emails = crate::pending_ops::load_local(&pool, &account_id, &email_ids).await?;
+    let mut updated = 0;
+    for id in &email_ids {
+        updated += sqlx::query(
+            "UPDATE emails SET read = COALESCE($1, read), flagged = COALESCE($2, flagged), answered = COALESCE($3, answered) WHERE id = $4 AND account_id = $5"
+        )
+        .bind(read)
+        .bind(flagged)
+        .bind(answered)
+        .bind(id)
+        .bind(&account_id)
+        .execute(&pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?
+        .rows_affected() as usize;
+    }
+
+    // Local-only rows (unsynced drafts) have no flags on the server
+    let messages: Vec<Location> = emails.into_iter().filter_map(|e| e.location).collect();
+    if !messages.is_empty() {
+        crate::pending_ops::enqueue(&app, &account_id, &Operation::Flags { messages, read, flagged, answered }).await?;
+    }
+
+    Ok(updated)
+}
+
+/// Tauri command: mark emails read or unread
+#[tauri::command]
+pub async fn mark_read(app: AppHandle, account_id: String, email_ids: Vec<String>, read: bool) -> Result<usize, String> {
+    set_email_flags(app, account_id, email_ids, Some(read), None, None).await
+}
+
+/// Permanently remove exactly `uids` from the selected mailbox. With UIDPLUS this is
+/// UID EXPUNGE; otherwise other messages' \Deleted flags are lifted around a plain EXPUNGE.
+pub async fn expunge_uids(session: &mut ImapSession, capabilities: &[String], uids: &[u32]) -> Result<(), String> {
+    let set = crate::imap::format_uid_set(uids);
+    store_flags(session, &set, "+FLAGS.SILENT (\\Deleted)").await?;
+
+    if crate::imap::has_capability(capabilities, "UIDPLUS") {
+        session
+            .uid_expunge(&set)
+            .await
+            .map_err(|e| format!("IMAP expunge error: {}", e))?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(|e| format!("IMAP expunge error: {}", e))?;
+        return Ok(());
+    }
+
+    let targets: HashSet<u32> = uids.iter().copied().collect();
+    let others: Vec<u32> = session.uid_search("DELETED")
+        .await
+        .map_err(|e| format!("Search error: {}", e))?
+        .into_iter()
+        .filter(|u| !targets.contains(u))
+        .collect();
+    let others_set = crate::imap::format_uid_set(&others);
+    if !others.is_empty() {
+        store_flags(session, &others_set, "-FLAGS.SILENT (\\Deleted)").await?;
+    }
+    let expunged = match session.expunge().await {
+        Ok(responses) => responses.try_collect::<Vec<_>>().await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if !others.is_empty() {
+        store_flags(session, &others_set, "+FLAGS.SILENT (\\Deleted)").await?;
+    }
+    expunged.map_err(|e| format!("IMAP expunge error: {}", e))
+}
+
+/// Payload of `emails-deleted`: which emails went to Trash and which are gone for good
+#[derive(Clone, serde::Serialize)]
+pub struct EmailsDeletedPayload {
+    pub account_id: String,
+    pub moved_to_trash: Vec<String>,
+    pub deleted_permanently: Vec<String>,
+    /// The server has no Trash folder, so nothing could be moved there
+    pub no_trash: bool,
+}
+
+/// Delete emails: anything outside Trash is moved to Trash, emails already in
+/// Trash are expunged for good. A server without a Trash folder would refuse the
+/// move, so there every email is expunged. Emits `emails-deleted` with the outcome
+/// and returns how many emails were deleted.
+pub async fn delete_emails(app: &AppHandle, account_id: &str, email_ids: &[String]) -> Result<usize, String> {
+    let state = app.state::<DbState>();
+    let pool = state.pool.clone();
+
+    // The stored folder list is enough, so this works offline
+    let mut folders = crate::imap_folders::load_folders(&pool, account_id).await?;
+    if folders.is_empty() {
+        // Never listed; when offline, assume a "Trash" folder as before
+        let account = crate::imap_folders::load_account(&pool, account_id).await?;
+        folders = crate::imap_folders::refresh_folders(&pool, &account).await.unwrap_or_default();
+    }
+    let trash_mailbox = crate::imap_folders::mailbox_for_role(&folders, "Trash");
+    let has_trash = folders.is_empty() || trash_mailbox.is_some() || folders.iter().any(|f| f.name == "Trash");
+    let is_trash = |folder: &str| folder == "Trash" || trash_mailbox.as_deref() == Some(folder);
+
+    let mut to_trash = Vec::new();
+    let mut expunge = Vec::new();
+    let mut removed = Vec::new();
+    for email in crate::pending_ops::load_local(&pool, account_id, email_ids).await? {
+        match email.location {
+            Some(_) if has_trash && !is_trash(&email.folder) => to_trash.push(email.id),
+            Some(location) => {
+                removed.push(email.id);
+                expunge.push(location);
+            }
+            // Local-only rows (unsynced drafts) have nothing to delete on the server
+            None => removed.push(email.id),
+        }
+    }
+
+    let mut deleted = 0;
+    if !to_trash.is_empty() {
+        deleted += transfer_emails(app, account_id, &to_trash, "Trash", true).await?;
+    }
+    crate::imap::delete_local_emails(&pool, &removed).await?;
+    deleted += removed.len();
+    if !expunge.is_empty() {
+        crate::pending_ops::enqueue(app, account_id, &Operation::Expunge { messages: expunge }).await?;
+    }
+
+    let _ = app.emit("emails-deleted", EmailsDeletedPayload {
+        account_id: account_id.to_string(),
+        moved_to_trash: to_trash,
+        deleted_permanently: removed,
+        no_trash: !has_trash,
+    });
+    Ok(deleted)
+}
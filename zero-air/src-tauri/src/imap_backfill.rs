@@ -0,0 +1,229 @@
+/// Full-history backfill — walks each synced folder from newest to oldest in
+/// bounded UID batches. The cursor (`folder_sync_state.lowest_uid`) is persisted
+/// after every batch, so an interrupted backfill resumes where it stopped.
+/// At shutdown the walk stops after the batch in progress.
+use tauri::{AppHandle, Emitter, Manager};
+use crate::db::DbState;
+use crate::imap_session::SessionLease;
+use std::sync::Mutex;
+use tokio::time::{sleep, Duration};
+
+/// Messages downloaded per UID FETCH
+const BACKFILL_BATCH: usize = 100;
+
+#[derive(Clone, serde::Serialize)]
+pub struct BackfillProgressPayload {
+    pub account_id: String,
+    pub folder: String,
+    pub fetched: usize,
+    pub remaining: usize,
+    pub status: String, // "running" | "done" | "error"
+}
+
+/// Accounts with a backfill in flight
+static BACKFILL_RUNNING: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Resume unfinished backfills for every account shortly after startup
+pub fn start_backfill_task(app: AppHandle) {
+    let token = crate::tasks::token();
+    crate::tasks::spawn("backfill", async move {
+        tokio::select! {
+            _ = sleep(Duration::from_secs(10)) => {}
+            _ = token.cancelled() => return,
+        }
+
+        let state = app.state::<DbState>();
+        let account_ids = sqlx::query_as::<_, (String,)>("SELECT id FROM accounts")
+            .fetch_all(&state.pool)
+            .await
+            .unwrap_or_default();
+
+        for (account_id,) in account_ids {
+            run_backfill(&app, &account_id).await;
+        }
+    });
+}
+
+/// Start a backfill for one account unless one is already running
+pub fn spawn_backfill(app: AppHandle, account_id: String) {
+    crate::tasks::spawn("backfill", async move {
+        run_backfill(&app, &account_id).await;
+    });
+}
+
+/// Tauri command: start (or resume) the history backfill for an account
+#[tauri::command]
+pub async fn start_backfill(app: AppHandle, account_id: String) -> Result<(), String> {
+    spawn_backfill(app, account_id);
+    Ok(())
+}
+
+pub async fn run_backfill(app: &AppHandle, account_id: &str) {
+    {
+        let mut running = BACKFILL_RUNNING.lock().unwrap();
+        if running.iter().any(|a| a == account_id) {
+            return;
+        }
+        running.push(account_id.to_string());
+    }
+
+    let state = app.state::<DbState>();
+
+    let folders = sqlx::query_as::<_, (String, i64, i64)>(
+        r#"SELECT folder, uid_validity, COALESCE(lowest_uid, highest_uid + 1)
+           FROM folder_sync_state
+           WHERE account_id = $1 AND backfill_done = 0 AND highest_uid > 0"#
+    )
+    .bind(account_id)
+    .fetch_all(&state.pool)
+    .await
+    .unwrap_or_default();
+
+    // Threads are rebuilt once at the end, not after every batch
+    let rethread = !folders.is_empty();
+    for (folder, uid_validity, lowest_uid) in folders {
+        if crate::tasks::is_shutting_down() {
+            break;
+        }
+        if let Err(e) = backfill_folder(app, account_id, &folder, uid_validity as u32, lowest_uid as u32).await {
+            log::warn!("[BACKFILL] {} / {}: {}", account_id, folder, e);
+            let _ = app.emit("backfill-progress", BackfillProgressPayload {
+                account_id: account_id.to_string(),
+                folder: folder.clone(),
+                fetched: 0,
+                remaining: 0,
+                status: "error".to_string(),
+            });
+        }
+    }
+
+    if rethread {
+        if let Err(e) = crate::threads::thread_account(&state.pool, account_id).await {
+            log::warn!("[BACKFILL] {}: rethreading failed: {}", account_id, e);
+        }
+    }
+
+    BACKFILL_RUNNING.lock().unwrap().retain(|a| a != account_id);
+}
+
+async fn backfill_folder(app: &AppHandle, account_id: &str, folder: &str, uid_validity: u32, below_uid: u32) -> Result<(), String> {
+    let state = app.state::<DbState>();
+    let pool = state.pool.clone();
+
+    let account = sqlx::query_as::<_, crate::db::Account>(
+        "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port, imap_security, smtp_security, ca_cert, tls_pinning, auth_method FROM accounts WHERE id = $1"
+    )
+    .bind(account_id)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?
+    .ok_or_else(|| "Account not found".to_string())?;
+
+    let mailbox_name = crate::imap_folders::resolve_mailbox(&pool, &account, folder).await?;
+
+    log::info!("[BACKFILL] Walking {} below UID {} for {}", folder, below_uid, account.email);
+
+    let mut session = crate::imap_session::acquire(&pool, &account).await?;
+    let result = walk_folder(app, &mut session, &mailbox_name, account_id, folder, uid_validity, below_uid).await;
+    let (fetched, complete) = session.finish(result)?;
+    if !complete {
+        log::info!("[BACKFILL] {} paused after {} older emails", folder, fetched);
+        return Ok(());
+    }
+
+    sqlx::query(
+        "UPDATE folder_sync_state SET backfill_done = 1 WHERE account_id = $1 AND folder = $2 AND uid_validity = $3"
+    )
+    .bind(account_id)
+    .bind(folder)
+    .bind(uid_validity as i64)
+    .execute(&pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+
+    log::info!("[BACKFILL] ✅ {} complete ({} older emails)", folder, fetched);
+    let _ = app.emit("backfill-progress", BackfillProgressPayload {
+        account_id: account_id.to_string(),
+        folder: folder.to_string(),
+        fetched,
+        remaining: 0,
+        status: "done".to_string(),
+    });
+
+    Ok(())
+}
+
+/// Download every message below `below_uid`, newest first, storing each batch and
+/// advancing the cursor as it goes. Returns how many messages were fetched and
+/// whether the walk reached the oldest one (false when stopped by shutdown).
+async fn walk_folder(
+    app: &AppHandle,
+    session: &mut SessionLease,
+    mailbox_name: &str,
+    account_id: &str,
+    folder: &str,
+    uid_validity: u32,
+    below_uid: u32,
+) -> Result<(usize, bool), String> {
+    let pool = app.state::<DbState>().pool.clone();
+    let capabilities = session.capabilities.clone();
+    let mailbox = crate::imap::select_mailbox(session, mailbox_name).await?;
+
+    // The next regular sync resets the folder; backfilling stale UIDs would be wrong
+    if mailbox.uid_validity != Some(uid_validity) {
+        return Err("UIDVALIDITY changed, waiting for the next sync".to_string());
+    }
+
+    let mut uids: Vec<u32> = if below_uid > 1 {
+        session.uid_search(format!("UID 1:{}", below_uid - 1))
+            .await
+            .map_err(|e| format!("Search error: {}", e))?
+            .into_iter()
+            .filter(|u| *u < below_uid)
+            .collect()
+    } else {
+        vec![]
+    };
+    // Newest first
+    uids.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut fetched = 0usize;
+    let mut remaining = uids.len();
+    for chunk in uids.chunks(BACKFILL_BATCH) {
+        if crate::tasks::is_shutting_down() {
+            return Ok((fetched, false));
+        }
+        let _job = crate::tasks::begin_job()?;
+        let messages = crate::imap::uid_fetch_all(session, &crate::imap::format_uid_set(chunk), crate::imap::LIST_FETCH_QUERY).await?;
+        let mut emails: Vec<serde_json::Value> = messages.iter()
+            .map(|msg| crate::imap::parse_fetched_message(msg, account_id, folder))
+            .collect();
+        crate::gmail::annotate(session, &capabilities, &mut emails).await?;
+
+        crate::imap::store_emails(&pool, account_id, folder, &emails).await?;
+        fetched += emails.len();
+        remaining -= chunk.len();
+
+        // Persist the cursor after every batch so a restart resumes here
+        sqlx::query(
+            "UPDATE folder_sync_state SET lowest_uid = $1 WHERE account_id = $2 AND folder = $3 AND uid_validity = $4"
+        )
+        .bind(chunk.iter().copied().min().unwrap_or(1) as i64)
+        .bind(account_id)
+        .bind(folder)
+        .bind(uid_validity as i64)
+        .execute(&pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+
+        let _ = app.emit("backfill-progress", BackfillProgressPayload {
+            account_id: account_id.to_string(),
+            folder: folder.to_string(),
+            fetched,
+            remaining,
+            status: "running".to_string(),
+        });
+    }
+
+    Ok((fetched, true))
+}
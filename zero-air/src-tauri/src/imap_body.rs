@@ -0,0 +1,367 @@
+/// Lazy body fetching — list sync only stores ENVELOPE/FLAGS/SIZE/BODYSTRUCTURE.
+/// Bodies (and single MIME parts) are downloaded when the user opens a message
+/// and cached in SQLite (`emails.body`, `email_parts`). `cid:` images in HTML bodies
+/// are pointed at the `mailpart` scheme (see inline_images).
+use tauri::{AppHandle, Manager};
+use crate::db::DbState;
+use async_imap::imap_proto::types::{BodyParams, BodyStructure, ContentEncoding, SectionPath};
+use mailparse::parse_mail;
+use serde::{Serialize, Deserialize};
+
+/// One leaf of a message's BODYSTRUCTURE, addressed by its IMAP section path
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MimePart {
+    pub path: String, // "1", "1.2", ...
+    pub mime_type: String,
+    pub charset: Option<String>,
+    pub encoding: Option<String>,
+    pub size: Option<u32>,
+    pub disposition: Option<String>,
+    pub filename: Option<String>,
+    pub content_id: Option<String>,
+}
+
+impl MimePart {
+    pub fn is_attachment(&self) -> bool {
+        self.disposition.as_deref() == Some("attachment")
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EmailPart {
+    pub part: String,
+    pub mime_type: String,
+    pub filename: Option<String>,
+    pub size: usize,
+    /// Decoded content for text/* parts
+    pub text: Option<String>,
+}
+
+/// Flatten a BODYSTRUCTURE into its leaf parts. Encapsulated message/rfc822
+/// parts are kept as a single leaf.
+pub fn flatten_bodystructure(bs: &BodyStructure) -> Vec<MimePart> {
+    let mut parts = Vec::new();
+    collect_parts(bs, "", &mut parts);
+    parts
+}
+
+fn collect_parts(bs: &BodyStructure, path: &str, out: &mut Vec<MimePart>) {
+    match bs {
+        BodyStructure::Multipart { bodies, .. } => {
+            for (i, body) in bodies.iter().enumerate() {
+                let child = if path.is_empty() { (i + 1).to_string() } else { format!("{}.{}", path, i + 1) };
+                collect_parts(body, &child, out);
+            }
+        }
+        BodyStructure::Basic { common, other, .. }
+        | BodyStructure::Text { common, other, .. }
+        | BodyStructure::Message { common, other, .. } => {
+            let param = |params: &BodyParams, key: &str| {
+                params.as_ref()
+                    .and_then(|ps| ps.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)))
+                    .map(|(_, v)| v.to_string())
+            };
+            let disposition = common.disposition.as_ref();
+            out.push(MimePart {
+                // A non-multipart message has a single part "1"
+                path: if path.is_empty() { "1".to_string() } else { path.to_string() },
+                mime_type: format!("{}/{}", common.ty.ty, common.ty.subtype).to_lowercase(),
+                charset: param(&common.ty.params, "charset"),
+                encoding: Some(match &other.transfer_encoding {
+                    ContentEncoding::SevenBit => "7bit".to_string(),
+                    ContentEncoding::EightBit => "8bit".to_string(),
+                    ContentEncoding::Binary => "binary".to_string(),
+                    ContentEncoding::Base64 => "base64".to_string(),
+                    ContentEncoding::QuotedPrintable => "quoted-printable".to_string(),
+                    ContentEncoding::Other(e) => e.to_lowercase(),
+                }),
+                size: Some(other.octets),
+                disposition: disposition.map(|d| d.ty.to_lowercase()),
+                filename: disposition
+                    .and_then(|d| decoded_param(&d.params, "filename"))
+                    .or_else(|| decoded_param(&common.ty.params, "name")),
+                content_id: other.id.as_ref().map(|id| id.trim_matches(|c| c == '<' || c == '>').to_string()),
+            });
+        }
+    }
+}
+
+/// A filename parameter, decoded from RFC 2231/2047
+fn decoded_param(params: &BodyParams, key: &str) -> Option<String> {
+    let params = params.as_ref()?;
+    crate::mime_header::decode_param(params.iter().map(|(k, v)| (k.as_ref(), v.as_ref())), key)
+}
+
+/// Wrap a raw (still transfer-encoded) part in minimal MIME headers so mailparse can decode it
+fn wrap_part(raw: &[u8], part: &MimePart) -> Vec<u8> {
+    let charset = part.charset.as_ref().map(|c| format!("; charset=\"{}\"", c)).unwrap_or_default();
+    let mut wrapped = format!(
+        "Content-Type: {}{}\r\nContent-Transfer-Encoding: {}\r\n\r\n",
+        part.mime_type, charset, part.encoding.as_deref().unwrap_or("7bit")
+    ).into_bytes();
+    wrapped.extend_from_slice(raw);
+    wrapped
+}
+
+/// Undo the Content-Transfer-Encoding of a raw part
+pub fn decode_part(raw: &[u8], part: &MimePart) -> Vec<u8> {
+    let wrapped = wrap_part(raw, part);
+    match parse_mail(&wrapped) {
+        Ok(parsed) => parsed.get_body_raw().unwrap_or_else(|_| raw.to_vec()),
+        Err(_) => raw.to_vec(),
+    }
+}
+
+/// Decode a raw text part to a String, honouring its charset
+pub fn decode_text_part(raw: &[u8], part: &MimePart) -> String {
+    let wrapped = wrap_part(raw, part);
+    match parse_mail(&wrapped) {
+        Ok(parsed) => parsed.get_body().unwrap_or_else(|_| String::from_utf8_lossy(raw).to_string()),
+        Err(_) => String::from_utf8_lossy(raw).to_string(),
+    }
+}
+
+fn section_path(path: &str) -> SectionPath {
+    SectionPath::Part(path.split('.').filter_map(|n| n.parse().ok()).collect(), None)
+}
+
+/// Pick the first inline text/html and text/plain leaves
+pub fn displayable_parts(parts: &[MimePart]) -> (Option<MimePart>, Option<MimePart>) {
+    let find = |mime: &str| parts.iter().find(|p| p.mime_type == mime && !p.is_attachment()).cloned();
+    (find("text/html"), find("text/plain"))
+}
+
+/// Location of a message on the server plus its cached structure
+pub struct StoredMessage {
+    pub account: crate::db::Account,
+    pub folder: String,
+    pub mailbox: String, // IMAP mailbox name
+    pub uid: u32,
+    pub parts: Vec<MimePart>,
+}
+
+pub async fn load_stored_message(app: &AppHandle, email_id: &str) -> Result<StoredMessage, String> {
+    let state = app.state::<DbState>();
+
+    let (account_id, folder, uid, body_structure) = sqlx::query_as::<_, (String, String, i64, Option<String>)>(
+        "SELECT account_id, folder, uid, body_structure FROM emails WHERE id = $1"
+    )
+    .bind(email_id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?
+    .ok_or("Email not found")?;
+
+    let account = sqlx::query_as::<_, crate::db::Account>(
+        "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port, imap_security, smtp_security, ca_cert, tls_pinning, auth_method FROM accounts WHERE id = $1"
+    )
+    .bind(&account_id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?
+    .ok_or("Account not found")?;
+
+    let parts = body_structure
+        .and_then(|s| serde_json::from_str::<Vec<MimePart>>(&s).ok())
+        .unwrap_or_default();
+
+    let mailbox = crate::imap_folders::resolve_mailbox(&state.pool, &account, &folder).await?;
+
+    Ok(StoredMessage { account, folder, mailbox, uid: uid as u32, parts })
+}
+
+/// Download the HTML and plain text bodies of a message: just those sections when
+/// the structure is known, the whole message (and its structure) otherwise.
+/// Returns the bodies and the structure if it had to be fetched.
+async fn fetch_displayable_bodies(
+    session: &mut crate::imap::ImapSession,
+    mailbox: &str,
+    uid: u32,
+    html_part: &Option<MimePart>,
+    plain_part: &Option<MimePart>,
+) -> Result<(String, String, Vec<MimePart>), String> {
+    crate::imap::select_mailbox(session, mailbox).await?;
+    let uid = uid.to_string();
+
+    if html_part.is_none() && plain_part.is_none() {
+        // Unknown structure: take the whole message
+        let messages = crate::imap::uid_fetch_all(session, &uid, "(UID BODYSTRUCTURE BODY.PEEK[])").await?;
+        let msg = messages.first();
+        let (html, plain) = msg.and_then(|m| m.body()).map(crate::imap::extract_bodies).unwrap_or_default();
+        let parts = msg.and_then(|m| m.bodystructure()).map(flatten_bodystructure).unwrap_or_default();
+        return Ok((html, plain, parts));
+    }
+
+    let sections: Vec<String> = [html_part, plain_part].iter()
+        .filter_map(|p| p.as_ref().map(|p| format!("BODY.PEEK[{}]", p.path)))
+        .collect();
+    let messages = crate::imap::uid_fetch_all(session, &uid, &format!("(UID {})", sections.join(" "))).await?;
+    let msg = messages.first().ok_or("Message no longer on the server")?;
+    let decode = |part: &Option<MimePart>| part.as_ref()
+        .and_then(|p| msg.section(&section_path(&p.path)).map(|raw| decode_text_part(raw, p)))
+        .unwrap_or_default();
+    Ok((decode(html_part), decode(plain_part), vec![]))
+}
+
+/// Make sure `emails.body` and `emails.snippet` are filled in, fetching only the
+/// displayable text parts from the server when possible. Returns the body.
+pub async fn ensure_body_cached(app: &AppHandle, email_id: &str) -> Result<String, String> {
+    let state = app.state::<DbState>();
+
+    let (account_id, message_id, body, cached) = sqlx::query_as::<_, (String, Option<String>, Option<String>, bool)>(
+        "SELECT account_id, message_id, body, COALESCE(body_cached, 0) FROM emails WHERE id = $1"
+    )
+    .bind(email_id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?
+    .ok_or("Email not found")?;
+    if let Some(body) = body {
+        if cached || !body.is_empty() {
+            return resolve_cached_cid_urls(app, email_id, body).await;
+        }
+    }
+
+    // Another copy of the same Gmail message may have been opened already
+    crate::gmail::inherit_from_copies(&state.pool, email_id).await?;
+    let inherited: Option<String> = sqlx::query_scalar("SELECT body FROM emails WHERE id = $1 AND body_cached = 1")
+        .bind(email_id)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?
+        .flatten();
+    if let Some(body) = inherited {
+        return resolve_cached_cid_urls(app, email_id, body).await;
+    }
+
+    let stored = load_stored_message(app, email_id).await?;
+    let (html_part, plain_part) = displayable_parts(&stored.parts);
+
+    let mut session = crate::imap_session::acquire(&state.pool, &stored.account).await?;
+    let bodies = fetch_displayable_bodies(&mut session, &stored.mailbox, stored.uid, &html_part, &plain_part).await;
+    let (body_html, body_plain, fetched_parts) = session.finish(bodies)?;
+
+    let parts = if fetched_parts.is_empty() {
+        stored.parts
+    } else {
+        sqlx::query("UPDATE emails SET body_structure = $1 WHERE id = $2")
+            .bind(serde_json::to_string(&fetched_parts).unwrap_or_default())
+            .bind(email_id)
+            .execute(&state.pool)
+            .await
+            .map_err(|e| format!("DB error: {}", e))?;
+        crate::attachments::index_attachments(&state.pool, email_id, &fetched_parts).await?;
+        fetched_parts
+    };
+    let owner = crate::inline_images::ImageOwner { account_id: &account_id, message_id: message_id.as_deref(), email_id };
+    let body_html = crate::inline_images::resolve_cid_urls(&body_html, &owner, &parts);
+
+    let body = if !body_html.is_empty() { body_html.clone() } else { body_plain.clone() };
+    let snippet_source = if !body_plain.is_empty() { body_plain } else { crate::imap::strip_html_tags(&body_html) };
+    let snippet: String = snippet_source.chars().take(150).collect();
+
+    sqlx::query("UPDATE emails SET body = $1, snippet = $2, body_cached = 1 WHERE id = $3")
+        .bind(&body)
+        .bind(&snippet)
+        .bind(email_id)
+        .execute(&state.pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+
+    Ok(body)
+}
+
+/// Bodies cached before inline images were resolved still have `cid:` URLs; rewrite them once
+async fn resolve_cached_cid_urls(app: &AppHandle, email_id: &str, body: String) -> Result<String, String> {
+    if !body.to_ascii_lowercase().contains("cid:") {
+        return Ok(body);
+    }
+    let state = app.state::<DbState>();
+    let Some((account_id, message_id, body_structure)) = sqlx::query_as::<_, (String, Option<String>, Option<String>)>(
+        "SELECT account_id, message_id, body_structure FROM emails WHERE id = $1"
+    )
+    .bind(email_id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?
+    else {
+        return Ok(body);
+    };
+    let parts: Vec<MimePart> = body_structure.and_then(|s| serde_json::from_str(&s).ok()).unwrap_or_default();
+    let owner = crate::inline_images::ImageOwner { account_id: &account_id, message_id: message_id.as_deref(), email_id };
+    let resolved = crate::inline_images::resolve_cid_urls(&body, &owner, &parts);
+    if resolved != body {
+        sqlx::query("UPDATE emails SET body = $1 WHERE id = $2")
+            .bind(&resolved)
+            .bind(email_id)
+            .execute(&state.pool)
+            .await
+            .map_err(|e| format!("DB error: {}", e))?;
+    }
+    Ok(resolved)
+}
+
+/// Return the decoded bytes of one MIME part, from the cache or the server
+pub async fn load_part_bytes(app: &AppHandle, email_id: &str, part_path: &str) -> Result<(MimePart, Vec<u8>), String> {
+    let state = app.state::<DbState>();
+    let stored = load_stored_message(app, email_id).await?;
+    let part = stored.parts.iter()
+        .find(|p| p.path == part_path)
+        .cloned()
+        .ok_or_else(|| format!("Unknown MIME part: {}", part_path))?;
+
+    let cached = sqlx::query_as::<_, (Vec<u8>,)>(
+        "SELECT data FROM email_parts WHERE email_id = $1 AND part_path = $2"
+    )
+    .bind(email_id)
+    .bind(part_path)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+    if let Some((data,)) = cached {
+        return Ok((part, data));
+    }
+
+    let mut session = crate::imap_session::acquire(&state.pool, &stored.account).await?;
+    let result = async {
+        crate::imap::select_mailbox(&mut session, &stored.mailbox).await?;
+        let messages = crate::imap::uid_fetch_all(&mut session, &stored.uid.to_string(), &format!("(UID BODY.PEEK[{}])", part.path)).await?;
+        messages.first()
+            .and_then(|m| m.section(&section_path(&part.path)).map(|raw| decode_part(raw, &part)))
+            .ok_or_else(|| "MIME part not returned by the server".to_string())
+    }
+    .await;
+    let data = session.finish(result)?;
+
+    sqlx::query("INSERT OR REPLACE INTO email_parts (email_id, part_path, data) VALUES ($1, $2, $3)")
+        .bind(email_id)
+        .bind(part_path)
+        .bind(&data)
+        .execute(&state.pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+
+    Ok((part, data))
+}
+
+/// Tauri command: fetch (or read from cache) the displayable body of an email
+#[tauri::command]
+pub async fn fetch_email_body(app: AppHandle, email_id: String) -> Result<String, String> {
+    ensure_body_cached(&app, &email_id).await
+}
+
+/// Tauri command: fetch (or read from cache) a single MIME part of an email
+#[tauri::command]
+pub async fn fetch_email_part(app: AppHandle, email_id: String, part: String) -> Result<EmailPart, String> {
+    let (info, data) = load_part_bytes(&app, &email_id, &part).await?;
+    // Cached bytes are already transfer-decoded; only the charset is left to apply
+    let text = info.mime_type.starts_with("text/")
+        .then(|| decode_text_part(&data, &MimePart { encoding: Some("8bit".to_string()), ..info.clone() }));
+    Ok(EmailPart {
+        part: info.path,
+        mime_type: info.mime_type,
+        filename: info.filename,
+        size: data.len(),
+        text,
+    })
+}
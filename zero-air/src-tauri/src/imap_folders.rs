@@ -0,0 +1,579 @@
+/// Folder discovery — LIST/LSUB with RFC 6154 SPECIAL-USE attributes.
+/// The folder tree is cached per account in `folders`, and every role the UI
+/// knows (Drafts, Sent, Trash, Junk, Archive, All) is mapped to a real mailbox.
+use tauri::{AppHandle, Manager};
+use crate::db::{Account, DbState};
+use sqlx::SqlitePool;
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use async_imap::types::{Name, NameAttribute};
+use futures::TryStreamExt;
+use crate::imap::ImapSession;
+
+/// Folder roles, named the way the UI names its folders
+pub const FOLDER_ROLES: [&str; 6] = ["Drafts", "Sent", "Trash", "Junk", "Archive", "All"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct Folder {
+    pub account_id: String,
+    pub name: String, // raw IMAP mailbox name
+    pub delimiter: Option<String>,
+    pub parent: Option<String>,
+    pub display_name: String, // decoded last path segment
+    pub role: Option<String>,
+    pub attributes: String, // space-separated LIST attributes
+    pub selectable: bool,
+    pub subscribed: bool,
+}
+
+/// Encode a mailbox name as modified UTF-7 (RFC 3501 §5.1.3)
+pub fn encode_mailbox_name(name: &str) -> String {
+    fn flush(pending: &mut Vec<u16>, out: &mut String) {
+        if pending.is_empty() {
+            return;
+        }
+        let bytes: Vec<u8> = pending.iter().flat_map(|u| u.to_be_bytes()).collect();
+        out.push('&');
+        out.push_str(&STANDARD_NO_PAD.encode(bytes).replace('/', ","));
+        out.push('-');
+        pending.clear();
+    }
+
+    let mut out = String::new();
+    let mut pending: Vec<u16> = Vec::new();
+    for c in name.chars() {
+        if (' '..='~').contains(&c) {
+            flush(&mut pending, &mut out);
+            if c == '&' { out.push_str("&-") } else { out.push(c) }
+        } else {
+            let mut buf = [0u16; 2];
+            pending.extend_from_slice(c.encode_utf16(&mut buf));
+        }
+    }
+    flush(&mut pending, &mut out);
+    out
+}
+
+/// Decode a modified UTF-7 mailbox name. Malformed shift sequences are kept as-is.
+pub fn decode_mailbox_name(name: &str) -> String {
+    let mut out = String::new();
+    let mut rest = name;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('-') else {
+            out.push_str(&rest[start..]);
+            return out;
+        };
+        let encoded = &after[..end];
+        if encoded.is_empty() {
+            out.push('&');
+        } else {
+            match STANDARD_NO_PAD.decode(encoded.replace(',', "/")) {
+                Ok(bytes) if bytes.len() % 2 == 0 => {
+                    let units: Vec<u16> = bytes.chunks(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+                    out.push_str(&String::from_utf16_lossy(&units));
+                }
+                _ => out.push_str(&rest[start..start + end + 2]),
+            }
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Role from an RFC 6154 attribute such as `\Sent`
+fn special_use_role(attribute: &str) -> Option<&'static str> {
+    match attribute.to_ascii_lowercase().as_str() {
+        "\\drafts" => Some("Drafts"),
+        "\\sent" => Some("Sent"),
+        "\\trash" => Some("Trash"),
+        "\\junk" => Some("Junk"),
+        "\\archive" => Some("Archive"),
+        "\\all" => Some("All"),
+        _ => None,
+    }
+}
+
+/// Role guessed from a well-known mailbox name, for servers without SPECIAL-USE
+fn guessed_role(leaf: &str) -> Option<&'static str> {
+    match leaf.to_ascii_lowercase().as_str() {
+        "drafts" | "draft" => Some("Drafts"),
+        "sent" | "sent mail" | "sent messages" | "sent items" => Some("Sent"),
+        "trash" | "deleted messages" | "deleted items" | "bin" => Some("Trash"),
+        "junk" | "spam" | "junk e-mail" | "bulk mail" => Some("Junk"),
+        "archive" | "archives" => Some("Archive"),
+        "all mail" => Some("All"),
+        _ => None,
+    }
+}
+
+fn attribute_name(attr: &NameAttribute<'_>) -> Option<String> {
+    let name = match attr {
+        NameAttribute::NoInferiors => "\\Noinferiors",
+        NameAttribute::NoSelect => "\\Noselect",
+        NameAttribute::Marked => "\\Marked",
+        NameAttribute::Unmarked => "\\Unmarked",
+        NameAttribute::All => "\\All",
+        NameAttribute::Archive => "\\Archive",
+        NameAttribute::Drafts => "\\Drafts",
+        NameAttribute::Flagged => "\\Flagged",
+        NameAttribute::Junk => "\\Junk",
+        NameAttribute::Sent => "\\Sent",
+        NameAttribute::Trash => "\\Trash",
+        NameAttribute::Extension(s) => s,
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+/// Run LIST/LSUB and build the account's folder tree with roles assigned
+pub async fn list_folders(session: &mut ImapSession, account_id: &str) -> Result<Vec<Folder>, String> {
+    let names: Vec<Name> = session.list(Some(""), Some("*"))
+        .await
+        .map_err(|e| format!("IMAP list error: {}", e))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("IMAP list error: {}", e))?;
+
+    // Not every server implements LSUB; an empty set just means "unknown"
+    let subscribed: HashSet<String> = match session.lsub(Some(""), Some("*")).await {
+        Ok(names) => names
+            .try_collect::<Vec<Name>>()
+            .await
+            .map(|names| names.iter().map(|n| n.name().to_string()).collect())
+            .unwrap_or_default(),
+        Err(_) => HashSet::new(),
+    };
+
+    let mut folders: Vec<Folder> = names.iter().map(|n| {
+        let name = n.name().to_string();
+        let delimiter = n.delimiter().map(|d| d.to_string());
+        let attributes: Vec<String> = n.attributes().iter().filter_map(attribute_name).collect();
+        let (parent, leaf) = match delimiter.as_deref().and_then(|d| name.rsplit_once(d)) {
+            Some((parent, leaf)) => (Some(parent.to_string()), decode_mailbox_name(leaf)),
+            None => (None, decode_mailbox_name(&name)),
+        };
+        let role = if name.eq_ignore_ascii_case("INBOX") {
+            Some("INBOX".to_string())
+        } else {
+            attributes.iter().find_map(|a| special_use_role(a)).map(|r| r.to_string())
+        };
+        Folder {
+            account_id: account_id.to_string(),
+            selectable: !attributes.iter().any(|a| a.eq_ignore_ascii_case("\\Noselect")),
+            subscribed: subscribed.contains(&name),
+            attributes: attributes.join(" "),
+            display_name: leaf,
+            name,
+            delimiter,
+            parent,
+            role,
+        }
+    }).collect();
+
+    // Name heuristics only fill roles SPECIAL-USE left open, one mailbox per role
+    let mut taken: HashSet<String> = folders.iter().filter_map(|f| f.role.clone()).collect();
+    for folder in folders.iter_mut().filter(|f| f.role.is_none() && f.selectable) {
+        if let Some(role) = guessed_role(&folder.display_name) {
+            if taken.insert(role.to_string()) {
+                folder.role = Some(role.to_string());
+            }
+        }
+    }
+
+    Ok(folders)
+}
+
+/// Replace the cached folder tree of an account
+pub async fn store_folders(pool: &SqlitePool, account_id: &str, folders: &[Folder]) -> Result<(), String> {
+    sqlx::query("DELETE FROM folders WHERE account_id = $1")
+        .bind(account_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+
+    for f in folders {
+        sqlx::query(
+            r#"INSERT OR REPLACE INTO folders (account_id, name, delimiter, parent, display_name, role, attributes, selectable, subscribed)
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#
+        )
+        .bind(&f.account_id)
+        .bind(&f.name)
+        .bind(&f.delimiter)
+        .bind(&f.parent)
+        .bind(&f.display_name)
+        .bind(&f.role)
+        .bind(&f.attributes)
+        .bind(f.selectable)
+        .bind(f.subscribed)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    }
+    Ok(())
+}
+
+pub async fn load_folders(pool: &SqlitePool, account_id: &str) -> Result<Vec<Folder>, String> {
+    sqlx::query_as::<_, Folder>(
+        "SELECT account_id, name, delimiter, parent, display_name, role, attributes, selectable, subscribed FROM folders WHERE account_id = $1 ORDER BY name"
+    )
+    .bind(account_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))
+}
+
+/// LIST the server's folders and cache them
+pub async fn refresh_folders(pool: &SqlitePool, account: &Account) -> Result<Vec<Folder>, String> {
+    let mut session = crate::imap_session::acquire(pool, account).await?;
+    let folders = list_folders(&mut session, &account.id).await;
+    let folders = session.finish(folders)?;
+
+    store_folders(pool, &account.id, &folders).await?;
+    log::info!("[FOLDERS] Discovered {} folders for {}", folders.len(), account.email);
+    Ok(folders)
+}
+
+/// Mailbox holding a role. Archive falls back to the All-mail folder (Gmail).
+pub fn mailbox_for_role(folders: &[Folder], role: &str) -> Option<String> {
+    let find = |r: &str| folders.iter().find(|f| f.role.as_deref() == Some(r)).map(|f| f.name.clone());
+    find(role).or_else(|| if role == "Archive" { find("All") } else { None })
+}
+
+/// Resolve a UI folder name — a role such as "Sent" or a raw mailbox name —
+/// to the IMAP mailbox to select. Discovers folders on first use.
+pub async fn resolve_mailbox(pool: &SqlitePool, account: &Account, folder: &str) -> Result<String, String> {
+    if folder.eq_ignore_ascii_case("INBOX") {
+        return Ok("INBOX".to_string());
+    }
+
+    let mut folders = load_folders(pool, &account.id).await?;
+    let mut refreshed = false;
+    if folders.is_empty() {
+        folders = refresh_folders(pool, account).await?;
+        refreshed = true;
+    }
+
+    if FOLDER_ROLES.contains(&folder) {
+        if let Some(name) = mailbox_for_role(&folders, folder) {
+            return Ok(name);
+        }
+        // The role may have been created since the last LIST
+        if !refreshed {
+            folders = refresh_folders(pool, account).await?;
+            if let Some(name) = mailbox_for_role(&folders, folder) {
+                return Ok(name);
+            }
+        }
+    }
+
+    // Raw mailbox name (or a role the server doesn't have — let the caller fail or create it)
+    Ok(folder.to_string())
+}
+
+/// Tauri command: folder tree of an account, optionally re-listed from the server
+#[tauri::command]
+pub async fn get_folders(app: AppHandle, account_id: String, refresh: Option<bool>) -> Result<Vec<Folder>, String> {
+    let state = app.state::<DbState>();
+
+    let folders = load_folders(&state.pool, &account_id).await?;
+    if !folders.is_empty() && !refresh.unwrap_or(false) {
+        return Ok(folders);
+    }
+
+    let account = load_account(&state.pool, &account_id).await?;
+    refresh_folders(&state.pool, &account).await
+}
+
+pub async fn load_account(pool: &SqlitePool, account_id: &str) -> Result<Account, String> {
+    sqlx::query_as::<_, Account>(
+        "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port, imap_security, smtp_security, ca_cert, tls_pinning, auth_method FROM accounts WHERE id = $1"
+    )
+    .bind(account_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?
+    .ok_or_else(|| "Account not found".to_string())
+}
+
+/// The folder and its descendants, by raw mailbox name
+fn folder_subtree<'a>(folders: &'a [Folder], folder: &Folder) -> Vec<&'a Folder> {
+    folders.iter().filter(|f| {
+        f.name == folder.name || folder.delimiter.as_deref()
+            .is_some_and(|d| f.name.starts_with(&format!("{}{}", folder.name, d)))
+    }).collect()
+}
+
+/// Value of `emails.folder` for a mailbox: role folders are stored under the role name
+pub fn local_folder_key(folders: &[Folder], folder: &Folder) -> String {
+    match &folder.role {
+        Some(role) if mailbox_for_role(folders, role).as_deref() == Some(folder.name.as_str()) => role.clone(),
+        _ => folder.name.clone(),
+    }
+}
+
+/// Value of `emails.folder` for a UI folder name — a role or a raw mailbox name
+pub async fn folder_key(pool: &SqlitePool, account_id: &str, folder: &str) -> Result<String, String> {
+    if folder.eq_ignore_ascii_case("INBOX") {
+        return Ok("INBOX".to_string());
+    }
+    let folders = load_folders(pool, account_id).await?;
+    // A role may stand for another role's mailbox, e.g. Archive for All Mail
+    let name = FOLDER_ROLES
+        .contains(&folder)
+        .then(|| mailbox_for_role(&folders, folder))
+        .flatten()
+        .unwrap_or_else(|| folder.to_string());
+    Ok(folders
+        .iter()
+        .find(|f| f.name == name)
+        .map_or_else(|| folder.to_string(), |f| local_folder_key(&folders, f)))
+}
+
+/// Re-home local rows (and their ids, which embed the folder) after a rename
+async fn relocate_local_emails(pool: &SqlitePool, account_id: &str, old_key: &str, new_key: &str) -> Result<(), String> {
+    let rows = sqlx::query_as::<_, (String, i64)>(
+        "SELECT id, uid FROM emails WHERE account_id = $1 AND folder = $2"
+    )
+    .bind(account_id)
+    .bind(old_key)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+
+    for (id, uid) in rows {
+        // Local drafts keep their own ids
+        let new_id = if id == crate::imap::local_email_id(account_id, old_key, uid as u32) {
+            crate::imap::local_email_id(account_id, new_key, uid as u32)
+        } else {
+            id.clone()
+        };
+        crate::imap::rekey_local_email(pool, &id, &new_id, new_key, uid as u32).await?;
+    }
+
+    sqlx::query("UPDATE folder_sync_state SET folder = $1 WHERE account_id = $2 AND folder = $3")
+        .bind(new_key)
+        .bind(account_id)
+        .bind(old_key)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    Ok(())
+}
+
+/// Drop every local row of a deleted folder
+async fn remove_local_emails(pool: &SqlitePool, account_id: &str, key: &str) -> Result<(), String> {
+    let ids: Vec<String> = sqlx::query_as::<_, (String,)>(
+        "SELECT id FROM emails WHERE account_id = $1 AND folder = $2"
+    )
+    .bind(account_id)
+    .bind(key)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?
+    .into_iter()
+    .map(|(id,)| id)
+    .collect();
+
+    crate::imap::delete_local_emails(pool, &ids).await?;
+
+    sqlx::query("DELETE FROM folder_sync_state WHERE account_id = $1 AND folder = $2")
+        .bind(account_id)
+        .bind(key)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    Ok(())
+}
+
+fn find_folder(folders: &[Folder], name: &str) -> Result<Folder, String> {
+    folders.iter()
+        .find(|f| f.name == name)
+        .cloned()
+        .ok_or_else(|| format!("Folder not found: {}", name))
+}
+
+/// Tauri command: create a folder (`name` is plain UTF-8), optionally under `parent`
+/// (a raw mailbox name), and subscribe to it
+#[tauri::command]
+pub async fn create_folder(app: AppHandle, account_id: String, name: String, parent: Option<String>) -> Result<Vec<Folder>, String> {
+    let state = app.state::<DbState>();
+    let account = load_account(&state.pool, &account_id).await?;
+    let folders = load_folders(&state.pool, &account_id).await?;
+
+    let delimiter = match &parent {
+        Some(p) => find_folder(&folders, p)?.delimiter,
+        None => folders.iter().find_map(|f| f.delimiter.clone()),
+    }
+    .unwrap_or_else(|| "/".to_string());
+    if name.is_empty() || name.contains(delimiter.as_str()) {
+        return Err(format!("Invalid folder name: {}", name));
+    }
+
+    let full_name = match &parent {
+        Some(p) => format!("{}{}{}", p, delimiter, encode_mailbox_name(&name)),
+        None => encode_mailbox_name(&name),
+    };
+
+    log::info!("[FOLDERS] Creating {} for {}", full_name, account.email);
+    let mut session = crate::imap_session::acquire(&state.pool, &account).await?;
+    let result = async {
+        session.create(&full_name).await?;
+        session.subscribe(&full_name).await
+    }
+    .await
+    .map_err(|e| format!("IMAP error: {}", e));
+    session.finish(result)?;
+
+    refresh_folders(&state.pool, &account).await
+}
+
+/// Tauri command: rename a folder to a new (plain UTF-8) leaf name, keeping its parent
+#[tauri::command]
+pub async fn rename_folder(app: AppHandle, account_id: String, folder: String, new_name: String) -> Result<Vec<Folder>, String> {
+    let state = app.state::<DbState>();
+    let account = load_account(&state.pool, &account_id).await?;
+    let folders = load_folders(&state.pool, &account_id).await?;
+    let target = find_folder(&folders, &folder)?;
+
+    if target.name.eq_ignore_ascii_case("INBOX") {
+        return Err("INBOX cannot be renamed".to_string());
+    }
+    let delimiter = target.delimiter.clone().unwrap_or_default();
+    if new_name.is_empty() || (!delimiter.is_empty() && new_name.contains(delimiter.as_str())) {
+        return Err(format!("Invalid folder name: {}", new_name));
+    }
+
+    let new_full = match &target.parent {
+        Some(p) => format!("{}{}{}", p, delimiter, encode_mailbox_name(&new_name)),
+        None => encode_mailbox_name(&new_name),
+    };
+
+    // Server renames the whole subtree; subscriptions are by name, so move them too
+    let subtree: Vec<(Folder, String)> = folder_subtree(&folders, &target).into_iter()
+        .map(|f| (f.clone(), format!("{}{}", new_full, &f.name[target.name.len()..])))
+        .collect();
+    let subscribed: Vec<(String, String)> = subtree.iter()
+        .filter(|(f, _)| f.subscribed)
+        .map(|(f, new)| (f.name.clone(), new.clone()))
+        .collect();
+
+    log::info!("[FOLDERS] Renaming {} → {} for {}", target.name, new_full, account.email);
+    let mut session = crate::imap_session::acquire(&state.pool, &account).await?;
+    let result = async {
+        session.rename(&target.name, &new_full).await?;
+        for (old, new) in &subscribed {
+            let _ = session.unsubscribe(old).await;
+            let _ = session.subscribe(new).await;
+        }
+        Ok(())
+    }
+    .await
+    .map_err(|e: async_imap::error::Error| format!("IMAP error: {}", e));
+    session.finish(result)?;
+
+    // Role folders are stored under their role name, which survives the rename
+    for (f, new) in &subtree {
+        let old_key = local_folder_key(&folders, f);
+        if old_key == f.name {
+            relocate_local_emails(&state.pool, &account_id, &old_key, new).await?;
+        }
+    }
+
+    refresh_folders(&state.pool, &account).await
+}
+
+/// Tauri command: delete a folder and its local emails
+#[tauri::command]
+pub async fn delete_folder(app: AppHandle, account_id: String, folder: String) -> Result<Vec<Folder>, String> {
+    let state = app.state::<DbState>();
+    let account = load_account(&state.pool, &account_id).await?;
+    let folders = load_folders(&state.pool, &account_id).await?;
+    let target = find_folder(&folders, &folder)?;
+
+    if target.name.eq_ignore_ascii_case("INBOX") {
+        return Err("INBOX cannot be deleted".to_string());
+    }
+    // Most servers refuse to delete a folder that still has children
+    if folder_subtree(&folders, &target).len() > 1 {
+        return Err(format!("Folder {} has subfolders; delete them first", target.display_name));
+    }
+
+    log::info!("[FOLDERS] Deleting {} for {}", target.name, account.email);
+    let mut session = crate::imap_session::acquire(&state.pool, &account).await?;
+    let result = async {
+        let _ = session.unsubscribe(&target.name).await;
+        session.delete(&target.name).await
+    }
+    .await
+    .map_err(|e| format!("IMAP error: {}", e));
+    session.finish(result)?;
+
+    remove_local_emails(&state.pool, &account_id, &local_folder_key(&folders, &target)).await?;
+    refresh_folders(&state.pool, &account).await
+}
+
+async fn set_subscription(app: &AppHandle, account_id: &str, folder: &str, subscribe: bool) -> Result<(), String> {
+    let state = app.state::<DbState>();
+    let account = load_account(&state.pool, account_id).await?;
+
+    let mut session = crate::imap_session::acquire(&state.pool, &account).await?;
+    let result = if subscribe { session.subscribe(folder).await } else { session.unsubscribe(folder).await };
+    session.finish(result.map_err(|e| format!("IMAP error: {}", e)))?;
+
+    sqlx::query("UPDATE folders SET subscribed = $1 WHERE account_id = $2 AND name = $3")
+        .bind(subscribe)
+        .bind(account_id)
+        .bind(folder)
+        .execute(&state.pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    Ok(())
+}
+
+/// Tauri command: SUBSCRIBE to a folder
+#[tauri::command]
+pub async fn subscribe_folder(app: AppHandle, account_id: String, folder: String) -> Result<(), String> {
+    set_subscription(&app, &account_id, &folder, true).await
+}
+
+/// Tauri command: UNSUBSCRIBE from a folder
+#[tauri::command]
+pub async fn unsubscribe_folder(app: AppHandle, account_id: String, folder: String) -> Result<(), String> {
+    set_subscription(&app, &account_id, &folder, false).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(decoded: &str, encoded: &str) {
+        assert_eq!(encode_mailbox_name(decoded), encoded);
+        assert_eq!(decode_mailbox_name(encoded), decoded);
+    }
+
+    #[test]
+    fn round_trips_mailbox_names() {
+        round_trip("INBOX", "INBOX");
+        round_trip("Entwürfe", "Entw&APw-rfe");
+        round_trip("&", "&-");
+        round_trip("Tom & Jerry", "Tom &- Jerry");
+        // RFC 3501 §5.1.3
+        round_trip("~peter/mail/台北/日本語", "~peter/mail/&U,BTFw-/&ZeVnLIqe-");
+        // Outside the BMP: a UTF-16 surrogate pair in one shift sequence
+        round_trip("📧 Inbox", "&2D3c5w- Inbox");
+        round_trip("📧📧", "&2D3c59g93Oc-");
+    }
+
+    #[test]
+    fn keeps_malformed_shift_sequences() {
+        assert_eq!(decode_mailbox_name("A&B"), "A&B");
+        assert_eq!(decode_mailbox_name("&@@@-x"), "&@@@-x");
+        // Three bytes: not a whole UTF-16 unit
+        assert_eq!(decode_mailbox_name("&AAAA-"), "&AAAA-");
+    }
+}
@@ -1,22 +1,77 @@
-/// IMAP IDLE — sync `imap` crate in a dedicated OS thread.
-/// Uses tokio mpsc to signal new-mail events to the Tauri runtime.
+/// IMAP IDLE — a supervisor runs one async-imap IDLE watcher per account on INBOX,
+/// plus the extra folders chosen for the account (`accounts.idle_folders`), each on
+/// its own connection. Watchers are started and stopped as accounts come and go.
+/// Servers without IDLE are polled with NOOP instead, and failed connections are
+/// retried with exponential backoff and jitter. At shutdown every watcher ends its
+/// IDLE with DONE and logs out.
+/// New-mail notifications and watcher status changes are emitted as Tauri events.
 use tauri::{AppHandle, Emitter, Manager};
-use crate::db::DbState;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use crate::db::{Account, DbState};
+use crate::imap::{format_uid_set, local_email_id, FlagUpdate, ImapSession, LIST_FETCH_QUERY};
+use async_imap::extensions::idle::IdleResponse;
+use async_imap::imap_proto::{MailboxDatum, Response};
+use async_imap::types::UnsolicitedResponse;
+use sqlx::SqlitePool;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
+use tokio::sync::Notify;
 use tokio::time::{sleep, Duration};
+use tokio_util::sync::CancellationToken;
+use rand::Rng;
+
+/// Re-issue IDLE before servers drop it (RFC 2177: at least every 29 minutes)
+const IDLE_TIMEOUT: Duration = Duration::from_secs(29 * 60);
+/// How often the supervisor re-reads the accounts on its own
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+/// First reconnect delay; it doubles with every failed attempt up to BACKOFF_MAX
+const BACKOFF_BASE: Duration = Duration::from_secs(5);
+const BACKOFF_MAX: Duration = Duration::from_secs(10 * 60);
+/// Polling interval for servers without IDLE, unless the account sets `poll_interval`
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(120);
+/// After activity, polling speeds up to this and then slows back down
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(15);
+/// Don't hold up shutdown for a server that doesn't answer LOGOUT
+const LOGOUT_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[derive(Clone, serde::Serialize)]
 pub struct NewMailPayload {
     pub account_id: String,
     pub folder: String,
+    /// UIDs of the arrived messages, already stored locally
+    pub uids: Vec<u32>,
+}
+
+/// State of one watcher, returned by `get_idle_status` and emitted as `idle-status`.
+/// `state` is "connecting", "idle", "polling" (server without IDLE), "reconnecting" or "stopped".
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WatcherStatus {
+    pub account_id: String,
+    pub email: String,
+    pub folder: String,
+    pub state: String,
+    /// Why the last connection ended, while reconnecting
+    pub error: Option<String>,
+    /// Seconds until the next attempt, while reconnecting
+    pub retry_in_secs: Option<u64>,
+    pub since: String,
+}
+
+struct Watcher {
+    token: CancellationToken,
+    status: WatcherStatus,
 }
 
 static IDLE_RUNNING: AtomicBool = AtomicBool::new(false);
+/// Running watchers, one per (account, folder)
+static WATCHERS: Mutex<Vec<Watcher>> = Mutex::new(Vec::new());
+/// Wakes the supervisor early, e.g. after an account was saved
+static RECONCILE: Notify = Notify::const_new();
+/// New-mail events across all watchers, for the periodic learning cycle
+static NEW_MAIL_EVENTS: AtomicU32 = AtomicU32::new(0);
 
-pub fn stop_idle() {
-    IDLE_RUNNING.store(false, Ordering::SeqCst);
+/// Ask the supervisor to re-read the accounts now instead of at its next round
+pub fn refresh_watchers() {
+    RECONCILE.notify_one();
 }
 
 pub fn start_idle_task(app: AppHandle) {
@@ -24,142 +79,645 @@ pub fn start_idle_task(app: AppHandle) {
         return;
     }
 
-    tokio::spawn(async move {
-        sleep(Duration::from_secs(4)).await;
-
-        loop {
-            if !IDLE_RUNNING.load(Ordering::SeqCst) { break; }
-
-            // Load primary account credentials from DB
-            let state = app.state::<DbState>();
-            let accounts = match sqlx::query_as::<_, crate::db::Account>(
-                "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port FROM accounts LIMIT 1"
-            )
-            .fetch_all(&state.pool)
-            .await {
-                Ok(a) => a,
-                Err(e) => {
-                    log::warn!("[IDLE] DB error: {}. Retry in 10s.", e);
-                    sleep(Duration::from_secs(10)).await;
-                    continue;
-                }
-            };
+    let token = crate::tasks::token();
+    crate::tasks::spawn("imap-idle", async move {
+        tokio::select! {
+            _ = sleep(Duration::from_secs(4)) => {}
+            _ = token.cancelled() => {}
+        }
+
+        while !token.is_cancelled() {
+            if let Err(e) = reconcile(&app).await {
+                log::warn!("[IDLE] {}", e);
+            }
+            tokio::select! {
+                _ = sleep(RECONCILE_INTERVAL) => {}
+                _ = RECONCILE.notified() => {}
+                _ = token.cancelled() => {}
+            }
+        }
+        IDLE_RUNNING.store(false, Ordering::SeqCst);
+
+        let stopped: Vec<Watcher> = WATCHERS.lock().unwrap().drain(..).collect();
+        for watcher in stopped {
+            watcher.token.cancel();
+            emit_stopped(&app, watcher.status);
+        }
+        log::info!("[IDLE] IMAP IDLE supervisor stopped.");
+    });
+}
+
+/// Folders to watch for every account that can log in
+async fn desired_watchers(app: &AppHandle) -> Result<Vec<(Account, String)>, String> {
+    let state = app.state::<DbState>();
+    let accounts = sqlx::query_as::<_, Account>(
+        "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port, imap_security, smtp_security, ca_cert, tls_pinning, auth_method FROM accounts"
+    )
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
 
-            if accounts.is_empty() {
-                sleep(Duration::from_secs(15)).await;
+    let extra_folders: Vec<(String, Option<String>)> = sqlx::query_as("SELECT id, idle_folders FROM accounts")
+        .fetch_all(&state.pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+
+    let mut desired = Vec::new();
+    for account in accounts {
+        if account.imap_host.is_none() || crate::oauth::AuthMethod::from_account(&account).is_err() {
+            continue;
+        }
+        let extra: Vec<String> = extra_folders.iter()
+            .find(|(id, _)| *id == account.id)
+            .and_then(|(_, json)| json.as_deref())
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default();
+
+        let mut folders = vec!["INBOX".to_string()];
+        for folder in extra {
+            if !folders.iter().any(|f| f.eq_ignore_ascii_case(&folder)) {
+                folders.push(folder);
+            }
+        }
+        for folder in folders {
+            desired.push((account.clone(), folder));
+        }
+    }
+    Ok(desired)
+}
+
+/// Start watchers that should run and stop the ones whose account or folder is gone
+async fn reconcile(app: &AppHandle) -> Result<(), String> {
+    let desired = desired_watchers(app).await?;
+
+    let (stopped, started) = {
+        let mut watchers = WATCHERS.lock().unwrap();
+        let (keep, stopped): (Vec<Watcher>, Vec<Watcher>) = watchers.drain(..).partition(|w| {
+            desired.iter().any(|(a, f)| a.id == w.status.account_id && *f == w.status.folder)
+        });
+        *watchers = keep;
+
+        let mut started = Vec::new();
+        for (account, folder) in desired {
+            if watchers.iter().any(|w| w.status.account_id == account.id && w.status.folder == folder) {
                 continue;
             }
+            let token = crate::tasks::token();
+            watchers.push(Watcher {
+                token: token.clone(),
+                status: status_of(&account.id, &account.email, &folder, "connecting", None),
+            });
+            started.push((account, folder, token));
+        }
+        (stopped, started)
+    };
 
-            let account = &accounts[0];
-            let imap_host = match &account.imap_host { Some(h) => h.clone(), None => { sleep(Duration::from_secs(15)).await; continue; } };
-            let imap_port = account.imap_port.unwrap_or(993) as u16;
-            let email_addr = account.email.clone();
-            let password = match &account.password { Some(p) => p.clone(), None => { sleep(Duration::from_secs(15)).await; continue; } };
-            let account_id = account.id.clone();
-
-            log::info!("[IDLE] Starting IDLE for {} on {}:{}", email_addr, imap_host, imap_port);
-
-            // Channel: OS thread → tokio runtime
-            let (tx, mut rx) = mpsc::channel::<bool>(4);
-            let running = Arc::new(AtomicBool::new(true));
-            let running_thread = running.clone();
-
-            // OS thread: sync IMAP IDLE loop
-            std::thread::spawn(move || {
-                let tls = match native_tls::TlsConnector::builder().build() {
-                    Ok(t) => t,
-                    Err(e) => { log::error!("[IDLE] TLS error: {}", e); return; }
-                };
-                let client = match imap::connect((imap_host.as_str(), imap_port), &imap_host, &tls) {
-                    Ok(c) => c,
-                    Err(e) => { log::error!("[IDLE] Connect error: {}", e); return; }
-                };
-                let mut session = match client.login(&email_addr, &password) {
-                    Ok(s) => s,
-                    Err((e, _)) => { log::error!("[IDLE] Login error: {}", e); return; }
-                };
-                if let Err(e) = session.select("INBOX") {
-                    log::error!("[IDLE] SELECT error: {}", e);
-                    return;
-                }
+    for watcher in stopped {
+        log::info!("[IDLE] Stopping watcher {} / {}", watcher.status.email, watcher.status.folder);
+        watcher.token.cancel();
+        emit_stopped(app, watcher.status);
+    }
+    for (account, folder, token) in started {
+        log::info!("[IDLE] Starting watcher {} / {}", account.email, folder);
+        crate::tasks::spawn("imap-idle-watcher", run_watcher(app.clone(), account.id, folder, token));
+    }
+    Ok(())
+}
 
-                log::info!("[IDLE] ✅ IMAP IDLE active for {}", email_addr);
-
-                loop {
-                    if !running_thread.load(Ordering::SeqCst) {
-                        session.logout().ok();
-                        break;
-                    }
-
-                    // Fetch current EXISTS count before IDLE
-                    let before_exists = session.select("INBOX").ok().map(|mb| mb.exists).unwrap_or(0);
-
-                    // Enter IDLE: blocks until server sends a response or 29-min keepalive
-                    let idle_result = session.idle().and_then(|mut handle| {
-                        handle.set_keepalive(std::time::Duration::from_secs(29 * 60));
-
-                        // wait_with_timeout blocks until server data OR timeout
-                        // Returns the session back after DONE command
-                        handle.wait_keepalive()
-                    });
-
-                    match idle_result {
-                        Ok(_) => {
-                            // After IDLE ends, check if EXISTS count increased
-                            if let Ok(mb) = session.select("INBOX") {
-                                if mb.exists > before_exists {
-                                    log::info!("[IDLE] 🔔 New email! EXISTS {} → {}", before_exists, mb.exists);
-                                    let _ = tx.blocking_send(true);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            log::warn!("[IDLE] IDLE error: {}. Thread exiting.", e);
-                            break;
+fn status_of(account_id: &str, email: &str, folder: &str, state: &str, error: Option<String>) -> WatcherStatus {
+    WatcherStatus {
+        account_id: account_id.to_string(),
+        email: email.to_string(),
+        folder: folder.to_string(),
+        state: state.to_string(),
+        error,
+        retry_in_secs: None,
+        since: chrono::Utc::now().to_rfc3339(),
+    }
+}
+
+/// Record a watcher's new state and tell the frontend
+fn set_status(app: &AppHandle, account_id: &str, folder: &str, state: &str, error: Option<String>) {
+    update_status(app, account_id, folder, |email| status_of(account_id, email, folder, state, error));
+}
+
+fn update_status(app: &AppHandle, account_id: &str, folder: &str, make: impl FnOnce(&str) -> WatcherStatus) {
+    let status = {
+        let mut watchers = WATCHERS.lock().unwrap();
+        let Some(watcher) = watchers.iter_mut().find(|w| w.status.account_id == account_id && w.status.folder == folder) else {
+            return;
+        };
+        watcher.status = make(&watcher.status.email);
+        watcher.status.clone()
+    };
+    let _ = app.emit("idle-status", status);
+}
+
+/// Delay before reconnect attempt `attempt` (0-based): exponential, capped, with
+/// jitter so watchers that failed together don't reconnect together
+fn backoff_delay(attempt: u32) -> Duration {
+    let ceiling = BACKOFF_BASE.saturating_mul(1u32 << attempt.min(16)).min(BACKOFF_MAX);
+    let ms = ceiling.as_millis() as u64;
+    Duration::from_millis(rand::thread_rng().gen_range(ms / 2..=ms))
+}
+
+fn emit_stopped(app: &AppHandle, mut status: WatcherStatus) {
+    status.state = "stopped".to_string();
+    status.error = None;
+    status.since = chrono::Utc::now().to_rfc3339();
+    let _ = app.emit("idle-status", status);
+}
+
+/// Keep one folder watched, reconnecting after failures, until cancelled
+async fn run_watcher(app: AppHandle, account_id: String, folder: String, token: CancellationToken) {
+    // Failed attempts in a row; reset by watch_folder once a connection is up
+    let mut attempt = 0u32;
+    loop {
+        let result = watch_folder(&app, &account_id, &folder, &token, &mut attempt).await;
+        if token.is_cancelled() {
+            return;
+        }
+
+        let error = result.err().unwrap_or_else(|| "connection closed".to_string());
+        let delay = backoff_delay(attempt);
+        attempt = attempt.saturating_add(1);
+        log::warn!("[IDLE] {} / {}: {}. Reconnecting in {}s.", account_id, folder, error, delay.as_secs());
+        update_status(&app, &account_id, &folder, |email| WatcherStatus {
+            retry_in_secs: Some(delay.as_secs()),
+            ..status_of(&account_id, email, &folder, "reconnecting", Some(error))
+        });
+
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = token.cancelled() => return,
+        }
+        set_status(&app, &account_id, &folder, "connecting", None);
+    }
+}
+
+/// What the server reported about the watched folder during one IDLE round
+#[derive(Default)]
+struct FolderChanges {
+    /// Last EXISTS count, if one arrived
+    exists: Option<u32>,
+    /// UIDs removed by EXPUNGE (or VANISHED)
+    expunged: Vec<u32>,
+    flags: Vec<FlagUpdate>,
+    /// A sequence number didn't fit the known UIDs; compare full UID lists instead
+    resync: bool,
+}
+
+impl FolderChanges {
+    /// Apply one untagged response. `uids` maps sequence numbers to UIDs and is kept
+    /// current, since every EXPUNGE shifts the numbers of the messages after it.
+    fn record(&mut self, response: &Response<'_>, uids: &mut Vec<u32>) {
+        match response {
+            Response::MailboxData(MailboxDatum::Exists(n)) => self.exists = Some(*n),
+            Response::Expunge(seq) => self.expunge(*seq, uids),
+            Response::Vanished { uids: ranges, .. } => {
+                let gone: Vec<u32> = uids.iter().copied().filter(|u| ranges.iter().any(|r| r.contains(u))).collect();
+                uids.retain(|u| !gone.contains(u));
+                self.exists = self.exists.map(|n| n.saturating_sub(gone.len() as u32));
+                self.expunged.extend(gone);
+            }
+            Response::Fetch(seq, attributes) => {
+                let update = match FlagUpdate::from_attributes(attributes) {
+                    Some(update) => Some(update),
+                    None => match uids.get((*seq as usize).wrapping_sub(1)) {
+                        Some(uid) => FlagUpdate::with_uid(*uid, attributes),
+                        None => {
+                            self.resync = true;
+                            None
                         }
-                    }
-                }
-            });
+                    },
+                };
+                self.flags.extend(update);
+            }
+            _ => {}
+        }
+    }
+
+    fn expunge(&mut self, seq: u32, uids: &mut Vec<u32>) {
+        // An EXISTS that came earlier counted the message being removed
+        self.exists = self.exists.map(|n| n.saturating_sub(1));
+        if seq == 0 || seq as usize > uids.len() {
+            self.resync = true;
+            return;
+        }
+        self.expunged.push(uids.remove(seq as usize - 1));
+    }
+}
+
+/// All UIDs of the selected folder, ascending — position i is sequence number i + 1
+async fn folder_uids(session: &mut ImapSession, query: &str) -> Result<Vec<u32>, String> {
+    let mut uids: Vec<u32> = session
+        .uid_search(query)
+        .await
+        .map_err(|e| format!("IMAP search error: {}", e))?
+        .into_iter()
+        .collect();
+    uids.sort_unstable();
+    Ok(uids)
+}
+
+/// Watch one folder: with IDLE when the server has it, otherwise by polling with NOOP.
+/// EXISTS, EXPUNGE and FETCH FLAGS responses are applied to the `emails` table and
+/// emitted as `new-mail`, `mail-expunged` and `flags-changed`.
+/// Returns when the connection fails, or once the session is logged out after `token`
+/// was cancelled. Changes are never abandoned halfway through being applied.
+async fn watch_folder(app: &AppHandle, account_id: &str, folder: &str, token: &CancellationToken, attempt: &mut u32) -> Result<(), String> {
+    let pool = app.state::<DbState>().pool.clone();
+    let setup = async {
+        // Re-read on every connect so changed settings are picked up
+        let account = crate::imap_folders::load_account(&pool, account_id).await?;
+        let mailbox = crate::imap_folders::resolve_mailbox(&pool, &account, folder).await?;
+        let credentials = crate::imap_session::ImapCredentials::from_account(&account)?;
+        // IDLE ties up its connection indefinitely, so it gets its own rather than a pooled one
+        let mut session = crate::imap::connect(&pool, &credentials).await?;
+        let capabilities = crate::imap::refresh_capabilities(&pool, &mut session, account_id).await?;
+        let poll_interval = poll_interval_of(&pool, account_id).await?;
+
+        crate::imap::select_mailbox(&mut session, &mailbox).await?;
+        let uids = folder_uids(&mut session, "ALL").await?;
+        // SELECT and SEARCH already accounted for these
+        while session.unsolicited_responses.try_recv().is_ok() {}
+        Ok::<_, String>((account, session, capabilities, uids, poll_interval))
+    };
+    // Nothing has been written yet, so a cancelled setup can simply be dropped
+    let (account, mut session, capabilities, mut uids, poll_interval) = tokio::select! {
+        result = setup => result?,
+        _ = token.cancelled() => return Ok(()),
+    };
+    let supports_idle = crate::imap::has_capability(&capabilities, "IDLE");
+
+    *attempt = 0;
+    if supports_idle {
+        log::info!("[IDLE] ✅ IMAP IDLE active for {} / {}", account.email, folder);
+        set_status(app, account_id, folder, "idle", None);
+    } else {
+        log::info!("[IDLE] {} has no IDLE, polling {} every {}s at most", account.email, folder, poll_interval.as_secs());
+        set_status(app, account_id, folder, "polling", None);
+    }
 
-            // Tokio side: receive signal → emit Tauri event
-            let app_clone = app.clone();
-            let account_id_clone = account_id.clone();
-
-            let mut mail_count: u32 = 0;
-            while let Some(_) = rx.recv().await {
-                if !IDLE_RUNNING.load(Ordering::SeqCst) { break; }
-                log::info!("[IDLE] Emitting new-mail event to frontend");
-                let _ = app_clone.emit("new-mail", NewMailPayload {
-                    account_id: account_id_clone.clone(),
-                    folder: "INBOX".to_string(),
-                });
-
-                // 🧠 Run autonomous triage on newly arrived emails
-                let triage_app = app_clone.clone();
-                let triage_account = account_id_clone.clone();
-                tokio::spawn(async move {
-                    crate::ai_triage::run_triage_on_new_emails(&triage_app, &triage_account).await;
-                });
-
-                // 🧠 Self-improvement: every 10 new mail events, run the learning cycle
-                mail_count += 1;
-                if mail_count % 10 == 0 {
-                    let learn_app = app_clone.clone();
-                    tokio::spawn(async move {
-                        crate::ai_triage::run_self_improvement_cycle(&learn_app).await;
-                    });
+    let mut wait = poll_interval;
+    loop {
+        let mut changes = FolderChanges::default();
+        if supports_idle {
+            // Returns on the first server response or after IDLE_TIMEOUT
+            let mut handle = session.idle();
+            handle.init().await.map_err(|e| format!("IDLE error: {}", e))?;
+            let first = {
+                let (idle_wait, _stop) = handle.wait_with_timeout(IDLE_TIMEOUT);
+                tokio::select! {
+                    result = idle_wait => Some(result.map_err(|e| format!("IDLE error: {}", e))?),
+                    _ = token.cancelled() => None,
                 }
+            };
+            // Anything after the first response is queued as unsolicited by DONE
+            session = handle.done().await.map_err(|e| format!("IDLE error: {}", e))?;
+            match &first {
+                Some(IdleResponse::NewData(data)) => changes.record(data.parsed(), &mut uids),
+                Some(_) => {}
+                None => break,
+            }
+        } else {
+            tokio::select! {
+                _ = sleep(wait) => {}
+                _ = token.cancelled() => break,
             }
+            // NOOP on the selected folder makes the server report what changed
+            tokio::time::timeout(MIN_POLL_INTERVAL, session.noop())
+                .await
+                .map_err(|_| "NOOP timed out".to_string())?
+                .map_err(|e| format!("NOOP error: {}", e))?;
+        }
 
-            running.store(false, Ordering::SeqCst);
+        while let Ok(response) = session.unsolicited_responses.try_recv() {
+            match response {
+                UnsolicitedResponse::Exists(n) => changes.exists = Some(n),
+                UnsolicitedResponse::Expunge(seq) => changes.expunge(seq, &mut uids),
+                UnsolicitedResponse::Other(data) => changes.record(data.parsed(), &mut uids),
+                _ => {}
+            }
+        }
 
-            if !IDLE_RUNNING.load(Ordering::SeqCst) { break; }
+        let changed = apply_changes(app, &mut session, &capabilities, account_id, folder, &mut uids, changes).await?;
+        // Poll quickly while the folder is busy, then back off to the configured interval
+        wait = if changed { MIN_POLL_INTERVAL.min(poll_interval) } else { (wait * 2).min(poll_interval) };
+    }
 
-            log::warn!("[IDLE] Reconnecting in 15s.");
-            sleep(Duration::from_secs(15)).await;
-        }
+    log::info!("[IDLE] Logging out {} / {}", account.email, folder);
+    let _ = tokio::time::timeout(LOGOUT_TIMEOUT, session.logout()).await;
+    Ok(())
+}
 
-        IDLE_RUNNING.store(false, Ordering::SeqCst);
-        log::info!("[IDLE] IMAP IDLE watcher stopped.");
+/// The account's polling interval for servers without IDLE
+async fn poll_interval_of(pool: &SqlitePool, account_id: &str) -> Result<Duration, String> {
+    let secs: Option<i64> = sqlx::query_scalar("SELECT poll_interval FROM accounts WHERE id = $1")
+        .bind(account_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?
+        .flatten();
+    Ok(secs
+        .map(|s| Duration::from_secs(s.max(MIN_POLL_INTERVAL.as_secs() as i64) as u64))
+        .unwrap_or(DEFAULT_POLL_INTERVAL))
+}
+
+/// Payload of `mail-expunged`
+#[derive(Clone, serde::Serialize)]
+pub struct MailExpungedPayload {
+    pub account_id: String,
+    pub folder: String,
+    pub uids: Vec<u32>,
+    pub email_ids: Vec<String>,
+}
+
+/// One entry of `flags-changed`
+#[derive(Clone, serde::Serialize)]
+pub struct FlagChange {
+    pub email_id: String,
+    pub uid: u32,
+    pub read: bool,
+    pub flagged: bool,
+    pub answered: bool,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct FlagsChangedPayload {
+    pub account_id: String,
+    pub folder: String,
+    pub changes: Vec<FlagChange>,
+}
+
+/// Bring the local rows in line with one IDLE or poll round and emit the typed events.
+/// Returns whether anything changed.
+async fn apply_changes(
+    app: &AppHandle,
+    session: &mut ImapSession,
+    capabilities: &[String],
+    account_id: &str,
+    folder: &str,
+    uids: &mut Vec<u32>,
+    mut changes: FolderChanges,
+) -> Result<bool, String> {
+    let state = app.state::<DbState>();
+    let pool = &state.pool;
+    // New messages: EXISTS above what is left after the expunges
+    let exists = changes.exists.map(|n| n as usize).unwrap_or(uids.len());
+    let mut new_uids = Vec::new();
+    if changes.resync || exists < uids.len() {
+        log::info!("[IDLE] {} / {}: sequence numbers out of step, comparing UID lists", account_id, folder);
+        let server = folder_uids(session, "ALL").await?;
+        changes.expunged.extend(uids.iter().filter(|u| server.binary_search(u).is_err()));
+        new_uids = server.iter().copied().filter(|u| uids.binary_search(u).is_err()).collect();
+        *uids = server;
+    } else if exists > uids.len() {
+        let last = uids.last().copied().unwrap_or(0);
+        // `n:*` always matches the last message, even when its UID is below n
+        new_uids = folder_uids(session, &format!("UID {}:*", last + 1)).await?
+            .into_iter()
+            .filter(|u| *u > last)
+            .collect();
+        uids.extend(&new_uids);
+    }
+
+    if !changes.expunged.is_empty() {
+        let email_ids: Vec<String> = changes.expunged.iter().map(|uid| local_email_id(account_id, folder, *uid)).collect();
+        crate::imap::delete_local_emails(pool, &email_ids).await?;
+        log::info!("[IDLE] {} / {}: {} expunged", account_id, folder, email_ids.len());
+        let _ = app.emit("mail-expunged", MailExpungedPayload {
+            account_id: account_id.to_string(),
+            folder: folder.to_string(),
+            uids: changes.expunged.clone(),
+            email_ids,
+        });
+    }
+
+    // Flag changes for messages expunged in the same round are moot
+    changes.flags.retain(|f| !changes.expunged.contains(&f.uid));
+    if !changes.flags.is_empty() {
+        crate::imap::apply_flag_updates(pool, account_id, folder, &changes.flags).await?;
+        log::info!("[IDLE] {} / {}: {} flag changes", account_id, folder, changes.flags.len());
+        let _ = app.emit("flags-changed", FlagsChangedPayload {
+            account_id: account_id.to_string(),
+            folder: folder.to_string(),
+            changes: changes.flags.iter().map(|f| FlagChange {
+                email_id: local_email_id(account_id, folder, f.uid),
+                uid: f.uid,
+                read: f.seen,
+                flagged: f.flagged,
+                answered: f.answered,
+            }).collect(),
+        });
+    }
+
+    if !new_uids.is_empty() {
+        let fetches = crate::imap::uid_fetch_all(session, &format_uid_set(&new_uids), LIST_FETCH_QUERY).await?;
+        let mut emails: Vec<serde_json::Value> = fetches.iter()
+            .map(|msg| crate::imap::parse_fetched_message(msg, account_id, folder))
+            .collect();
+        crate::gmail::annotate(session, capabilities, &mut emails).await?;
+        crate::imap::store_emails(pool, account_id, folder, &emails).await?;
+        crate::threads::thread_account(pool, account_id).await?;
+        log::info!("[IDLE] 🔔 {} / {}: {} new", account_id, folder, new_uids.len());
+        notify_new_mail(app, account_id, folder, new_uids.clone());
+    }
+    Ok(!changes.expunged.is_empty() || !changes.flags.is_empty() || !new_uids.is_empty())
+}
+
+/// Emit `new-mail`; for INBOX also kick off triage (and every 10th time, the learning cycle)
+fn notify_new_mail(app: &AppHandle, account_id: &str, folder: &str, uids: Vec<u32>) {
+    log::info!("[IDLE] Emitting new-mail event to frontend");
+    let _ = app.emit("new-mail", NewMailPayload {
+        account_id: account_id.to_string(),
+        folder: folder.to_string(),
+        uids,
+    });
+
+    if !folder.eq_ignore_ascii_case("INBOX") {
+        return;
+    }
+
+    // 🧠 Run autonomous triage on newly arrived emails
+    let triage_app = app.clone();
+    let triage_account = account_id.to_string();
+    crate::tasks::spawn("triage", async move {
+        crate::ai_triage::run_triage_on_new_emails(&triage_app, &triage_account).await;
     });
+
+    // 🧠 Self-improvement: every 10 new mail events, run the learning cycle
+    let mail_count = NEW_MAIL_EVENTS.fetch_add(1, Ordering::SeqCst) + 1;
+    if mail_count % 10 == 0 {
+        let learn_app = app.clone();
+        crate::tasks::spawn("self-improvement", async move {
+            crate::ai_triage::run_self_improvement_cycle(&learn_app).await;
+        });
+    }
+}
+
+/// Tauri command: state of every running watcher
+#[tauri::command]
+pub async fn get_idle_status() -> Result<Vec<WatcherStatus>, String> {
+    Ok(WATCHERS.lock().unwrap().iter().map(|w| w.status.clone()).collect())
+}
+
+/// Tauri command: folders watched with IDLE in addition to INBOX
+#[tauri::command]
+pub async fn set_idle_folders(app: AppHandle, account_id: String, folders: Vec<String>) -> Result<(), String> {
+    let state = app.state::<DbState>();
+    let json = serde_json::to_string(&folders).map_err(|e| format!("JSON error: {}", e))?;
+    sqlx::query("UPDATE accounts SET idle_folders = $1 WHERE id = $2")
+        .bind(&json)
+        .bind(&account_id)
+        .execute(&state.pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    refresh_watchers();
+    Ok(())
+}
+
+/// Tauri command: polling interval (seconds) for servers without IDLE. The account's
+/// watchers restart to pick it up.
+#[tauri::command]
+pub async fn set_poll_interval(app: AppHandle, account_id: String, seconds: Option<i64>) -> Result<(), String> {
+    let state = app.state::<DbState>();
+    sqlx::query("UPDATE accounts SET poll_interval = $1 WHERE id = $2")
+        .bind(seconds)
+        .bind(&account_id)
+        .execute(&state.pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    restart_watchers(&app, &account_id);
+    Ok(())
+}
+
+/// Stop the account's watchers; the supervisor starts them again right away
+fn restart_watchers(app: &AppHandle, account_id: &str) {
+    let stopped: Vec<Watcher> = {
+        let mut watchers = WATCHERS.lock().unwrap();
+        let (stopped, keep) = watchers.drain(..).partition(|w| w.status.account_id == account_id);
+        *watchers = keep;
+        stopped
+    };
+    for watcher in stopped {
+        watcher.token.cancel();
+        emit_stopped(app, watcher.status);
+    }
+    refresh_watchers();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_imap::imap_proto::AttributeValue;
+    use std::borrow::Cow;
+
+    fn exists(n: u32) -> Response<'static> {
+        Response::MailboxData(MailboxDatum::Exists(n))
+    }
+
+    fn seen(seq: u32) -> Response<'static> {
+        Response::Fetch(seq, vec![AttributeValue::Flags(vec![Cow::Borrowed("\\Seen")])])
+    }
+
+    fn record_all(responses: &[Response<'_>], uids: &mut Vec<u32>) -> FolderChanges {
+        let mut changes = FolderChanges::default();
+        for response in responses {
+            changes.record(response, uids);
+        }
+        changes
+    }
+
+    #[test]
+    fn shifts_sequence_numbers_after_each_expunge() {
+        let mut uids = vec![10, 20, 30, 40, 50];
+        // Seq 2 is uid 20; after that uid 40 has moved down to seq 3
+        let changes = record_all(&[Response::Expunge(2), Response::Expunge(3)], &mut uids);
+        assert_eq!(changes.expunged, vec![20, 40]);
+        assert_eq!(uids, vec![10, 30, 50]);
+        assert!(!changes.resync);
+
+        // The same message number reported twice removes neighbours in turn
+        let mut uids = vec![10, 20, 30];
+        let changes = record_all(&[Response::Expunge(1), Response::Expunge(1), Response::Expunge(1)], &mut uids);
+        assert_eq!(changes.expunged, vec![10, 20, 30]);
+        assert!(uids.is_empty());
+        assert!(!changes.resync);
+    }
+
+    #[test]
+    fn counts_expunges_after_exists() {
+        let mut uids = vec![10, 20, 30];
+        // One new message arrives, then an old one is removed
+        let changes = record_all(&[exists(4), Response::Expunge(1)], &mut uids);
+        assert_eq!(changes.exists, Some(3));
+        assert_eq!(changes.expunged, vec![10]);
+        assert_eq!(uids, vec![20, 30]);
+        assert!(!changes.resync);
+
+        // The new message itself is removed; its UID isn't known yet
+        let mut uids = vec![10, 20, 30];
+        let changes = record_all(&[exists(4), Response::Expunge(4)], &mut uids);
+        assert_eq!(changes.exists, Some(3));
+        assert!(changes.expunged.is_empty());
+        assert!(changes.resync);
+    }
+
+    #[test]
+    fn keeps_exists_that_follows_expunge() {
+        let mut uids = vec![10, 20, 30];
+        let changes = record_all(&[Response::Expunge(3), exists(3)], &mut uids);
+        assert_eq!(changes.exists, Some(3));
+        assert_eq!(uids, vec![10, 20]);
+    }
+
+    #[test]
+    fn resyncs_on_unknown_sequence_numbers() {
+        let mut uids = vec![10, 20];
+        assert!(record_all(&[Response::Expunge(0)], &mut uids).resync);
+        assert!(record_all(&[Response::Expunge(3)], &mut uids).resync);
+        assert!(record_all(&[seen(3)], &mut uids).resync);
+        assert_eq!(uids, vec![10, 20]);
+    }
+
+    #[test]
+    fn removes_vanished_uids() {
+        let mut uids = vec![10, 20, 30, 40];
+        let changes = record_all(
+            &[exists(4), Response::Vanished { earlier: false, uids: vec![15..=30, 99..=99] }],
+            &mut uids,
+        );
+        assert_eq!(changes.expunged, vec![20, 30]);
+        assert_eq!(uids, vec![10, 40]);
+        assert_eq!(changes.exists, Some(2));
+    }
+
+    #[test]
+    fn resolves_fetch_by_shifted_sequence_number() {
+        let mut uids = vec![10, 20, 30];
+        let changes = record_all(&[Response::Expunge(1), seen(2)], &mut uids);
+        assert_eq!(changes.flags, vec![FlagUpdate { uid: 30, seen: true, ..Default::default() }]);
+
+        // A UID in the response wins over the sequence number
+        let mut uids = vec![10, 20];
+        let fetch = Response::Fetch(1, vec![
+            AttributeValue::Uid(20),
+            AttributeValue::Flags(vec![Cow::Borrowed("\\Flagged")]),
+        ]);
+        let changes = record_all(&[fetch], &mut uids);
+        assert_eq!(changes.flags, vec![FlagUpdate { uid: 20, flagged: true, ..Default::default() }]);
+    }
+
+    #[test]
+    fn backs_off_with_jitter_up_to_the_cap() {
+        for attempt in 0..40 {
+            let ceiling = BACKOFF_BASE.saturating_mul(1u32 << attempt.min(16)).min(BACKOFF_MAX);
+            let delay = backoff_delay(attempt);
+            assert!(delay >= ceiling / 2 && delay <= ceiling, "attempt {}: {:?}", attempt, delay);
+            assert!(delay <= BACKOFF_MAX);
+        }
+        assert!(backoff_delay(0) <= BACKOFF_BASE);
+        assert!(backoff_delay(30) >= BACKOFF_MAX / 2);
+    }
 }
@@ -0,0 +1,457 @@
+/// Server-side search — finds mail that isn't in the local cache with UID SEARCH
+/// (CHARSET UTF-8), downloads the headers of the matches and returns them as rows.
+/// With ESEARCH (RFC 4731) the matches come back as a compact UID set; the response
+/// parser doesn't know `* ESEARCH`, so `EsearchCompat` hands it over as the
+/// equivalent `* SEARCH` list. Results are trimmed to the newest `limit` locally.
+/// On Gmail, `gmail_raw` passes a query in Gmail's own search syntax (X-GM-RAW).
+use tauri::{AppHandle, Manager};
+use crate::db::{DbState, Email};
+use crate::imap::ImapSession;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Matches downloaded when the query doesn't set a limit
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 500;
+/// LITERAL- (RFC 7888) only allows non-synchronizing literals up to this size
+const LITERAL_MINUS_MAX: usize = 4096;
+
+/// Structured search criteria; every field that is set must match.
+/// Dates are `YYYY-MM-DD`; like IMAP, `since` is inclusive and `before` exclusive.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+pub struct SearchQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub subject: Option<String>,
+    /// Message body only
+    pub body: Option<String>,
+    /// Headers and body
+    pub text: Option<String>,
+    pub since: Option<String>,
+    pub before: Option<String>,
+    pub seen: Option<bool>,
+    pub flagged: Option<bool>,
+    pub answered: Option<bool>,
+    /// Size bounds in bytes
+    pub larger: Option<u32>,
+    pub smaller: Option<u32>,
+    /// Gmail search syntax, e.g. `has:attachment older_than:1y` (Gmail only)
+    pub gmail_raw: Option<String>,
+    /// Most matches to download, newest first
+    pub limit: Option<usize>,
+}
+
+/// How search strings can be sent, depending on the server's capabilities
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Literals {
+    /// LITERAL+: non-synchronizing literals of any size
+    Plus,
+    /// LITERAL-: non-synchronizing literals up to LITERAL_MINUS_MAX bytes
+    Minus,
+    /// Quoted strings only
+    None,
+}
+
+/// An astring for a search key. ASCII goes in a quoted string (line breaks dropped);
+/// UTF-8 needs a literal, sent non-synchronizing as the server must allow.
+/// Synchronizing literals would have to wait for the server's go-ahead mid-command,
+/// which the session can't do, so without LITERAL+/- non-ASCII text is refused.
+fn search_string(value: &str, literals: Literals) -> Result<String, String> {
+    if value.is_ascii() {
+        let cleaned: String = value.chars().filter(|c| *c != '\r' && *c != '\n').collect();
+        return Ok(format!("\"{}\"", cleaned.replace('\\', "\\\\").replace('"', "\\\"")));
+    }
+    match literals {
+        Literals::Plus => {}
+        Literals::Minus if value.len() <= LITERAL_MINUS_MAX => {}
+        Literals::Minus => return Err(format!("Search text is too long for this server (at most {} bytes)", LITERAL_MINUS_MAX)),
+        Literals::None => return Err("This server can't search for text with non-ASCII characters".to_string()),
+    }
+    Ok(format!("{{{}+}}\r\n{}", value.len(), value))
+}
+
+/// `YYYY-MM-DD` to the IMAP date format, e.g. `1-Feb-1994`
+fn imap_date(value: &str) -> Result<String, String> {
+    let date = chrono::NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d")
+        .map_err(|e| format!("Invalid date {}: {}", value, e))?;
+    Ok(date.format("%-d-%b-%Y").to_string())
+}
+
+/// Translate the query into UID SEARCH criteria
+fn search_criteria(query: &SearchQuery, literals: Literals, esearch: bool, gmail: bool) -> Result<String, String> {
+    let mut keys: Vec<String> = Vec::new();
+    if let Some(raw) = query.gmail_raw.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+        if !gmail {
+            return Err("Gmail search syntax needs a Gmail account".to_string());
+        }
+        keys.push(format!("X-GM-RAW {}", search_string(raw, literals)?));
+    }
+    let strings = [
+        ("FROM", &query.from),
+        ("TO", &query.to),
+        ("SUBJECT", &query.subject),
+        ("BODY", &query.body),
+        ("TEXT", &query.text),
+    ];
+    for (key, value) in strings {
+        if let Some(value) = value.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+            keys.push(format!("{} {}", key, search_string(value, literals)?));
+        }
+    }
+    if let Some(since) = &query.since {
+        keys.push(format!("SINCE {}", imap_date(since)?));
+    }
+    if let Some(before) = &query.before {
+        keys.push(format!("BEFORE {}", imap_date(before)?));
+    }
+    let flags = [
+        (query.seen, "SEEN", "UNSEEN"),
+        (query.flagged, "FLAGGED", "UNFLAGGED"),
+        (query.answered, "ANSWERED", "UNANSWERED"),
+    ];
+    for (wanted, yes, no) in flags {
+        if let Some(wanted) = wanted {
+            keys.push(if wanted { yes } else { no }.to_string());
+        }
+    }
+    if let Some(larger) = query.larger {
+        keys.push(format!("LARGER {}", larger));
+    }
+    if let Some(smaller) = query.smaller {
+        keys.push(format!("SMALLER {}", smaller));
+    }
+
+    if keys.is_empty() {
+        return Err("Search query is empty".to_string());
+    }
+    let criteria = format!("CHARSET UTF-8 {}", keys.join(" "));
+    Ok(if esearch { format!("RETURN (ALL) {}", criteria) } else { criteria })
+}
+
+/// Run the search on the selected folder; matching UIDs, newest first
+async fn search_uids(session: &mut ImapSession, capabilities: &[String], query: &SearchQuery) -> Result<Vec<u32>, String> {
+    let has = |name: &str| crate::imap::has_capability(capabilities, name);
+    let literals = if has("LITERAL+") {
+        Literals::Plus
+    } else if has("LITERAL-") {
+        Literals::Minus
+    } else {
+        Literals::None
+    };
+    let criteria = search_criteria(query, literals, has("ESEARCH"), crate::gmail::supported(capabilities))?;
+    let mut uids: Vec<u32> = session
+        .uid_search(&criteria)
+        .await
+        .map_err(|e| format!("Search error: {}", e))?
+        .into_iter()
+        .collect();
+    uids.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(uids)
+}
+
+/// Rewrite an ESEARCH response line, e.g. `* ESEARCH (TAG "A7") UID ALL 4:6,9`, as
+/// the `* SEARCH 4 5 6 9` it stands for. Only `ALL` carries matches; other return
+/// data (COUNT, MIN, MAX) is dropped, as only `RETURN (ALL)` is ever asked for.
+fn esearch_as_search(line: &[u8]) -> Option<Vec<u8>> {
+    let rest = line.strip_prefix(b"* ESEARCH")?;
+    let text = std::str::from_utf8(rest).ok()?.trim_end();
+    if !(text.is_empty() || text.starts_with(' ')) {
+        return None;
+    }
+    // The correlator is `(TAG "...")`; the tag itself has no ')' in it
+    let text = text.trim_start();
+    let text = match text.strip_prefix('(') {
+        Some(correlated) => &correlated[correlated.find(')')? + 1..],
+        None => text,
+    };
+    let mut tokens = text.split_whitespace().peekable();
+    if tokens.peek().is_some_and(|t| t.eq_ignore_ascii_case("UID")) {
+        tokens.next();
+    }
+    let mut uids: Vec<u32> = Vec::new();
+    while let Some(key) = tokens.next() {
+        let value = tokens.next()?;
+        if key.eq_ignore_ascii_case("ALL") {
+            for range in value.split(',') {
+                let (start, end) = range.split_once(':').unwrap_or((range, range));
+                let (start, end): (u32, u32) = (start.parse().ok()?, end.parse().ok()?);
+                uids.extend(start.min(end)..=start.max(end));
+            }
+        }
+    }
+    let mut out = b"* SEARCH".to_vec();
+    for uid in uids {
+        out.extend_from_slice(format!(" {}", uid).as_bytes());
+    }
+    out.extend_from_slice(b"\r\n");
+    Some(out)
+}
+
+/// Literal announced at the end of a response line, `{123}` or `{123+}`
+fn trailing_literal(line: &[u8]) -> Option<usize> {
+    let line = line.strip_suffix(b"\r\n").or_else(|| line.strip_suffix(b"\n"))?;
+    let inner = line.strip_suffix(b"}")?;
+    let start = inner.iter().rposition(|&b| b == b'{')?;
+    let digits = inner[start + 1..].strip_suffix(b"+").unwrap_or(&inner[start + 1..]);
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+/// Reads the server's responses line by line and passes them on unchanged, except
+/// for `* ESEARCH` lines, which become `* SEARCH` lines (see `esearch_as_search`).
+/// Literal data (message bodies and the like) is passed through without looking at it.
+#[derive(Debug)]
+pub struct EsearchCompat<S> {
+    inner: S,
+    /// Incomplete response line read so far
+    line: Vec<u8>,
+    /// Literal bytes still to pass through untouched
+    literal_left: usize,
+    /// Bytes ready for the reader, from `pending[consumed..]`
+    pending: Vec<u8>,
+    consumed: usize,
+}
+
+impl<S> EsearchCompat<S> {
+    pub fn new(inner: S) -> Self {
+        EsearchCompat { inner, line: Vec::new(), literal_left: 0, pending: Vec::new(), consumed: 0 }
+    }
+
+    fn push(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.literal_left > 0 {
+                let n = self.literal_left.min(data.len());
+                self.pending.extend_from_slice(&data[..n]);
+                self.literal_left -= n;
+                data = &data[n..];
+                continue;
+            }
+            let Some(end) = data.iter().position(|&b| b == b'\n') else {
+                self.line.extend_from_slice(data);
+                return;
+            };
+            self.line.extend_from_slice(&data[..=end]);
+            data = &data[end + 1..];
+            let line = std::mem::take(&mut self.line);
+            self.literal_left = trailing_literal(&line).unwrap_or(0);
+            match esearch_as_search(&line) {
+                Some(search) => self.pending.extend_from_slice(&search),
+                None => self.pending.extend_from_slice(&line),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for EsearchCompat<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.consumed < this.pending.len() {
+                let n = buf.remaining().min(this.pending.len() - this.consumed);
+                buf.put_slice(&this.pending[this.consumed..this.consumed + n]);
+                this.consumed += n;
+                if this.consumed == this.pending.len() {
+                    this.pending.clear();
+                    this.consumed = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            let mut chunk = [0u8; 8192];
+            let mut read = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut this.inner).poll_read(cx, &mut read) {
+                Poll::Ready(Ok(())) if read.filled().is_empty() => {
+                    // End of stream: hand out what is left of an unterminated line
+                    if this.line.is_empty() {
+                        return Poll::Ready(Ok(()));
+                    }
+                    this.pending = std::mem::take(&mut this.line);
+                }
+                Poll::Ready(Ok(())) => this.push(read.filled()),
+                other => return other,
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for EsearchCompat<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Tauri command: search a folder on the server (INBOX by default). The newest
+/// matches are fetched into the local cache and returned like `get_emails` rows.
+#[tauri::command]
+pub async fn search_server(app: AppHandle, account_id: String, folder: Option<String>, query: SearchQuery) -> Result<Vec<Email>, String> {
+    let state = app.state::<DbState>();
+    let pool = state.pool.clone();
+    let folder = folder.unwrap_or_else(|| "INBOX".to_string());
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let account = crate::imap_folders::load_account(&pool, &account_id).await?;
+    let mailbox_name = crate::imap_folders::resolve_mailbox(&pool, &account, &folder).await?;
+
+    let mut session = crate::imap_session::acquire(&pool, &account).await?;
+    let capabilities = session.capabilities.clone();
+    let result = async {
+        crate::imap::select_mailbox(&mut session, &mailbox_name).await?;
+        let mut uids = search_uids(&mut session, &capabilities, &query).await?;
+        let total = uids.len();
+        uids.truncate(limit);
+        if uids.is_empty() {
+            return Ok((total, vec![]));
+        }
+        let messages = crate::imap::uid_fetch_all(&mut session, &crate::imap::format_uid_set(&uids), crate::imap::LIST_FETCH_QUERY).await?;
+        let mut emails: Vec<serde_json::Value> = messages.iter()
+            .map(|msg| crate::imap::parse_fetched_message(msg, &account_id, &folder))
+            .collect();
+        crate::gmail::annotate(&mut session, &capabilities, &mut emails).await?;
+        Ok((total, emails))
+    }.await;
+    let (total, emails) = session.finish(result)?;
+
+    crate::imap::store_emails(&pool, &account_id, &folder, &emails).await?;
+    if !emails.is_empty() {
+        crate::threads::thread_account(&pool, &account_id).await?;
+    }
+    log::info!("[SEARCH] {} matches in {} for {}, returning {}", total, folder, account.email, emails.len());
+
+    let mut rows = Vec::with_capacity(emails.len());
+    for email in &emails {
+        let row = sqlx::query_as::<_, Email>(
+            "SELECT id, uid, account_id, folder, subject, sender, sender_email, to_email, date, snippet, body, body_cached, read, flagged, answered, ai_priority, ai_labels, ai_summary, gm_msgid, gm_thrid, message_id, thread_id FROM emails WHERE id = $1"
+        )
+        .bind(email["id"].as_str().unwrap_or(""))
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+        rows.extend(row);
+    }
+    rows.sort_by_key(|e| std::cmp::Reverse(e.uid));
+    crate::addresses::load_addresses(&pool, &mut rows).await?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use tokio::io::AsyncReadExt;
+
+    /// Hands out the given chunks one read at a time
+    struct Chunks(VecDeque<Vec<u8>>);
+
+    impl AsyncRead for Chunks {
+        fn poll_read(self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+            if let Some(chunk) = self.get_mut().0.pop_front() {
+                buf.put_slice(&chunk);
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    async fn read_through(chunks: &[&[u8]]) -> String {
+        let mut reader = EsearchCompat::new(Chunks(chunks.iter().map(|c| c.to_vec()).collect()));
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).await.unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn quotes_ascii_strings() {
+        assert_eq!(search_string("alice", Literals::None).unwrap(), "\"alice\"");
+        assert_eq!(search_string("say \"hi\" \\o/", Literals::None).unwrap(), "\"say \\\"hi\\\" \\\\o/\"");
+        assert_eq!(search_string("a\r\nb", Literals::Plus).unwrap(), "\"ab\"");
+    }
+
+    #[test]
+    fn sends_utf8_as_a_literal_when_allowed() {
+        assert_eq!(search_string("Grüße", Literals::Plus).unwrap(), "{7+}\r\nGrüße");
+        assert_eq!(search_string("Grüße", Literals::Minus).unwrap(), "{7+}\r\nGrüße");
+        let long = "ü".repeat(LITERAL_MINUS_MAX);
+        assert!(search_string(&long, Literals::Plus).is_ok());
+        assert!(search_string(&long, Literals::Minus).is_err());
+        assert!(search_string("Grüße", Literals::None).is_err());
+    }
+
+    #[test]
+    fn formats_imap_dates() {
+        assert_eq!(imap_date("1994-02-01").unwrap(), "1-Feb-1994");
+        assert_eq!(imap_date(" 2024-12-31 ").unwrap(), "31-Dec-2024");
+        assert!(imap_date("2024-13-01").is_err());
+        assert!(imap_date("01/02/2024").is_err());
+    }
+
+    #[test]
+    fn builds_search_criteria() {
+        let query = SearchQuery {
+            from: Some(" alice ".to_string()),
+            subject: Some("".to_string()),
+            since: Some("2024-01-05".to_string()),
+            seen: Some(false),
+            flagged: Some(true),
+            larger: Some(1024),
+            ..Default::default()
+        };
+        assert_eq!(
+            search_criteria(&query, Literals::None, false, false).unwrap(),
+            "CHARSET UTF-8 FROM \"alice\" SINCE 5-Jan-2024 UNSEEN FLAGGED LARGER 1024"
+        );
+        assert_eq!(
+            search_criteria(&query, Literals::None, true, false).unwrap(),
+            "RETURN (ALL) CHARSET UTF-8 FROM \"alice\" SINCE 5-Jan-2024 UNSEEN FLAGGED LARGER 1024"
+        );
+    }
+
+    #[test]
+    fn rejects_unusable_queries() {
+        assert!(search_criteria(&SearchQuery::default(), Literals::Plus, false, false).is_err());
+        let raw = SearchQuery { gmail_raw: Some("has:attachment".to_string()), ..Default::default() };
+        assert!(search_criteria(&raw, Literals::Plus, false, false).is_err());
+        assert_eq!(
+            search_criteria(&raw, Literals::Plus, false, true).unwrap(),
+            "CHARSET UTF-8 X-GM-RAW \"has:attachment\""
+        );
+        let utf8 = SearchQuery { text: Some("café".to_string()), ..Default::default() };
+        assert!(search_criteria(&utf8, Literals::None, false, false).is_err());
+        let bad_date = SearchQuery { before: Some("yesterday".to_string()), ..Default::default() };
+        assert!(search_criteria(&bad_date, Literals::Plus, false, false).is_err());
+    }
+
+    #[test]
+    fn translates_esearch_lines() {
+        assert_eq!(
+            esearch_as_search(b"* ESEARCH (TAG \"A7\") UID ALL 4:6,9\r\n").unwrap(),
+            b"* SEARCH 4 5 6 9\r\n"
+        );
+        assert_eq!(esearch_as_search(b"* ESEARCH (TAG \"A7\") UID\r\n").unwrap(), b"* SEARCH\r\n");
+        assert_eq!(
+            esearch_as_search(b"* ESEARCH UID COUNT 2 ALL 3,1\r\n").unwrap(),
+            b"* SEARCH 3 1\r\n"
+        );
+        assert!(esearch_as_search(b"* SEARCH 1 2\r\n").is_none());
+        assert!(esearch_as_search(b"* ESEARCHX\r\n").is_none());
+    }
+
+    #[tokio::test]
+    async fn rewrites_esearch_across_reads() {
+        let out = read_through(&[b"* ESEARCH (TAG \"A3\") U", b"ID ALL 2:3\r\nA3 OK done\r\n"]).await;
+        assert_eq!(out, "* SEARCH 2 3\r\nA3 OK done\r\n");
+    }
+
+    #[tokio::test]
+    async fn passes_literals_through_untouched() {
+        let literal = "* ESEARCH (TAG \"x\") UID ALL 1\r\n";
+        let response = format!("* 1 FETCH (UID 5 BODY[] {{{}}}\r\n{})\r\nA4 OK done\r\n", literal.len(), literal);
+        let (head, tail) = response.as_bytes().split_at(30);
+        assert_eq!(read_through(&[head, tail]).await, response);
+    }
+}
@@ -0,0 +1,212 @@
+/// Shared IMAP sessions. Commands borrow an authenticated session for the account
+/// instead of connecting and logging in every time; idle sessions are kept alive
+/// with NOOP and transparently replaced when the server has dropped them.
+use crate::db::Account;
+use crate::imap::ImapSession;
+use crate::oauth::AuthMethod;
+use crate::tls::{Security, TlsSettings};
+use sqlx::SqlitePool;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+/// Sessions idle longer than this are checked with NOOP before being handed out
+const VERIFY_AFTER: Duration = Duration::from_secs(30);
+/// How often idle sessions get a NOOP
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(120);
+/// Idle sessions unused for this long are logged out
+const MAX_IDLE: Duration = Duration::from_secs(10 * 60);
+/// A NOOP that takes longer than this counts as a dead connection
+const NOOP_TIMEOUT: Duration = Duration::from_secs(20);
+/// Idle sessions kept per account; extra ones are logged out when returned
+const MAX_IDLE_PER_ACCOUNT: usize = 2;
+
+/// What a session was authenticated with. A changed password, host or security
+/// setting makes pooled sessions for the account unusable.
+#[derive(Clone, PartialEq)]
+pub struct ImapCredentials {
+    pub host: String,
+    pub port: u16,
+    pub email: String,
+    pub auth: AuthMethod,
+    pub security: Security,
+    pub tls: TlsSettings,
+}
+
+impl ImapCredentials {
+    pub fn from_account(account: &Account) -> Result<Self, String> {
+        let security = Security::parse(account.imap_security.as_deref())?;
+        Ok(ImapCredentials {
+            host: account.imap_host.clone().ok_or("IMAP host not configured")?,
+            port: account.imap_port.map(|p| p as u16).unwrap_or(security.default_imap_port()),
+            email: account.email.clone(),
+            auth: AuthMethod::from_account(account)?,
+            security,
+            tls: TlsSettings::from_account(account),
+        })
+    }
+}
+
+struct PooledSession {
+    account_id: String,
+    credentials: ImapCredentials,
+    session: ImapSession,
+    capabilities: Vec<String>,
+    returned_at: Instant,
+    verified_at: Instant,
+}
+
+/// Authenticated sessions not currently borrowed by a command
+static IDLE_SESSIONS: Mutex<Vec<PooledSession>> = Mutex::new(Vec::new());
+
+fn checkout(account_id: &str, credentials: &ImapCredentials) -> Option<PooledSession> {
+    let mut idle = IDLE_SESSIONS.lock().unwrap();
+    // Sessions logged in with old settings are useless now
+    let stale: Vec<PooledSession> = {
+        let (stale, keep) = idle.drain(..).partition(|p| p.account_id == account_id && p.credentials != *credentials);
+        *idle = keep;
+        stale
+    };
+    if !stale.is_empty() {
+        crate::tasks::spawn("imap-logout", logout_all(stale));
+    }
+
+    let pos = idle.iter().rposition(|p| p.account_id == account_id)?;
+    Some(idle.swap_remove(pos))
+}
+
+fn checkin(pooled: PooledSession) {
+    let mut idle = IDLE_SESSIONS.lock().unwrap();
+    // During shutdown the pool is being emptied; log out instead of parking the session
+    if crate::tasks::is_shutting_down()
+        || idle.iter().filter(|p| p.account_id == pooled.account_id).count() >= MAX_IDLE_PER_ACCOUNT
+    {
+        drop(idle);
+        crate::tasks::spawn("imap-logout", logout_all(vec![pooled]));
+        return;
+    }
+    idle.push(pooled);
+}
+
+async fn ping(session: &mut ImapSession) -> bool {
+    matches!(tokio::time::timeout(NOOP_TIMEOUT, session.noop()).await, Ok(Ok(())))
+}
+
+async fn logout_all(sessions: Vec<PooledSession>) {
+    for mut p in sessions {
+        p.session.logout().await.ok();
+    }
+}
+
+/// An authenticated session borrowed from the pool. It derefs to the session;
+/// `release` hands it back. A lease that is dropped instead — on error, or because
+/// the future using it was cancelled mid-command — closes the connection, since
+/// the server may still be sending responses nobody will read.
+pub struct SessionLease {
+    account_id: String,
+    credentials: ImapCredentials,
+    session: ImapSession,
+    /// Read once at login (`imap::refresh_capabilities`); empty if that failed
+    pub capabilities: Vec<String>,
+}
+
+impl SessionLease {
+    /// Return the session to the pool for the next command
+    pub fn release(self) {
+        let now = Instant::now();
+        checkin(PooledSession {
+            account_id: self.account_id,
+            credentials: self.credentials,
+            session: self.session,
+            capabilities: self.capabilities,
+            returned_at: now,
+            verified_at: now,
+        });
+    }
+
+    /// Release the session if `result` is Ok, drop it otherwise, and pass `result` through
+    pub fn finish<R>(self, result: Result<R, String>) -> Result<R, String> {
+        if result.is_ok() {
+            self.release();
+        }
+        result
+    }
+}
+
+impl Deref for SessionLease {
+    type Target = ImapSession;
+
+    fn deref(&self) -> &ImapSession {
+        &self.session
+    }
+}
+
+impl DerefMut for SessionLease {
+    fn deref_mut(&mut self) -> &mut ImapSession {
+        &mut self.session
+    }
+}
+
+/// Borrow an authenticated session of the account. A pooled session is reused when
+/// it is alive, otherwise a new one is logged in. The caller selects its own mailbox.
+pub async fn acquire(pool: &SqlitePool, account: &Account) -> Result<SessionLease, String> {
+    let credentials = ImapCredentials::from_account(account)?;
+
+    let mut reused = None;
+    if let Some(mut p) = checkout(&account.id, &credentials) {
+        if p.verified_at.elapsed() < VERIFY_AFTER || ping(&mut p.session).await {
+            reused = Some((p.session, p.capabilities));
+        } else {
+            log::info!("[IMAP] Pooled session for {} was dropped, reconnecting", credentials.email);
+        }
+    }
+    let (session, capabilities) = match reused {
+        Some(reused) => reused,
+        None => {
+            let mut session = crate::imap::connect(pool, &credentials).await?;
+            let capabilities = crate::imap::refresh_capabilities(pool, &mut session, &account.id).await
+                .unwrap_or_else(|e| {
+                    log::warn!("[IMAP] {}", e);
+                    Vec::new()
+                });
+            (session, capabilities)
+        }
+    };
+
+    Ok(SessionLease { account_id: account.id.clone(), credentials, session, capabilities })
+}
+
+/// NOOP idle sessions periodically and log out the ones nobody used for a while
+pub fn start_keepalive_task() {
+    let token = crate::tasks::token();
+    crate::tasks::spawn("imap-keepalive", async move {
+        loop {
+            tokio::select! {
+                _ = sleep(KEEPALIVE_INTERVAL) => {}
+                _ = token.cancelled() => return,
+            }
+
+            let idle: Vec<PooledSession> = IDLE_SESSIONS.lock().unwrap().drain(..).collect();
+            for mut p in idle {
+                if p.returned_at.elapsed() > MAX_IDLE {
+                    p.session.logout().await.ok();
+                    continue;
+                }
+                if ping(&mut p.session).await {
+                    p.verified_at = Instant::now();
+                    checkin(p);
+                }
+            }
+        }
+    });
+}
+
+/// Log out every pooled session; used at shutdown
+pub async fn close_all() {
+    let idle: Vec<PooledSession> = IDLE_SESSIONS.lock().unwrap().drain(..).collect();
+    if !idle.is_empty() {
+        log::info!("[IMAP] Logging out {} pooled sessions", idle.len());
+    }
+    logout_all(idle).await;
+}
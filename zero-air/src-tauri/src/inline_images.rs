@@ -0,0 +1,246 @@
+/// Inline images — `cid:` references in HTML bodies (RFC 2392) are rewritten to
+/// `mailpart://localhost/<account id>/<message id>/<email id>/<content id>` when the
+/// body is cached. The `mailpart` URI scheme registered in lib.rs serves the image part
+/// from the `email_parts` cache, downloading it on first use, so the `body` column stays small.
+use tauri::http::{HeaderValue, Response, StatusCode};
+use tauri::{AppHandle, Manager};
+use crate::db::DbState;
+use crate::imap_body::MimePart;
+
+pub const SCHEME: &str = "mailpart";
+
+/// Custom schemes are served from http://<scheme>.localhost on Windows and Android
+#[cfg(any(windows, target_os = "android"))]
+const BASE_URL: &str = "http://mailpart.localhost";
+#[cfg(not(any(windows, target_os = "android")))]
+const BASE_URL: &str = "mailpart://localhost";
+
+fn url_encode(value: &str) -> String {
+    value.bytes().fold(String::with_capacity(value.len()), |mut out, b| {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+        out
+    })
+}
+
+fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%').then(|| value.get(i + 1..i + 3)).flatten();
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// The message an inline image belongs to. The account and Message-ID let the image
+/// be found again after the row is re-keyed by a move.
+pub struct ImageOwner<'a> {
+    pub account_id: &'a str,
+    pub message_id: Option<&'a str>,
+    pub email_id: &'a str,
+}
+
+pub fn inline_url(owner: &ImageOwner, content_id: &str) -> String {
+    format!(
+        "{}/{}/{}/{}/{}",
+        BASE_URL,
+        url_encode(owner.account_id),
+        url_encode(owner.message_id.unwrap_or_default()),
+        url_encode(owner.email_id),
+        url_encode(content_id)
+    )
+}
+
+/// Point `cid:` URLs at the matching inline parts. References to Content-IDs the
+/// message doesn't have are left alone.
+pub fn resolve_cid_urls(html: &str, owner: &ImageOwner, parts: &[MimePart]) -> String {
+    let known: Vec<&str> = parts.iter().filter_map(|p| p.content_id.as_deref()).collect();
+    if known.is_empty() {
+        return html.to_string();
+    }
+    // ASCII lowercasing keeps byte offsets, so positions carry over to `html`
+    let lower = html.to_ascii_lowercase();
+    let mut out = String::with_capacity(html.len());
+    let mut copied = 0;
+    let mut search = 0;
+    while let Some(found) = lower[search..].find("cid:") {
+        let start = search + found;
+        let id_start = start + 4;
+        let is_end = |c: char| matches!(c, '"' | '\'' | ')' | '<' | '>') || c.is_whitespace();
+        // Some senders keep the angle brackets of the Content-ID: `cid:<id>`
+        let bracketed = html[id_start..].starts_with('<')
+            .then(|| html[id_start + 1..].find(|c: char| c == '>' || is_end(c)))
+            .flatten()
+            .filter(|&i| html[id_start + 1 + i..].starts_with('>'));
+        let id_end = match bracketed {
+            Some(i) => id_start + i + 2,
+            None => html[id_start..].find(is_end).map_or(html.len(), |i| id_start + i),
+        };
+        search = id_start;
+        let decoded = url_decode(&html[id_start..id_end]);
+        let cid = decoded.trim_start_matches('<').trim_end_matches('>');
+        if let Some(content_id) = known.iter().find(|k| k.eq_ignore_ascii_case(cid)) {
+            out.push_str(&html[copied..start]);
+            out.push_str(&inline_url(owner, content_id));
+            copied = id_end;
+            search = id_end;
+        }
+    }
+    out.push_str(&html[copied..]);
+    out
+}
+
+/// The image behind a `mailpart` URL path: `/<account id>/<message id>/<email id>/<content id>`
+async fn load_inline_image(app: &AppHandle, path: &str) -> Result<(String, Vec<u8>), String> {
+    let segments: Vec<String> = path.trim_start_matches('/').split('/').map(url_decode).collect();
+    let [account_id, message_id, email_id, content_id] = segments.as_slice() else {
+        return Err("Malformed inline image URL".to_string());
+    };
+
+    let state = app.state::<DbState>();
+    let mut found = sqlx::query_as::<_, (String, String, String)>(
+        "SELECT email_id, part_path, mime_type FROM attachments WHERE email_id = $1 AND content_id = $2"
+    )
+    .bind(email_id)
+    .bind(content_id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+
+    // The row may have been re-keyed by a move since its body was cached; follow the
+    // same message in the same account, never another message with that Content-ID
+    if found.is_none() && !message_id.is_empty() {
+        found = sqlx::query_as::<_, (String, String, String)>(
+            r#"SELECT a.email_id, a.part_path, a.mime_type
+               FROM attachments a JOIN emails e ON e.id = a.email_id
+               WHERE e.account_id = $1 AND e.message_id = $2 AND a.content_id = $3
+               LIMIT 1"#
+        )
+        .bind(account_id)
+        .bind(message_id)
+        .bind(content_id)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    }
+    let (email_id, part_path, mime_type) = found.ok_or_else(|| format!("Unknown inline image: {}", content_id))?;
+
+    // The type comes from the server; only a well-formed image type is served as such
+    let image_type = mime_type
+        .parse::<mime_guess::Mime>()
+        .ok()
+        .filter(|m| m.type_() == mime_guess::mime::IMAGE)
+        .ok_or_else(|| format!("Not an image: {}", mime_type))?;
+    let (_, data) = crate::imap_body::load_part_bytes(app, &email_id, &part_path).await?;
+    Ok((image_type.essence_str().to_string(), data))
+}
+
+fn response(status: u16, content_type: &str, body: Vec<u8>) -> Response<Vec<u8>> {
+    let content_type = HeaderValue::from_str(content_type)
+        .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream"));
+    Response::builder()
+        .status(status)
+        .header("Content-Type", content_type)
+        .header("X-Content-Type-Options", "nosniff")
+        // Nothing served here may run scripts or load anything else (e.g. SVG)
+        .header("Content-Security-Policy", "default-src 'none'; style-src 'unsafe-inline'")
+        .header("Cache-Control", "private, max-age=86400")
+        .body(body)
+        .unwrap_or_else(|e| {
+            log::error!("[INLINE] Could not build response: {}", e);
+            let mut response = Response::new(Vec::new());
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        })
+}
+
+/// Response to a `mailpart` URI scheme request (registered in lib.rs)
+pub async fn serve(app: &AppHandle, path: &str) -> Response<Vec<u8>> {
+    match load_inline_image(app, path).await {
+        Ok((mime_type, data)) => response(200, &mime_type, data),
+        Err(e) => {
+            log::warn!("[INLINE] {}: {}", path, e);
+            response(404, "text/plain", e.into_bytes())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: ImageOwner<'static> = ImageOwner { account_id: "acc", message_id: Some("<m@x>"), email_id: "e1" };
+
+    fn part(content_id: &str) -> MimePart {
+        MimePart {
+            path: "2".to_string(),
+            mime_type: "image/png".to_string(),
+            charset: None,
+            encoding: Some("base64".to_string()),
+            size: None,
+            disposition: Some("inline".to_string()),
+            filename: None,
+            content_id: Some(content_id.to_string()),
+        }
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(url_decode("a%20b%3Cc%3e"), "a b<c>");
+        assert_eq!(url_decode("caf%C3%A9"), "café");
+        // Broken escapes are kept as they are
+        assert_eq!(url_decode("100%"), "100%");
+        assert_eq!(url_decode("%zz%4"), "%zz%4");
+        assert_eq!(url_decode(&url_encode("img 1@x/<y>")), "img 1@x/<y>");
+    }
+
+    #[test]
+    fn rewrites_known_content_ids() {
+        let parts = [part("img1@x")];
+        let url = inline_url(&OWNER, "img1@x");
+        assert_eq!(
+            resolve_cid_urls(r#"<img src="cid:img1@x"><img src='CID:IMG1@X'>"#, &OWNER, &parts),
+            format!(r#"<img src="{}"><img src='{}'>"#, url, url)
+        );
+        assert_eq!(
+            resolve_cid_urls("<div style=\"background:url(cid:img1%40x)\">", &OWNER, &parts),
+            format!("<div style=\"background:url({})\">", url)
+        );
+    }
+
+    #[test]
+    fn accepts_angle_brackets_in_references() {
+        let parts = [part("img1@x")];
+        let url = inline_url(&OWNER, "img1@x");
+        assert_eq!(
+            resolve_cid_urls(r#"<img src="cid:<img1@x>">"#, &OWNER, &parts),
+            format!(r#"<img src="{}">"#, url)
+        );
+        assert_eq!(
+            resolve_cid_urls(r#"<img src="cid:%3Cimg1@x%3E">"#, &OWNER, &parts),
+            format!(r#"<img src="{}">"#, url)
+        );
+        assert_eq!(resolve_cid_urls("<a href=cid:img1@x>x</a>", &OWNER, &parts), format!("<a href={}>x</a>", url));
+    }
+
+    #[test]
+    fn leaves_unknown_references_alone() {
+        let html = r#"<img src="cid:other@x"> cid: <img src="cid:">"#;
+        assert_eq!(resolve_cid_urls(html, &OWNER, &[part("img1@x")]), html);
+        assert_eq!(resolve_cid_urls(r#"<img src="cid:img1@x">"#, &OWNER, &[]), r#"<img src="cid:img1@x">"#);
+    }
+}
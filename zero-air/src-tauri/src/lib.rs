@@ -5,7 +5,23 @@ pub mod imap;
 pub mod smtp;
 pub mod ai;
 pub mod imap_idle;
+pub mod imap_backfill;
+pub mod imap_body;
+pub mod imap_folders;
+pub mod imap_actions;
+pub mod imap_session;
+pub mod imap_search;
+pub mod gmail;
+pub mod pending_ops;
+pub mod mime_header;
+pub mod attachments;
+pub mod addresses;
+pub mod threads;
+pub mod inline_images;
+pub mod tls;
+pub mod oauth;
 pub mod ai_triage;
+pub mod tasks;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -36,8 +52,14 @@ pub fn run() {
               Ok(pool) => {
                   handle.manage(db::DbState { pool });
                   log::info!("Database initialized successfully");
+                  // 🔌 Keep pooled IMAP sessions alive between commands
+                  imap_session::start_keepalive_task();
                   // 🚀 Start IMAP IDLE real-time push watcher in background
                   imap_idle::start_idle_task(handle.clone());
+                  // 📚 Resume full-history backfills left unfinished
+                  imap_backfill::start_backfill_task(handle.clone());
+                  // 📤 Replay operations queued while offline
+                  pending_ops::start_replay_task(handle.clone());
               },
               Err(e) => {
                   log::error!("Failed to initialize database: {}", e);
@@ -47,6 +69,14 @@ pub fn run() {
       
       Ok(())
     })
+    // 🖼️ Inline images (cid:) of HTML bodies, served from the part cache
+    .register_asynchronous_uri_scheme_protocol(inline_images::SCHEME, |ctx, request, responder| {
+        let app = ctx.app_handle().clone();
+        let path = request.uri().path().to_string();
+        tauri::async_runtime::spawn(async move {
+            responder.respond(inline_images::serve(&app, &path).await);
+        });
+    })
     .invoke_handler(tauri::generate_handler![
         db::save_account,
         db::get_accounts,
@@ -57,6 +87,19 @@ pub fn run() {
         db::delete_email,
         db::save_ai_config,
         imap::sync_emails,
+        imap_search::search_server,
+        gmail::get_gmail_labels,
+        gmail::get_email_labels,
+        gmail::add_gmail_label,
+        gmail::remove_gmail_label,
+        pending_ops::get_pending_operations,
+        attachments::get_attachments,
+        attachments::download_attachment,
+        attachments::save_attachment,
+        addresses::get_contacts,
+        addresses::get_emails_by_address,
+        threads::get_threads,
+        threads::get_thread,
         imap::save_draft,
         smtp::send_email,
         ai::ai_generate,
@@ -78,7 +121,42 @@ pub fn run() {
         ai_triage::trigger_triage,
         imap::imap_delete_email,
         imap::imap_bulk_delete,
+        imap_backfill::start_backfill,
+        imap_body::fetch_email_body,
+        imap_body::fetch_email_part,
+        imap_folders::get_folders,
+        imap_folders::create_folder,
+        imap_folders::rename_folder,
+        imap_folders::delete_folder,
+        imap_folders::subscribe_folder,
+        imap_folders::unsubscribe_folder,
+        imap_actions::imap_move_emails,
+        imap_actions::imap_copy_emails,
+        imap_actions::set_email_flags,
+        imap_actions::mark_read,
+        tls::get_tls_pins,
+        tls::forget_tls_pin,
+        oauth::oauth_authorize,
+        oauth::oauth_sign_out,
+        imap_idle::get_idle_status,
+        imap_idle::set_idle_folders,
+        imap_idle::set_poll_interval,
+        imap::get_imap_capabilities,
     ])
-    .run(tauri::generate_context!())
-    .expect("error while running tauri application");
+    .on_window_event(|window, event| {
+      // Closing the last window quits the app; stop background work right away
+      if let tauri::WindowEvent::CloseRequested { .. } = event {
+        if window.app_handle().webview_windows().len() <= 1 {
+          tasks::begin_shutdown();
+        }
+      }
+    })
+    .build(tauri::generate_context!())
+    .expect("error while building tauri application")
+    .run(|_app, event| match event {
+      tauri::RunEvent::ExitRequested { .. } => tasks::begin_shutdown(),
+      // 🛑 Let IDLE sessions log out and running jobs finish before the process ends
+      tauri::RunEvent::Exit => tauri::async_runtime::block_on(tasks::shutdown()),
+      _ => {}
+    });
 }
@@ -0,0 +1,238 @@
+/// Header value decoding — RFC 2047 encoded words and RFC 2231 parameters.
+/// Real-world headers often break the rules: encoded words glued to other text
+/// (`=?UTF-8?B?...?=.pdf`), a character split across two words, missing base64
+/// padding, raw 8-bit UTF-8 or Latin-1 bytes. Decoding is lenient about all of these;
+/// charset conversion is left to mailparse.
+use base64::Engine;
+use base64::alphabet::STANDARD as ALPHABET;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig, STANDARD};
+use base64::engine::DecodePaddingMode;
+use mailparse::{parse_content_disposition, parse_header};
+
+/// Base64 that accepts encoded words with or without padding
+const LENIENT_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &ALPHABET,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// One `=?charset?B|Q?text?=` word, not yet converted from its charset
+struct EncodedWord<'a> {
+    /// The word as it appeared, kept if its charset is unknown
+    raw: &'a str,
+    charset: &'a str,
+    bytes: Vec<u8>,
+}
+
+/// Parse the encoded word at the start of `s`
+fn encoded_word(s: &str) -> Option<EncodedWord<'_>> {
+    let inner = s.strip_prefix("=?")?;
+    let charset_end = inner.find('?')?;
+    let encoding = inner.get(charset_end + 1..charset_end + 2)?;
+    let text = inner.get(charset_end + 2..)?.strip_prefix('?')?;
+    let text = &text[..text.find("?=")?];
+    let raw = &s[..2 + charset_end + 3 + text.len() + 2];
+    if charset_end == 0 || raw.contains(char::is_whitespace) {
+        return None;
+    }
+    // RFC 2231 allows a language after the charset: =?UTF-8*de?Q?...?=
+    let charset = inner[..charset_end].split('*').next().unwrap_or_default();
+    let bytes = if encoding.eq_ignore_ascii_case("b") {
+        LENIENT_BASE64.decode(text.trim_end_matches('=')).ok()?
+    } else if encoding.eq_ignore_ascii_case("q") {
+        decode_q(text)
+    } else {
+        return None;
+    };
+    Some(EncodedWord { raw, charset, bytes })
+}
+
+/// The Q encoding: `_` is a space and `=XX` a byte; a broken escape is kept as it is
+fn decode_q(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'=').then(|| text.get(i + 1..i + 3)).flatten();
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(if bytes[i] == b'_' { b' ' } else { bytes[i] });
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Text of `bytes` in `charset`; None if mailparse doesn't know the charset
+fn decode_charset(charset: &str, bytes: &[u8]) -> Option<String> {
+    if charset.eq_ignore_ascii_case("utf-8") || charset.eq_ignore_ascii_case("us-ascii") {
+        return Some(String::from_utf8_lossy(bytes).to_string());
+    }
+    // mailparse decodes a lone, well-formed word in any charset it supports
+    let word = format!("=?{}?B?{}?=", charset, STANDARD.encode(bytes));
+    let line = format!("X: {}", word);
+    let (header, _) = parse_header(line.as_bytes()).ok()?;
+    let decoded = header.get_value();
+    (decoded != word).then_some(decoded)
+}
+
+/// Convert a run of adjacent words in the same charset as one piece of text
+fn flush_words(words: &mut Vec<EncodedWord>, out: &mut String) {
+    let Some(first) = words.first() else { return };
+    let bytes: Vec<u8> = words.iter().flat_map(|w| w.bytes.iter().copied()).collect();
+    match decode_charset(first.charset, &bytes) {
+        Some(text) => out.push_str(&text),
+        None => words.iter().for_each(|w| out.push_str(w.raw)),
+    }
+    words.clear();
+}
+
+/// Decode the encoded words in a header value; other text is kept as it is
+pub fn decode_words(value: &str) -> String {
+    if !value.contains("=?") {
+        return value.to_string();
+    }
+    let mut out = String::with_capacity(value.len());
+    let mut words: Vec<EncodedWord> = Vec::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("=?") {
+        let (before, candidate) = rest.split_at(start);
+        match encoded_word(candidate) {
+            Some(word) => {
+                // Whitespace between two encoded words is not part of the text (RFC 2047 §6.2)
+                let adjacent = !words.is_empty() && before.trim().is_empty();
+                let same_charset = words.first().is_some_and(|w| w.charset.eq_ignore_ascii_case(word.charset));
+                if !(adjacent && same_charset) {
+                    flush_words(&mut words, &mut out);
+                }
+                if !adjacent {
+                    out.push_str(before);
+                }
+                rest = &candidate[word.raw.len()..];
+                words.push(word);
+            }
+            None => {
+                flush_words(&mut words, &mut out);
+                out.push_str(before);
+                out.push_str("=?");
+                rest = &candidate[2..];
+            }
+        }
+    }
+    flush_words(&mut words, &mut out);
+    out.push_str(rest);
+    out
+}
+
+/// A raw header value, e.g. an ENVELOPE subject or display name, as text: UTF-8 or
+/// else Latin-1, unfolded, with encoded words decoded
+pub fn decode_header_bytes(raw: &[u8]) -> String {
+    let text = match std::str::from_utf8(raw) {
+        Ok(text) => text.to_string(),
+        Err(_) => raw.iter().map(|&b| b as char).collect(),
+    };
+    let unfolded: String = text.split(['\r', '\n']).collect();
+    decode_words(unfolded.trim()).trim().to_string()
+}
+
+/// Value of one MIME parameter from an already split parameter list, e.g. the
+/// BODYSTRUCTURE params of a part. RFC 2231 continuations (`filename*0*`, `filename*1`)
+/// and charsets (`utf-8''%E2%82%AC`) are resolved, then RFC 2047 words decoded.
+pub fn decode_param<'a>(params: impl IntoIterator<Item = (&'a str, &'a str)>, key: &str) -> Option<String> {
+    // Rebuilt as a header value so mailparse's RFC 2231 handling can be reused
+    let value = params.into_iter().fold(String::from("x"), |mut value, (k, v)| {
+        value.push_str(&format!("; {}=\"{}\"", k, v.replace(['"', ';', '\r', '\n'], "")));
+        value
+    });
+    parse_content_disposition(&value)
+        .params
+        .remove(&key.to_lowercase())
+        .map(|v| decode_words(v.trim()))
+        .filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_base64_and_quoted_printable_words() {
+        assert_eq!(decode_header_bytes(b"=?UTF-8?B?w5xiZXJ3ZWlzdW5nIGVyaGFsdGVu?="), "Überweisung erhalten");
+        assert_eq!(decode_header_bytes(b"=?UTF-8?Q?Gr=C3=BC=C3=9Fe_aus_K=C3=B6ln?="), "Grüße aus Köln");
+        assert_eq!(decode_header_bytes(b"Re: =?utf-8?q?caf=C3=A9?= tomorrow"), "Re: café tomorrow");
+        assert_eq!(decode_header_bytes(b"=?utf-8?b?8J+OiSBQYXJ0eQ==?="), "🎉 Party");
+    }
+
+    #[test]
+    fn decodes_legacy_charsets() {
+        assert_eq!(decode_header_bytes(b"=?ISO-8859-1?Q?Se=F1or_Garc=EDa?="), "Señor García");
+        assert_eq!(decode_header_bytes(b"=?windows-1252?Q?=80_49,90_Rechnung?="), "€ 49,90 Rechnung");
+        assert_eq!(decode_header_bytes(b"=?koi8-r?B?8NLJ18XU?="), "Привет");
+        assert_eq!(decode_header_bytes(b"=?Shift_JIS?B?k/qWe4zq?="), "日本語");
+    }
+
+    #[test]
+    fn drops_whitespace_between_adjacent_words() {
+        assert_eq!(
+            decode_header_bytes(b"=?UTF-8?Q?Ihre_Bestellung_?= =?UTF-8?Q?ist_unterwegs?="),
+            "Ihre Bestellung ist unterwegs"
+        );
+        // Folded across lines
+        assert_eq!(
+            decode_header_bytes(b"=?UTF-8?B?0J/RgNC40LLQtdGC?=\r\n =?UTF-8?B?INC80LjRgA==?="),
+            "Привет мир"
+        );
+        // Whitespace next to plain text is kept
+        assert_eq!(decode_header_bytes(b"[list] =?UTF-8?Q?R=C3=A9union?= notes"), "[list] Réunion notes");
+    }
+
+    #[test]
+    fn joins_a_character_split_across_words() {
+        // "é" is C3 A9; some mailers cut encoded words at a fixed byte length
+        assert_eq!(decode_header_bytes(b"=?UTF-8?Q?caf=C3?= =?UTF-8?Q?=A9?="), "café");
+        assert_eq!(decode_header_bytes(b"=?UTF-8?B?5pel5pys?= =?UTF-8?B?6Kqe?="), "日本語");
+    }
+
+    #[test]
+    fn tolerates_malformed_words() {
+        // Glued to surrounding text
+        assert_eq!(decode_header_bytes(b"Fwd:=?UTF-8?Q?R=C3=A9sum=C3=A9?=.pdf"), "Fwd:Résumé.pdf");
+        // Missing base64 padding
+        assert_eq!(decode_header_bytes(b"=?UTF-8?B?w6lsw6h2ZQ?="), "élève");
+        // Charset with an RFC 2231 language
+        assert_eq!(decode_header_bytes(b"=?UTF-8*de?Q?Gr=C3=BC=C3=9Fe?="), "Grüße");
+        // Broken escape in Q kept literally
+        assert_eq!(decode_header_bytes(b"=?UTF-8?Q?100=_sicher?="), "100= sicher");
+    }
+
+    #[test]
+    fn leaves_undecodable_text_alone() {
+        assert_eq!(decode_header_bytes(b"=?x-unknown?Q?abc?="), "=?x-unknown?Q?abc?=");
+        assert_eq!(decode_header_bytes(b"=?UTF-8?Q?unterminated"), "=?UTF-8?Q?unterminated");
+        assert_eq!(decode_header_bytes(b"=?UTF-8?X?abc?="), "=?UTF-8?X?abc?=");
+        assert_eq!(decode_header_bytes(b"a =? b ?= c"), "a =? b ?= c");
+        assert_eq!(decode_header_bytes(b"Meeting at 10:00"), "Meeting at 10:00");
+    }
+
+    #[test]
+    fn accepts_raw_8bit_headers() {
+        assert_eq!(decode_header_bytes("Größe prüfen".as_bytes()), "Größe prüfen");
+        assert_eq!(decode_header_bytes(b"Se\xf1or P\xe9rez"), "Señor Pérez");
+        assert_eq!(decode_header_bytes(b"  Quarterly\r\n\treport  "), "Quarterly\treport");
+    }
+
+    #[test]
+    fn decodes_parameters() {
+        assert_eq!(decode_param([("filename*", "utf-8''%E2%82%AC%20rates.pdf")], "filename").as_deref(), Some("€ rates.pdf"));
+        assert_eq!(
+            decode_param([("filename*0*", "utf-8''%C3%A9l"), ("filename*1", "e.pdf")], "filename").as_deref(),
+            Some("éle.pdf")
+        );
+        assert_eq!(decode_param([("name", "=?UTF-8?B?w6lsw6hu?=.pdf")], "NAME").as_deref(), Some("élèn.pdf"));
+        assert_eq!(decode_param([("name", "")], "name"), None);
+    }
+}
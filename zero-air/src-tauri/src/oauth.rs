@@ -0,0 +1,577 @@
+/// OAuth2 sign-in for IMAP and SMTP — authorization-code flow with PKCE over a
+/// loopback redirect (RFC 8252), refresh-token storage and automatic access-token
+/// refresh. Endpoints are per account, so any provider (or a local mock server) works.
+use tauri::{AppHandle, Emitter, Manager};
+use crate::db::{Account, DbState};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long the user has to finish signing in in the browser
+const AUTHORIZE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// Access tokens are refreshed this many seconds before they expire
+const EXPIRY_MARGIN: i64 = 60;
+/// A token endpoint that doesn't answer within this time counts as failed
+const TOKEN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How an account authenticates
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthMethod {
+    Password(String),
+    /// Access tokens come from `oauth_accounts`, see `access_token`
+    OAuth2 { account_id: String },
+}
+
+impl AuthMethod {
+    pub fn from_account(account: &Account) -> Result<Self, String> {
+        match account.auth_method.as_deref() {
+            Some("oauth2") => Ok(AuthMethod::OAuth2 { account_id: account.id.clone() }),
+            _ => Ok(AuthMethod::Password(account.password.clone().ok_or("Password not configured")?)),
+        }
+    }
+}
+
+/// Provider settings sent by the frontend when signing in
+#[derive(Debug, Clone, Deserialize)]
+pub struct OAuthConfig {
+    pub auth_url: String,
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub scope: String,
+    /// SASL mechanism for IMAP: "XOAUTH2" (default) or "OAUTHBEARER". SMTP always uses XOAUTH2.
+    pub mechanism: Option<String>,
+    /// Fixed loopback port, for providers that require an exact redirect URI
+    pub redirect_port: Option<u16>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct OAuthAccount {
+    token_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    mechanism: String,
+    access_token: Option<String>,
+    refresh_token: Option<String>,
+    expires_at: Option<i64>,
+}
+
+/// A usable access token and the SASL mechanism to present it with
+#[derive(Debug, Clone)]
+pub struct AccessToken {
+    pub token: String,
+    pub mechanism: String,
+}
+
+/// Token endpoint response (RFC 6749 §5.1 / §5.2)
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: Option<String>,
+    refresh_token: Option<String>,
+    expires_in: Option<i64>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct AuthorizePayload {
+    pub account_id: String,
+    pub url: String,
+}
+
+/// Per-account refresh locks, so concurrent connects don't each spend the refresh token
+/// and a slow provider only holds up its own accounts
+static REFRESH_LOCKS: Mutex<Vec<(String, Arc<tokio::sync::Mutex<()>>)>> = Mutex::new(Vec::new());
+
+fn refresh_lock(account_id: &str) -> Arc<tokio::sync::Mutex<()>> {
+    let mut locks = REFRESH_LOCKS.lock().unwrap();
+    if let Some((_, lock)) = locks.iter().find(|(id, _)| id == account_id) {
+        return lock.clone();
+    }
+    let lock = Arc::new(tokio::sync::Mutex::new(()));
+    locks.push((account_id.to_string(), lock.clone()));
+    lock
+}
+
+fn base64url(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// PKCE code verifier (64 unreserved characters) and its S256 challenge
+fn pkce_pair() -> (String, String) {
+    let verifier = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    let challenge = base64url(&Sha256::digest(verifier.as_bytes()));
+    (verifier, challenge)
+}
+
+/// SASL XOAUTH2 initial response (Google/Microsoft)
+pub fn xoauth2_payload(user: &str, token: &str) -> String {
+    format!("user={}\x01auth=Bearer {}\x01\x01", user, token)
+}
+
+/// SASL OAUTHBEARER initial response (RFC 7628)
+pub fn oauthbearer_payload(user: &str, host: &str, port: u16, token: &str) -> String {
+    format!("n,a={},\x01host={}\x01port={}\x01auth=Bearer {}\x01\x01", user, host, port, token)
+}
+
+/// Open the authorization page in the system browser
+fn open_browser(url: &str) -> std::io::Result<()> {
+    #[cfg(target_os = "macos")]
+    let mut cmd = std::process::Command::new("open");
+    #[cfg(target_os = "windows")]
+    let mut cmd = {
+        let mut c = std::process::Command::new("rundll32");
+        c.arg("url.dll,FileProtocolHandler");
+        c
+    };
+    #[cfg(all(unix, not(target_os = "macos")))]
+    let mut cmd = std::process::Command::new("xdg-open");
+    cmd.arg(url).spawn().map(|_| ())
+}
+
+/// Wait for the browser to hit the loopback redirect and return its query parameters
+async fn await_redirect(listener: &TcpListener) -> Result<Vec<(String, String)>, String> {
+    loop {
+        let (mut stream, _) = listener.accept().await.map_err(|e| format!("OAuth redirect error: {}", e))?;
+
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 16 * 1024 {
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => request.extend_from_slice(&buf[..n]),
+            }
+        }
+        let request = String::from_utf8_lossy(&request);
+        let target = request.split_whitespace().nth(1).unwrap_or("/");
+        let params: Vec<(String, String)> = reqwest::Url::parse(&format!("http://localhost{}", target))
+            .map(|u| u.query_pairs().into_owned().collect())
+            .unwrap_or_default();
+
+        // Browsers also ask for /favicon.ico and the like
+        if !params.iter().any(|(k, _)| k == "code" || k == "error") {
+            let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+            continue;
+        }
+
+        let body = "<html><body style=\"font-family:sans-serif\"><p>Sign-in complete. You can close this window and return to the app.</p></body></html>";
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(), body
+        );
+        let _ = stream.write_all(response.as_bytes()).await;
+        return Ok(params);
+    }
+}
+
+/// POST a grant to the token endpoint
+async fn request_token(token_url: &str, client_id: &str, client_secret: Option<&str>, grant: &[(&str, &str)]) -> Result<TokenResponse, String> {
+    let mut form: Vec<(&str, &str)> = grant.to_vec();
+    form.push(("client_id", client_id));
+    if let Some(secret) = client_secret.filter(|s| !s.is_empty()) {
+        form.push(("client_secret", secret));
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(TOKEN_TIMEOUT)
+        .build()
+        .map_err(|e| format!("OAuth token error: {}", e))?;
+    let response = client
+        .post(token_url)
+        .header("Accept", "application/json")
+        .form(&form)
+        .send()
+        .await
+        .map_err(|e| format!("OAuth token error: {}", e))?;
+    let status = response.status();
+    let token: TokenResponse = response.json().await.map_err(|e| format!("OAuth token error: {}", e))?;
+
+    if let Some(error) = &token.error {
+        return Err(format!("OAuth token error: {} {}", error, token.error_description.as_deref().unwrap_or("")).trim_end().to_string());
+    }
+    if !status.is_success() || token.access_token.is_none() {
+        return Err(format!("OAuth token error: HTTP {} without an access token", status));
+    }
+    Ok(token)
+}
+
+/// Trade the authorization code from the redirect for tokens
+async fn exchange_code(config: &OAuthConfig, code: &str, redirect_uri: &str, verifier: &str) -> Result<TokenResponse, String> {
+    request_token(
+        &config.token_url,
+        &config.client_id,
+        config.client_secret.as_deref(),
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("code_verifier", verifier),
+        ],
+    )
+    .await
+}
+
+fn expires_at(token: &TokenResponse) -> Option<i64> {
+    token.expires_in.map(|secs| chrono::Utc::now().timestamp() + secs)
+}
+
+/// A valid access token of the account, refreshed first when it is (nearly) expired
+pub async fn access_token(pool: &SqlitePool, account_id: &str) -> Result<AccessToken, String> {
+    let lock = refresh_lock(account_id);
+    let _guard = lock.lock().await;
+
+    let stored = sqlx::query_as::<_, OAuthAccount>(
+        "SELECT token_url, client_id, client_secret, mechanism, access_token, refresh_token, expires_at FROM oauth_accounts WHERE account_id = $1"
+    )
+    .bind(account_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?
+    .ok_or("OAuth2 is not set up for this account, sign in again")?;
+
+    let now = chrono::Utc::now().timestamp();
+    if let Some(token) = &stored.access_token {
+        if stored.expires_at.map_or(true, |at| at - EXPIRY_MARGIN > now) {
+            return Ok(AccessToken { token: token.clone(), mechanism: stored.mechanism });
+        }
+    }
+
+    let refresh_token = stored.refresh_token.as_deref().ok_or("OAuth2 session expired, sign in again")?;
+    log::info!("[OAUTH] Refreshing access token for {}", account_id);
+    let token = request_token(
+        &stored.token_url,
+        &stored.client_id,
+        stored.client_secret.as_deref(),
+        &[("grant_type", "refresh_token"), ("refresh_token", refresh_token)],
+    )
+    .await
+    .map_err(|e| format!("{} — sign in again if the grant was revoked", e))?;
+
+    // Providers may rotate the refresh token; keep the old one otherwise
+    sqlx::query(
+        "UPDATE oauth_accounts SET access_token = $1, refresh_token = COALESCE($2, refresh_token), expires_at = $3 WHERE account_id = $4"
+    )
+    .bind(&token.access_token)
+    .bind(&token.refresh_token)
+    .bind(expires_at(&token))
+    .bind(account_id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+
+    Ok(AccessToken { token: token.access_token.unwrap_or_default(), mechanism: stored.mechanism })
+}
+
+/// Tauri command: sign the account in with OAuth2. Opens the provider's page in the
+/// browser (the URL is also emitted as `oauth-authorize` in case that fails), waits
+/// for the loopback redirect, exchanges the code and switches the account to OAuth2.
+#[tauri::command]
+pub async fn oauth_authorize(app: AppHandle, account_id: String, config: OAuthConfig) -> Result<(), String> {
+    let state = app.state::<DbState>();
+    let mechanism = match config.mechanism.as_deref().map(|m| m.to_ascii_uppercase()) {
+        None => "XOAUTH2".to_string(),
+        Some(m) if m == "XOAUTH2" || m == "OAUTHBEARER" => m,
+        Some(m) => return Err(format!("Unsupported SASL mechanism: {}", m)),
+    };
+
+    let listener = TcpListener::bind(("127.0.0.1", config.redirect_port.unwrap_or(0)))
+        .await
+        .map_err(|e| format!("OAuth redirect error: {}", e))?;
+    let port = listener.local_addr().map_err(|e| format!("OAuth redirect error: {}", e))?.port();
+    let redirect_uri = format!("http://127.0.0.1:{}/callback", port);
+
+    let (verifier, challenge) = pkce_pair();
+    let csrf = uuid::Uuid::new_v4().simple().to_string();
+    let url = reqwest::Url::parse_with_params(&config.auth_url, &[
+        ("response_type", "code"),
+        ("client_id", config.client_id.as_str()),
+        ("redirect_uri", redirect_uri.as_str()),
+        ("scope", config.scope.as_str()),
+        ("state", csrf.as_str()),
+        ("code_challenge", challenge.as_str()),
+        ("code_challenge_method", "S256"),
+        // Needed by Google to hand out a refresh token
+        ("access_type", "offline"),
+        ("prompt", "consent"),
+    ])
+    .map_err(|e| format!("Invalid authorization URL: {}", e))?;
+
+    log::info!("[OAUTH] Waiting for sign-in of {} on {}", account_id, redirect_uri);
+    let _ = app.emit("oauth-authorize", AuthorizePayload { account_id: account_id.clone(), url: url.to_string() });
+    if let Err(e) = open_browser(url.as_str()) {
+        log::warn!("[OAUTH] Could not open the browser: {}", e);
+    }
+
+    let params = tokio::time::timeout(AUTHORIZE_TIMEOUT, await_redirect(&listener))
+        .await
+        .map_err(|_| "OAuth sign-in timed out".to_string())??;
+    let param = |name: &str| params.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone());
+
+    if let Some(error) = param("error") {
+        return Err(format!("OAuth sign-in refused: {} {}", error, param("error_description").unwrap_or_default()).trim_end().to_string());
+    }
+    if param("state").as_deref() != Some(csrf.as_str()) {
+        return Err("OAuth sign-in failed: state mismatch".to_string());
+    }
+    let code = param("code").ok_or("OAuth sign-in failed: no authorization code")?;
+
+    let token = exchange_code(&config, &code, &redirect_uri, &verifier).await?;
+
+    sqlx::query(
+        r#"INSERT OR REPLACE INTO oauth_accounts
+           (account_id, auth_url, token_url, client_id, client_secret, scope, mechanism, access_token, refresh_token, expires_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"#
+    )
+    .bind(&account_id)
+    .bind(&config.auth_url)
+    .bind(&config.token_url)
+    .bind(&config.client_id)
+    .bind(&config.client_secret)
+    .bind(&config.scope)
+    .bind(&mechanism)
+    .bind(&token.access_token)
+    .bind(&token.refresh_token)
+    .bind(expires_at(&token))
+    .execute(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+
+    sqlx::query("UPDATE accounts SET auth_method = 'oauth2' WHERE id = $1")
+        .bind(&account_id)
+        .execute(&state.pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+
+    if token.refresh_token.is_none() {
+        log::warn!("[OAUTH] Provider returned no refresh token for {}; sign-in will be needed when it expires", account_id);
+    }
+    log::info!("[OAUTH] {} signed in with OAuth2 ({})", account_id, mechanism);
+    Ok(())
+}
+
+/// Tauri command: forget the OAuth2 tokens and go back to password login
+#[tauri::command]
+pub async fn oauth_sign_out(app: AppHandle, account_id: String) -> Result<(), String> {
+    let state = app.state::<DbState>();
+    sqlx::query("DELETE FROM oauth_accounts WHERE account_id = $1")
+        .bind(&account_id)
+        .execute(&state.pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    sqlx::query("UPDATE accounts SET auth_method = 'password' WHERE id = $1")
+        .bind(&account_id)
+        .execute(&state.pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    log::info!("[OAUTH] {} signed out of OAuth2", account_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    /// A token endpoint on a local port answering each request with the next
+    /// `(status, json)`; the handle yields the form bodies it received
+    async fn token_server(responses: Vec<(u16, &'static str)>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let mut bodies = Vec::new();
+            for (status, json) in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                loop {
+                    if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        let head = String::from_utf8_lossy(&request[..end]).to_ascii_lowercase();
+                        let length = head
+                            .lines()
+                            .find_map(|l| l.strip_prefix("content-length:"))
+                            .and_then(|v| v.trim().parse::<usize>().ok())
+                            .unwrap_or(0);
+                        if request.len() >= end + 4 + length {
+                            bodies.push(String::from_utf8_lossy(&request[end + 4..end + 4 + length]).to_string());
+                            break;
+                        }
+                    }
+                    let n = stream.read(&mut buf).await.unwrap();
+                    assert!(n > 0, "request ended early");
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status, json.len(), json
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+            bodies
+        });
+        (url, handle)
+    }
+
+    fn form(body: &str) -> Vec<(String, String)> {
+        reqwest::Url::parse(&format!("http://localhost/?{}", body)).unwrap().query_pairs().into_owned().collect()
+    }
+
+    fn has(body: &str, key: &str, value: &str) -> bool {
+        form(body).iter().any(|(k, v)| k == key && v == value)
+    }
+
+    /// A database with one OAuth2 account whose access token expires at `expires_at`
+    async fn account_db(token_url: &str, expires_at: i64) -> SqlitePool {
+        let pool = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        sqlx::query(
+            r#"CREATE TABLE oauth_accounts (
+                account_id TEXT PRIMARY KEY, auth_url TEXT NOT NULL, token_url TEXT NOT NULL, client_id TEXT NOT NULL,
+                client_secret TEXT, scope TEXT NOT NULL, mechanism TEXT NOT NULL DEFAULT 'XOAUTH2',
+                access_token TEXT, refresh_token TEXT, expires_at INTEGER
+            )"#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO oauth_accounts VALUES ('acc', 'http://auth', $1, 'client', 'secret', 'mail', 'OAUTHBEARER', 'old-access', 'old-refresh', $2)"
+        )
+        .bind(token_url)
+        .bind(expires_at)
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool
+    }
+
+    async fn stored_tokens(pool: &SqlitePool) -> (Option<String>, Option<String>, Option<i64>) {
+        sqlx::query_as("SELECT access_token, refresh_token, expires_at FROM oauth_accounts WHERE account_id = 'acc'")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn pkce_challenge_is_s256_of_the_verifier() {
+        // RFC 7636 appendix B
+        assert_eq!(
+            base64url(&Sha256::digest(b"dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk")),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+        let (verifier, challenge) = pkce_pair();
+        assert_eq!(verifier.len(), 64);
+        assert!(verifier.chars().all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c)));
+        assert_eq!(challenge, base64url(&Sha256::digest(verifier.as_bytes())));
+        assert_ne!(pkce_pair().0, verifier);
+    }
+
+    #[test]
+    fn builds_sasl_payloads() {
+        assert_eq!(xoauth2_payload("me@example.com", "tok"), "user=me@example.com\x01auth=Bearer tok\x01\x01");
+        assert_eq!(
+            oauthbearer_payload("me@example.com", "imap.example.com", 993, "tok"),
+            "n,a=me@example.com,\x01host=imap.example.com\x01port=993\x01auth=Bearer tok\x01\x01"
+        );
+    }
+
+    #[tokio::test]
+    async fn exchanges_the_authorization_code() {
+        let (url, server) = token_server(vec![(200, r#"{"access_token":"a1","refresh_token":"r1","expires_in":3600}"#)]).await;
+        let config = OAuthConfig {
+            auth_url: "http://auth".to_string(),
+            token_url: url,
+            client_id: "client".to_string(),
+            client_secret: None,
+            scope: "mail".to_string(),
+            mechanism: None,
+            redirect_port: None,
+        };
+        let token = exchange_code(&config, "the-code", "http://127.0.0.1:1/callback", "the-verifier").await.unwrap();
+        assert_eq!(token.access_token.as_deref(), Some("a1"));
+        assert_eq!(token.refresh_token.as_deref(), Some("r1"));
+        let at = expires_at(&token).unwrap() - chrono::Utc::now().timestamp();
+        assert!((3590..=3600).contains(&at));
+
+        let body = &server.await.unwrap()[0];
+        assert!(has(body, "grant_type", "authorization_code"));
+        assert!(has(body, "code", "the-code"));
+        assert!(has(body, "redirect_uri", "http://127.0.0.1:1/callback"));
+        assert!(has(body, "code_verifier", "the-verifier"));
+        assert!(has(body, "client_id", "client"));
+        assert!(!form(body).iter().any(|(k, _)| k == "client_secret"));
+    }
+
+    #[tokio::test]
+    async fn refresh_stores_a_rotated_refresh_token() {
+        let (url, server) = token_server(vec![(200, r#"{"access_token":"a2","refresh_token":"r2","expires_in":3600}"#)]).await;
+        let pool = account_db(&url, chrono::Utc::now().timestamp() - 10).await;
+        let token = access_token(&pool, "acc").await.unwrap();
+        assert_eq!(token.token, "a2");
+        assert_eq!(token.mechanism, "OAUTHBEARER");
+
+        let body = &server.await.unwrap()[0];
+        assert!(has(body, "grant_type", "refresh_token"));
+        assert!(has(body, "refresh_token", "old-refresh"));
+        assert!(has(body, "client_secret", "secret"));
+        let (access, refresh, expires) = stored_tokens(&pool).await;
+        assert_eq!(access.as_deref(), Some("a2"));
+        assert_eq!(refresh.as_deref(), Some("r2"));
+        assert!(expires.unwrap() > chrono::Utc::now().timestamp() + 3500);
+    }
+
+    #[tokio::test]
+    async fn refresh_keeps_the_refresh_token_when_none_is_returned() {
+        let (url, server) = token_server(vec![(200, r#"{"access_token":"a2","expires_in":3600}"#)]).await;
+        let pool = account_db(&url, chrono::Utc::now().timestamp() - 10).await;
+        assert_eq!(access_token(&pool, "acc").await.unwrap().token, "a2");
+        server.await.unwrap();
+        let (access, refresh, _) = stored_tokens(&pool).await;
+        assert_eq!(access.as_deref(), Some("a2"));
+        assert_eq!(refresh.as_deref(), Some("old-refresh"));
+    }
+
+    #[tokio::test]
+    async fn refreshes_within_the_expiry_margin() {
+        let (url, server) = token_server(vec![(200, r#"{"access_token":"a2","expires_in":3600}"#)]).await;
+        let pool = account_db(&url, chrono::Utc::now().timestamp() + EXPIRY_MARGIN / 2).await;
+        assert_eq!(access_token(&pool, "acc").await.unwrap().token, "a2");
+        assert_eq!(server.await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn uses_the_stored_token_outside_the_margin() {
+        // Nothing listens here, so a refresh would fail
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        drop(listener);
+        let pool = account_db(&url, chrono::Utc::now().timestamp() + EXPIRY_MARGIN + 60).await;
+        assert_eq!(access_token(&pool, "acc").await.unwrap().token, "old-access");
+    }
+
+    #[tokio::test]
+    async fn reports_token_endpoint_errors() {
+        let (url, server) = token_server(vec![
+            (400, r#"{"error":"invalid_grant","error_description":"Token has been expired or revoked."}"#),
+            (400, r#"{"error":"invalid_client"}"#),
+            (500, r#"{}"#),
+        ])
+        .await;
+        let pool = account_db(&url, chrono::Utc::now().timestamp() - 10).await;
+        let err = access_token(&pool, "acc").await.unwrap_err();
+        assert!(err.starts_with("OAuth token error: invalid_grant Token has been expired or revoked."), "{}", err);
+        assert!(err.ends_with("sign in again if the grant was revoked"), "{}", err);
+        // The failed refresh leaves the stored tokens alone
+        assert_eq!(stored_tokens(&pool).await.1.as_deref(), Some("old-refresh"));
+
+        assert_eq!(request_token(&url, "client", None, &[]).await.unwrap_err(), "OAuth token error: invalid_client");
+        assert_eq!(
+            request_token(&url, "client", None, &[]).await.unwrap_err(),
+            "OAuth token error: HTTP 500 Internal Server Error without an access token"
+        );
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,644 @@
+/// Offline operation queue — move, copy, flag, delete and draft commands change the
+/// local rows at once and queue the server side in `pending_operations`. The queue is
+/// replayed in order whenever a connection can be made: by a background task, right
+/// after something is queued, and before every sync.
+///
+/// A message moved or copied locally gets a `pending_*` row id until the server
+/// reports its new UID (COPYUID). Later queued operations on such a row carry no
+/// server location; replaying the earlier operation fills it in. Messages that are
+/// gone from the server, or whose folder changed UIDVALIDITY, are conflicts: the local
+/// row is removed and `pending-conflict` is emitted.
+///
+/// Only the server refusing an operation counts towards MAX_ATTEMPTS; while it can't
+/// be reached, operations wait indefinitely.
+use tauri::{AppHandle, Emitter, Manager};
+use crate::db::{Account, DbState};
+use crate::imap::{local_email_id, ImapSession};
+use crate::imap_session::SessionLease;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::time::{sleep, Duration};
+
+/// Row id prefix of messages whose server location isn't known yet
+const PLACEHOLDER_PREFIX: &str = "pending_";
+/// How often the queue is retried while there is something in it
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// An operation the server keeps rejecting (NO/BAD) is dropped after this many attempts
+const MAX_ATTEMPTS: i64 = 5;
+
+/// Where a message is on the server. `folder`/`uid` are None while an earlier
+/// queued operation that moves or copies it hasn't been replayed yet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Location {
+    /// Local row id when the operation was queued
+    pub email_id: String,
+    pub folder: Option<String>,
+    pub uid: Option<u32>,
+    /// UIDVALIDITY the UID belongs to, when known
+    pub uid_validity: Option<u32>,
+}
+
+/// One message of a queued move or copy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferItem {
+    pub source: Location,
+    /// Local row standing for the message in the target folder
+    pub local_id: String,
+    /// The MOVE/COPY was sent in an earlier attempt, but its result never came back
+    #[serde(default)]
+    pub sent: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Operation {
+    Transfer { items: Vec<TransferItem>, target: String, is_move: bool },
+    Flags { messages: Vec<Location>, read: Option<bool>, flagged: Option<bool>, answered: Option<bool> },
+    Expunge { messages: Vec<Location> },
+    AppendDraft { email_id: String, message: String },
+}
+
+impl Operation {
+    fn kind(&self) -> &'static str {
+        match self {
+            Operation::Transfer { is_move: true, .. } => "move",
+            Operation::Transfer { is_move: false, .. } => "copy",
+            Operation::Flags { .. } => "flags",
+            Operation::Expunge { .. } => "expunge",
+            Operation::AppendDraft { .. } => "append_draft",
+        }
+    }
+
+    /// Local rows the operation is about
+    fn email_ids(&self) -> Vec<String> {
+        match self {
+            Operation::Transfer { items, .. } => items.iter().map(|i| i.local_id.clone()).collect(),
+            Operation::Flags { messages, .. } | Operation::Expunge { messages } => messages.iter().map(|l| l.email_id.clone()).collect(),
+            Operation::AppendDraft { email_id, .. } => vec![email_id.clone()],
+        }
+    }
+
+    fn locations_mut(&mut self) -> Vec<&mut Location> {
+        match self {
+            Operation::Transfer { items, .. } => items.iter_mut().map(|i| &mut i.source).collect(),
+            Operation::Flags { messages, .. } | Operation::Expunge { messages } => messages.iter_mut().collect(),
+            Operation::AppendDraft { .. } => vec![],
+        }
+    }
+}
+
+/// A local row and, unless it only exists locally, where its message is on the server
+pub struct LocalEmail {
+    pub id: String,
+    pub folder: String,
+    pub uid: u32,
+    pub location: Option<Location>,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct PendingOperation {
+    pub id: i64,
+    pub kind: String,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct PendingConflictPayload {
+    pub account_id: String,
+    pub operation: String,
+    /// Local rows removed because their message is gone from the server
+    pub email_ids: Vec<String>,
+    pub reason: String,
+}
+
+/// Wakes the replay task early, e.g. right after an operation was queued
+static REPLAY: Notify = Notify::const_new();
+/// One replay at a time per account; later callers wait for it
+static REPLAY_LOCKS: Mutex<Vec<(String, Arc<tokio::sync::Mutex<()>>)>> = Mutex::new(Vec::new());
+
+fn replay_lock(account_id: &str) -> Arc<tokio::sync::Mutex<()>> {
+    let mut locks = REPLAY_LOCKS.lock().unwrap();
+    if let Some((_, lock)) = locks.iter().find(|(id, _)| id == account_id) {
+        return lock.clone();
+    }
+    let lock = Arc::new(tokio::sync::Mutex::new(()));
+    locks.push((account_id.to_string(), lock.clone()));
+    lock
+}
+
+pub fn placeholder_id() -> String {
+    format!("{}{}", PLACEHOLDER_PREFIX, uuid::Uuid::new_v4().simple())
+}
+
+fn is_placeholder(email_id: &str) -> bool {
+    email_id.starts_with(PLACEHOLDER_PREFIX)
+}
+
+/// Load local rows with their server location. Fails for ids that don't exist.
+pub async fn load_local(pool: &SqlitePool, account_id: &str, email_ids: &[String]) -> Result<Vec<LocalEmail>, String> {
+    let mut emails = Vec::new();
+    for id in email_ids {
+        let (folder, uid) = sqlx::query_as::<_, (String, i64)>(
+            "SELECT folder, uid FROM emails WHERE id = $1 AND account_id = $2"
+        )
+        .bind(id)
+        .bind(account_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?
+        .ok_or_else(|| format!("Email not found: {}", id))?;
+        let uid = uid as u32;
+
+        let location = if *id == local_email_id(account_id, &folder, uid) {
+            let uid_validity: Option<i64> = sqlx::query_scalar(
+                "SELECT uid_validity FROM folder_sync_state WHERE account_id = $1 AND folder = $2"
+            )
+            .bind(account_id)
+            .bind(&folder)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| format!("DB error: {}", e))?;
+            Some(Location { email_id: id.clone(), folder: Some(folder.clone()), uid: Some(uid), uid_validity: uid_validity.map(|v| v as u32) })
+        } else if is_placeholder(id) {
+            Some(Location { email_id: id.clone(), folder: None, uid: None, uid_validity: None })
+        } else {
+            // Unsynced drafts and sent copies have nothing on the server
+            None
+        };
+        emails.push(LocalEmail { id: id.clone(), folder, uid, location });
+    }
+    Ok(emails)
+}
+
+/// Queue a server operation and wake the replay task
+pub async fn enqueue(app: &AppHandle, account_id: &str, operation: &Operation) -> Result<(), String> {
+    let pool = app.state::<DbState>().pool.clone();
+    let payload = serde_json::to_string(operation).map_err(|e| format!("JSON error: {}", e))?;
+    sqlx::query("INSERT INTO pending_operations (account_id, kind, payload, created_at) VALUES ($1, $2, $3, $4)")
+        .bind(account_id)
+        .bind(operation.kind())
+        .bind(&payload)
+        .bind(chrono::Utc::now().to_rfc3339())
+        .execute(&pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    emit_count(app, &pool, account_id).await;
+    REPLAY.notify_one();
+    Ok(())
+}
+
+async fn emit_count(app: &AppHandle, pool: &SqlitePool, account_id: &str) {
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pending_operations WHERE account_id = $1")
+        .bind(account_id)
+        .fetch_one(pool)
+        .await
+        .unwrap_or(0);
+    let _ = app.emit("pending-operations", serde_json::json!({ "account_id": account_id, "count": count }));
+}
+
+/// Replay queued operations of every account, now and whenever woken or every RETRY_INTERVAL
+pub fn start_replay_task(app: AppHandle) {
+    let token = crate::tasks::token();
+    crate::tasks::spawn("pending-operations", async move {
+        loop {
+            let account_ids: Vec<String> = sqlx::query_scalar("SELECT DISTINCT account_id FROM pending_operations")
+                .fetch_all(&app.state::<DbState>().pool)
+                .await
+                .unwrap_or_default();
+            for account_id in account_ids {
+                if token.is_cancelled() {
+                    return;
+                }
+                replay_account(&app, &account_id).await;
+            }
+            tokio::select! {
+                _ = sleep(RETRY_INTERVAL) => {}
+                _ = REPLAY.notified() => {}
+                _ = token.cancelled() => return,
+            }
+        }
+    });
+}
+
+/// Replay the account's queue in order until it is empty or the server can't be reached.
+/// If a replay is already running, waits for it and then replays what is left, so
+/// callers such as `sync_emails` only go on once queued changes are on the server.
+pub async fn replay_account(app: &AppHandle, account_id: &str) {
+    let lock = replay_lock(account_id);
+    // Released when dropped, also if `replay_queue` panics
+    let _replaying = lock.lock().await;
+    if let Err(e) = replay_queue(app, account_id).await {
+        log::info!("[QUEUE] {}: {}", account_id, e);
+    }
+}
+
+async fn replay_queue(app: &AppHandle, account_id: &str) -> Result<(), String> {
+    let pool = app.state::<DbState>().pool.clone();
+    let mut session = None;
+
+    loop {
+        if crate::tasks::is_shutting_down() {
+            return Ok(());
+        }
+        // One at a time: replaying an operation can fill in locations of the next ones
+        let next = sqlx::query_as::<_, (i64, String, i64)>(
+            "SELECT id, payload, attempts FROM pending_operations WHERE account_id = $1 ORDER BY id LIMIT 1"
+        )
+        .bind(account_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+        let Some((op_id, payload, attempts)) = next else { break };
+
+        let operation: Operation = match serde_json::from_str(&payload) {
+            Ok(operation) => operation,
+            Err(e) => {
+                log::warn!("[QUEUE] Dropping unreadable operation {}: {}", op_id, e);
+                remove_operation(&pool, op_id).await?;
+                continue;
+            }
+        };
+
+        let account = crate::imap_folders::load_account(&pool, account_id).await?;
+        if session.is_none() {
+            // Still offline: leave the queue as it is for the next attempt
+            session = Some(crate::imap_session::acquire(&pool, &account).await?);
+        }
+        let _job = crate::tasks::begin_job()?;
+        let lease = session.as_mut().expect("session was just acquired");
+        match execute(app, &pool, lease, &account, op_id, &operation).await {
+            Ok(()) => {
+                remove_operation(&pool, op_id).await?;
+                log::info!("[QUEUE] Replayed {} for {}", operation.kind(), account.email);
+            }
+            Err(e) => {
+                // The session may be in any state now; a dropped lease closes it
+                drop(session);
+                // Connection trouble says nothing about the operation itself
+                let rejected = crate::imap::is_rejection(&e);
+                if rejected && attempts + 1 >= MAX_ATTEMPTS {
+                    log::warn!("[QUEUE] Giving up on {} for {}: {}", operation.kind(), account.email, e);
+                    give_up(app, &pool, account_id, op_id, &e).await?;
+                } else {
+                    sqlx::query("UPDATE pending_operations SET attempts = attempts + $1, last_error = $2 WHERE id = $3")
+                        .bind(rejected as i64)
+                        .bind(&e)
+                        .bind(op_id)
+                        .execute(&pool)
+                        .await
+                        .map_err(|e| format!("DB error: {}", e))?;
+                }
+                emit_count(app, &pool, account_id).await;
+                return Err(e);
+            }
+        }
+        emit_count(app, &pool, account_id).await;
+    }
+
+    if let Some(lease) = session {
+        lease.release();
+    }
+    Ok(())
+}
+
+async fn remove_operation(pool: &SqlitePool, op_id: i64) -> Result<(), String> {
+    sqlx::query("DELETE FROM pending_operations WHERE id = $1")
+        .bind(op_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    Ok(())
+}
+
+async fn save_operation(pool: &SqlitePool, op_id: i64, operation: &Operation) -> Result<(), String> {
+    let payload = serde_json::to_string(operation).map_err(|e| format!("JSON error: {}", e))?;
+    sqlx::query("UPDATE pending_operations SET payload = $1 WHERE id = $2")
+        .bind(&payload)
+        .bind(op_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    Ok(())
+}
+
+/// Make the next sync of these folders start from scratch, so their local rows fall
+/// back in line with the server
+async fn resync_folders(pool: &SqlitePool, account_id: &str, folders: &HashSet<String>) -> Result<(), String> {
+    for folder in folders {
+        sqlx::query("DELETE FROM folder_sync_state WHERE account_id = $1 AND folder = $2")
+            .bind(account_id)
+            .bind(folder)
+            .execute(pool)
+            .await
+            .map_err(|e| format!("DB error: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Drop an operation the server keeps rejecting; the folders involved are resynced
+async fn give_up(app: &AppHandle, pool: &SqlitePool, account_id: &str, op_id: i64, error: &str) -> Result<(), String> {
+    // Read back, as a transfer may have made progress since it was loaded
+    let payload: Option<String> = sqlx::query_scalar("SELECT payload FROM pending_operations WHERE id = $1")
+        .bind(op_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    let Some(mut operation) = payload.and_then(|p| serde_json::from_str::<Operation>(&p).ok()) else {
+        return remove_operation(pool, op_id).await;
+    };
+    let mut folders: HashSet<String> = operation.locations_mut().into_iter().filter_map(|l| l.folder.clone()).collect();
+    if let Operation::Transfer { target, .. } = &operation {
+        folders.insert(target.clone());
+    }
+    resync_folders(pool, account_id, &folders).await?;
+    remove_operation(pool, op_id).await?;
+    let _ = app.emit("pending-conflict", PendingConflictPayload {
+        account_id: account_id.to_string(),
+        operation: operation.kind().to_string(),
+        email_ids: operation.email_ids(),
+        reason: error.to_string(),
+    });
+    Ok(())
+}
+
+/// Fill in `location` for later queued operations that refer to `email_id`.
+/// Returns whether any later operation refers to it.
+async fn resolve_later(pool: &SqlitePool, account_id: &str, op_id: i64, email_id: &str, folder: &str, uid: u32) -> Result<bool, String> {
+    let later = sqlx::query_as::<_, (i64, String)>(
+        "SELECT id, payload FROM pending_operations WHERE account_id = $1 AND id > $2 ORDER BY id"
+    )
+    .bind(account_id)
+    .bind(op_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+
+    let mut referenced = false;
+    for (id, payload) in later {
+        let Ok(mut operation) = serde_json::from_str::<Operation>(&payload) else { continue };
+        let mut changed = false;
+        for location in operation.locations_mut() {
+            if location.email_id != email_id {
+                continue;
+            }
+            referenced = true;
+            if location.folder.is_none() {
+                location.folder = Some(folder.to_string());
+                location.uid = Some(uid);
+                changed = true;
+            }
+        }
+        if changed {
+            save_operation(pool, id, &operation).await?;
+        }
+    }
+    Ok(referenced)
+}
+
+/// The server location of a message is now known. Later operations on it get the
+/// location; if there are none, a placeholder row takes its real id.
+async fn settle(pool: &SqlitePool, account_id: &str, op_id: i64, email_id: &str, folder: &str, uid: u32) -> Result<(), String> {
+    if resolve_later(pool, account_id, op_id, email_id, folder, uid).await? || !is_placeholder(email_id) {
+        return Ok(());
+    }
+    crate::imap::rekey_local_email(pool, email_id, &local_email_id(account_id, folder, uid), folder, uid).await
+}
+
+/// Locations grouped by server folder, plus the ones without a known location
+fn group_locations(locations: Vec<Location>) -> (BTreeMap<String, Vec<Location>>, Vec<Location>) {
+    let mut groups: BTreeMap<String, Vec<Location>> = BTreeMap::new();
+    let mut unplaced = Vec::new();
+    for location in locations {
+        match (&location.folder, location.uid) {
+            (Some(folder), Some(_)) => groups.entry(folder.clone()).or_default().push(location),
+            _ => unplaced.push(location),
+        }
+    }
+    (groups, unplaced)
+}
+
+/// SELECT the folder and split `locations` into messages still there and ones that
+/// are gone (expunged, or their UIDVALIDITY no longer applies)
+async fn select_present(
+    pool: &SqlitePool,
+    session: &mut ImapSession,
+    account: &Account,
+    folder: &str,
+    locations: Vec<Location>,
+) -> Result<(Vec<Location>, Vec<Location>), String> {
+    let mailbox_name = crate::imap_folders::resolve_mailbox(pool, account, folder).await?;
+    let mailbox = crate::imap::select_mailbox(session, &mailbox_name).await?;
+    let (valid, mut gone): (Vec<Location>, Vec<Location>) = locations
+        .into_iter()
+        .partition(|l| l.uid_validity.map_or(true, |v| Some(v) == mailbox.uid_validity));
+    if valid.is_empty() {
+        return Ok((valid, gone));
+    }
+
+    let uids: Vec<u32> = valid.iter().filter_map(|l| l.uid).collect();
+    let existing = session
+        .uid_search(format!("UID {}", crate::imap::format_uid_set(&uids)))
+        .await
+        .map_err(|e| format!("Search error: {}", e))?;
+    let (present, missing): (Vec<Location>, Vec<Location>) = valid
+        .into_iter()
+        .partition(|l| l.uid.is_some_and(|uid| existing.contains(&uid)));
+    gone.extend(missing);
+    Ok((present, gone))
+}
+
+/// Remove the local rows of messages an operation couldn't reach and tell the frontend
+async fn conflict(app: &AppHandle, pool: &SqlitePool, account_id: &str, operation: &str, email_ids: Vec<String>, reason: &str) -> Result<(), String> {
+    if email_ids.is_empty() {
+        return Ok(());
+    }
+    log::warn!("[QUEUE] {} of {} messages skipped: {}", operation, email_ids.len(), reason);
+    crate::imap::delete_local_emails(pool, &email_ids).await?;
+    let _ = app.emit("pending-conflict", PendingConflictPayload {
+        account_id: account_id.to_string(),
+        operation: operation.to_string(),
+        email_ids,
+        reason: reason.to_string(),
+    });
+    Ok(())
+}
+
+/// Transfer items sent in an earlier attempt whose result never came back. Sending them
+/// again could copy twice, and a finished move would look like a vanished message, so
+/// their local rows are dropped and both folders resynced instead.
+async fn forget_unconfirmed(pool: &SqlitePool, account_id: &str, items: &[TransferItem], target: &str) -> Result<(), String> {
+    if items.is_empty() {
+        return Ok(());
+    }
+    log::warn!("[QUEUE] Result of an earlier transfer of {} messages is unknown, resyncing", items.len());
+    let mut folders: HashSet<String> = items.iter().filter_map(|i| i.source.folder.clone()).collect();
+    folders.insert(target.to_string());
+    resync_folders(pool, account_id, &folders).await?;
+    let local_ids: Vec<String> = items.iter().map(|i| i.local_id.clone()).collect();
+    crate::imap::delete_local_emails(pool, &local_ids).await
+}
+
+const GONE: &str = "the message is no longer on the server";
+const UNPLACED: &str = "the server did not report where an earlier move put the message";
+
+async fn execute(
+    app: &AppHandle,
+    pool: &SqlitePool,
+    session: &mut SessionLease,
+    account: &Account,
+    op_id: i64,
+    operation: &Operation,
+) -> Result<(), String> {
+    let capabilities = session.capabilities.clone();
+    let account_id = account.id.as_str();
+    let kind = operation.kind();
+    match operation {
+        Operation::Transfer { items, target, is_move } => {
+            let target_mailbox = crate::imap_folders::resolve_mailbox(pool, account, target).await?;
+            let local_ids: BTreeMap<String, String> = items.iter()
+                .map(|i| (i.source.email_id.clone(), i.local_id.clone()))
+                .collect();
+            let local_of = |locations: &[Location]| -> Vec<String> {
+                locations.iter().filter_map(|l| local_ids.get(&l.email_id).cloned()).collect()
+            };
+
+            let (unconfirmed, mut remaining): (Vec<TransferItem>, Vec<TransferItem>) = items.iter().cloned().partition(|i| i.sent);
+            forget_unconfirmed(pool, account_id, &unconfirmed, target).await?;
+            // Progress is saved in the payload: which items are in flight, and which are done
+            let save = |remaining: Vec<TransferItem>| Operation::Transfer { items: remaining, target: target.clone(), is_move: *is_move };
+
+            let (groups, unplaced) = group_locations(remaining.iter().map(|i| i.source.clone()).collect());
+            conflict(app, pool, account_id, kind, local_of(&unplaced), UNPLACED).await?;
+            // Already in the target, e.g. moved away and back while offline: nothing to
+            // send, the local row takes the id of the message where it is
+            for item in &remaining {
+                if let (Some(folder), Some(uid)) = (&item.source.folder, item.source.uid) {
+                    if folder == target {
+                        settle(pool, account_id, op_id, &item.local_id, target, uid).await?;
+                    }
+                }
+            }
+            remaining.retain(|i| i.source.folder.as_ref().is_some_and(|f| f != target) && i.source.uid.is_some());
+            save_operation(pool, op_id, &save(remaining.clone())).await?;
+            for (folder, locations) in groups {
+                if folder == *target {
+                    continue;
+                }
+                let (present, gone) = select_present(pool, session, account, &folder, locations).await?;
+                conflict(app, pool, account_id, kind, local_of(&gone), GONE).await?;
+                let handled: HashSet<&str> = present.iter().chain(&gone).map(|l| l.email_id.as_str()).collect();
+                if present.is_empty() {
+                    remaining.retain(|i| !handled.contains(i.source.email_id.as_str()));
+                    save_operation(pool, op_id, &save(remaining.clone())).await?;
+                    continue;
+                }
+
+                for item in remaining.iter_mut().filter(|i| present.iter().any(|l| l.email_id == i.source.email_id)) {
+                    item.sent = true;
+                }
+                save_operation(pool, op_id, &save(remaining.clone())).await?;
+                let uids: Vec<u32> = present.iter().filter_map(|l| l.uid).collect();
+                let uid_map = match crate::imap_actions::transfer(session, &capabilities, &uids, &target_mailbox, *is_move).await {
+                    Ok(uid_map) => uid_map,
+                    Err(e) => {
+                        // The MOVE/COPY itself refused: nothing happened, so it is safe to send
+                        // again. A refused EXPUNGE after a COPY that went through is not.
+                        let refused = e.starts_with("UID MOVE") || e.starts_with("UID COPY");
+                        if refused && crate::imap::is_rejection(&e) {
+                            remaining.iter_mut().for_each(|i| i.sent = false);
+                            save_operation(pool, op_id, &save(remaining)).await?;
+                        }
+                        return Err(e);
+                    }
+                };
+                for location in &present {
+                    let Some(local_id) = local_ids.get(&location.email_id) else { continue };
+                    match location.uid.and_then(|uid| uid_map.get(&uid)) {
+                        Some(&new_uid) => settle(pool, account_id, op_id, local_id, target, new_uid).await?,
+                        // No COPYUID: the target folder's next sync downloads the message
+                        None => crate::imap::delete_local_emails(pool, std::slice::from_ref(local_id)).await?,
+                    }
+                }
+                remaining.retain(|i| !handled.contains(i.source.email_id.as_str()));
+                save_operation(pool, op_id, &save(remaining.clone())).await?;
+            }
+        }
+        Operation::Flags { messages, read, flagged, answered } => {
+            let items = crate::imap_actions::flag_store_items(*read, *flagged, *answered);
+            let (groups, unplaced) = group_locations(messages.clone());
+            conflict(app, pool, account_id, kind, unplaced.into_iter().map(|l| l.email_id).collect(), UNPLACED).await?;
+            for (folder, locations) in groups {
+                let (present, gone) = select_present(pool, session, account, &folder, locations).await?;
+                conflict(app, pool, account_id, kind, gone.into_iter().map(|l| l.email_id).collect(), GONE).await?;
+                if present.is_empty() {
+                    continue;
+                }
+                let set = crate::imap::format_uid_set(&present.iter().filter_map(|l| l.uid).collect::<Vec<_>>());
+                for item in &items {
+                    crate::imap_actions::store_flags(session, &set, item).await?;
+                }
+                for location in &present {
+                    settle(pool, account_id, op_id, &location.email_id, &folder, location.uid.unwrap_or(0)).await?;
+                }
+            }
+        }
+        Operation::Expunge { messages } => {
+            let (groups, unplaced) = group_locations(messages.clone());
+            if !unplaced.is_empty() {
+                log::warn!("[QUEUE] {} messages to expunge have no known location", unplaced.len());
+            }
+            for (folder, locations) in groups {
+                // Messages already gone need no expunging
+                let (present, _gone) = select_present(pool, session, account, &folder, locations).await?;
+                if !present.is_empty() {
+                    let uids: Vec<u32> = present.iter().filter_map(|l| l.uid).collect();
+                    crate::imap_actions::expunge_uids(session, &capabilities, &uids).await?;
+                    log::info!("[QUEUE] Permanently deleted {} emails from {}", uids.len(), folder);
+                }
+            }
+        }
+        Operation::AppendDraft { email_id, message } => {
+            // Discarded before it reached the server
+            let exists: Option<i64> = sqlx::query_scalar("SELECT 1 FROM emails WHERE id = $1")
+                .bind(email_id)
+                .fetch_optional(pool)
+                .await
+                .map_err(|e| format!("DB error: {}", e))?;
+            if exists.is_none() {
+                return Ok(());
+            }
+            let drafts_mailbox = crate::imap_folders::resolve_mailbox(pool, account, "Drafts").await?;
+            let mut missing = false;
+            let appended = crate::imap::append_raw(session, &drafts_mailbox, message, |r| missing |= crate::imap::is_try_create(r)).await;
+            if let Err(e) = appended {
+                if !missing {
+                    return Err(e);
+                }
+                // No Drafts folder on the server yet — create it
+                log::warn!("Append to {} failed ({}), creating it", drafts_mailbox, e);
+                crate::imap::run_raw(session, &format!("CREATE {}", crate::imap::quote_mailbox(&drafts_mailbox)), |_| {}).await?;
+                crate::imap::append_raw(session, &drafts_mailbox, message, |_| {}).await?;
+            }
+            log::info!("Draft {} saved to folder: {}", email_id, drafts_mailbox);
+        }
+    }
+    Ok(())
+}
+
+/// Tauri command: operations waiting to be replayed on the server, oldest first
+#[tauri::command]
+pub async fn get_pending_operations(app: AppHandle, account_id: String) -> Result<Vec<PendingOperation>, String> {
+    let state = app.state::<DbState>();
+    sqlx::query_as::<_, PendingOperation>(
+        "SELECT id, kind, attempts, last_error, created_at FROM pending_operations WHERE account_id = $1 ORDER BY id"
+    )
+    .bind(&account_id)
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))
+}
@@ -1,8 +1,51 @@
 use tauri::{AppHandle, Manager};
 use crate::db::DbState;
-use lettre::{Message, SmtpTransport, Transport};
-use lettre::transport::smtp::authentication::Credentials;
+use crate::oauth::AuthMethod;
+use crate::tls::{Security, TlsSettings};
+use lettre::Message;
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::transport::smtp::client::SmtpConnection;
+use lettre::transport::smtp::extension::ClientId;
 use lettre::message::{header::ContentType, MultiPart, SinglePart, Attachment};
+use std::time::Duration;
+
+/// Give up on an SMTP server that doesn't answer within this time
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Open an SMTP connection with the account's security mode. When pinning is on,
+/// the server certificate (DER) is returned for checking before credentials are sent.
+fn open_connection(host: &str, port: u16, security: Security, tls: &TlsSettings) -> Result<(SmtpConnection, Option<Vec<u8>>), String> {
+    let hello = ClientId::default();
+    let conn = match security {
+        Security::Tls => {
+            let params = tls.smtp_parameters(host)?;
+            SmtpConnection::connect((host, port), Some(SMTP_TIMEOUT), &hello, Some(&params), None)
+                .map_err(|e| format!("SMTP connect error: {}", e))?
+        }
+        Security::StartTls => {
+            let mut conn = SmtpConnection::connect((host, port), Some(SMTP_TIMEOUT), &hello, None, None)
+                .map_err(|e| format!("SMTP connect error: {}", e))?;
+            if !conn.can_starttls() {
+                return Err(format!("SMTP server {}:{} does not offer STARTTLS", host, port));
+            }
+            conn.starttls(&tls.smtp_parameters(host)?, &hello)
+                .map_err(|e| format!("STARTTLS error: {}", e))?;
+            conn
+        }
+        Security::None => {
+            log::warn!("[SMTP] {}:{} is configured without encryption", host, port);
+            SmtpConnection::connect((host, port), Some(SMTP_TIMEOUT), &hello, None, None)
+                .map_err(|e| format!("SMTP connect error: {}", e))?
+        }
+    };
+
+    let der = if tls.pinning && security != Security::None {
+        Some(conn.peer_certificate().map_err(|e| format!("TLS error: {}", e))?)
+    } else {
+        None
+    };
+    Ok((conn, der))
+}
 
 #[tauri::command]
 pub async fn send_email(
@@ -16,7 +59,7 @@ pub async fn send_email(
     let state = app.state::<DbState>();
 
     let account = sqlx::query_as::<_, crate::db::Account>(
-        "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port FROM accounts WHERE id = $1"
+        "SELECT id, full_name, email, password, imap_host, imap_port, smtp_host, smtp_port, imap_security, smtp_security, ca_cert, tls_pinning, auth_method FROM accounts WHERE id = $1"
     )
     .bind(&account_id)
     .fetch_optional(&state.pool)
@@ -26,7 +69,7 @@ pub async fn send_email(
 
     let smtp_host = account.smtp_host.as_deref().ok_or("SMTP host not configured")?;
     let from_email = &account.email;
-    let password = account.password.as_deref().ok_or("Password not configured")?;
+    let auth = AuthMethod::from_account(&account)?;
     let full_name = account.full_name.as_deref().unwrap_or(from_email);
 
     log::info!("Sending email from {} to {} via {} (attachments: {})", 
@@ -38,6 +81,9 @@ pub async fn send_email(
     let to_addr = to.parse().map_err(|e| format!("Invalid to: {}", e))?;
 
     let attachment_paths = attachments.unwrap_or_default();
+    // Set here rather than by the relay, so the Sent row can be threaded
+    let domain = from_email.rsplit('@').next().unwrap_or("localhost");
+    let message_id = format!("<{}@{}>", uuid::Uuid::new_v4(), domain);
 
     let email = if attachment_paths.is_empty() {
         // Simple text email
@@ -45,6 +91,7 @@ pub async fn send_email(
             .from(from_addr)
             .to(to_addr)
             .subject(&subject)
+            .message_id(Some(message_id.clone()))
             .header(ContentType::TEXT_PLAIN)
             .body(body.clone())
             .map_err(|e| format!("Failed to build email: {}", e))?
@@ -83,19 +130,40 @@ pub async fn send_email(
             .from(from_addr)
             .to(to_addr)
             .subject(&subject)
+            .message_id(Some(message_id.clone()))
             .multipart(multipart)
             .map_err(|e| format!("Failed to build email: {}", e))?
     };
 
-    let creds = Credentials::new(from_email.clone(), password.to_string());
-
-    let mailer = SmtpTransport::relay(smtp_host)
-        .map_err(|e| format!("SMTP relay error: {}", e))?
-        .credentials(creds)
-        .build();
+    // lettre speaks XOAUTH2 but not OAUTHBEARER, so OAuth2 accounts always use XOAUTH2 here
+    let (mechanisms, creds) = match auth {
+        AuthMethod::Password(password) => (
+            vec![Mechanism::Plain, Mechanism::Login],
+            Credentials::new(from_email.clone(), password),
+        ),
+        AuthMethod::OAuth2 { account_id } => {
+            let token = crate::oauth::access_token(&state.pool, &account_id).await?;
+            (vec![Mechanism::Xoauth2], Credentials::new(from_email.clone(), token.token))
+        }
+    };
+    let security = Security::parse(account.smtp_security.as_deref())?;
+    let smtp_port = account.smtp_port.map(|p| p as u16).unwrap_or(security.default_smtp_port());
+    let tls = TlsSettings::from_account(&account);
+
+    // Connect and secure the channel first, so a pinned certificate is checked before AUTH
+    let host = smtp_host.to_string();
+    let (conn, der) = tokio::task::spawn_blocking(move || open_connection(&host, smtp_port, security, &tls))
+        .await
+        .map_err(|e| format!("Thread error: {}", e))??;
+    if let Some(der) = der {
+        crate::tls::verify_pin(&state.pool, smtp_host, smtp_port, Some(der)).await?;
+    }
 
     let result = tokio::task::spawn_blocking(move || {
-        mailer.send(&email)
+        let mut conn = conn;
+        conn.auth(&mechanisms, &creds)?;
+        conn.send(email.envelope(), &email.formatted())?;
+        conn.quit()
     }).await.map_err(|e| format!("Thread error: {}", e))?;
 
     match result {
@@ -103,8 +171,8 @@ pub async fn send_email(
             log::info!("Email sent successfully to {}", to);
             let email_id = format!("sent_{}", chrono::Utc::now().timestamp_millis());
             let _ = sqlx::query(
-                r#"INSERT OR REPLACE INTO emails (id, uid, account_id, folder, subject, sender, sender_email, date, snippet, body, read)
-                   VALUES ($1, 0, $2, 'Sent', $3, $4, $5, $6, $7, $8, 1)"#
+                r#"INSERT OR REPLACE INTO emails (id, uid, account_id, folder, subject, sender, sender_email, date, snippet, body, read, body_cached, message_id)
+                   VALUES ($1, 0, $2, 'Sent', $3, $4, $5, $6, $7, $8, 1, 1, $9)"#
             )
             .bind(&email_id)
             .bind(&account_id)
@@ -114,8 +182,11 @@ pub async fn send_email(
             .bind(chrono::Utc::now().to_rfc2822())
             .bind(&body.chars().take(120).collect::<String>())
             .bind(&body)
+            .bind(&message_id)
             .execute(&state.pool)
             .await;
+            let _ = crate::addresses::store_addresses(&state.pool, &email_id, &crate::addresses::outgoing(full_name, from_email, &to)).await;
+            let _ = crate::threads::thread_account(&state.pool, &account_id).await;
 
             Ok(())
         },
@@ -0,0 +1,116 @@
+/// Background work registry — every long-running task is spawned through `spawn` and
+/// watches a cancellation token derived from one app-wide shutdown token. Short jobs
+/// that write to the database (sync, triage, backfill batches) hold a `JobGuard`, so
+/// shutdown waits for them instead of cutting them off halfway.
+/// `begin_shutdown` and `shutdown` are called from the window-close and exit handlers in lib.rs.
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// How long shutdown waits for tasks and jobs before giving up on them
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Task {
+    name: String,
+    handle: JoinHandle<()>,
+}
+
+static SHUTDOWN: OnceLock<CancellationToken> = OnceLock::new();
+/// Spawned tasks that may still be running
+static TASKS: Mutex<Vec<Task>> = Mutex::new(Vec::new());
+/// Jobs currently holding a `JobGuard`
+static ACTIVE_JOBS: AtomicUsize = AtomicUsize::new(0);
+
+fn root() -> &'static CancellationToken {
+    SHUTDOWN.get_or_init(CancellationToken::new)
+}
+
+/// A token that is cancelled when the app shuts down (or earlier, if the caller cancels it)
+pub fn token() -> CancellationToken {
+    root().child_token()
+}
+
+pub fn is_shutting_down() -> bool {
+    root().is_cancelled()
+}
+
+/// Spawn a background task and keep its handle so shutdown can wait for it.
+/// Long-running tasks must stop when their token is cancelled.
+pub fn spawn<F>(name: &str, future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let handle = tokio::spawn(future);
+    let mut tasks = TASKS.lock().unwrap();
+    tasks.retain(|t| !t.handle.is_finished());
+    tasks.push(Task { name: name.to_string(), handle });
+}
+
+/// Held for the duration of a job that must not be interrupted halfway
+pub struct JobGuard(());
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        ACTIVE_JOBS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Register a job; refused once shutdown has begun
+pub fn begin_job() -> Result<JobGuard, String> {
+    ACTIVE_JOBS.fetch_add(1, Ordering::SeqCst);
+    let guard = JobGuard(());
+    if is_shutting_down() {
+        return Err("Shutting down".to_string());
+    }
+    Ok(guard)
+}
+
+/// Cancel all background work. Safe to call more than once.
+pub fn begin_shutdown() {
+    if !is_shutting_down() {
+        log::info!("[TASKS] Shutting down background tasks");
+        root().cancel();
+    }
+}
+
+/// Cancel all background work and wait (bounded by SHUTDOWN_TIMEOUT) for tasks to
+/// finish, running jobs to complete and pooled IMAP sessions to log out
+pub async fn shutdown() {
+    begin_shutdown();
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+
+    // Tasks may spawn follow-ups (e.g. session logouts) while winding down
+    loop {
+        let tasks: Vec<Task> = TASKS.lock().unwrap().drain(..).collect();
+        if tasks.is_empty() {
+            break;
+        }
+        for task in tasks {
+            let abort = task.handle.abort_handle();
+            match tokio::time::timeout_at(deadline, task.handle).await {
+                Ok(Err(e)) if e.is_panic() => log::warn!("[TASKS] {} panicked: {}", task.name, e),
+                Ok(_) => {}
+                Err(_) => {
+                    log::warn!("[TASKS] {} did not stop in time", task.name);
+                    abort.abort();
+                }
+            }
+        }
+    }
+
+    while ACTIVE_JOBS.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        sleep(Duration::from_millis(50)).await;
+    }
+    let unfinished = ACTIVE_JOBS.load(Ordering::SeqCst);
+    if unfinished > 0 {
+        log::warn!("[TASKS] {} jobs still running at exit", unfinished);
+    }
+
+    if tokio::time::timeout_at(deadline, crate::imap_session::close_all()).await.is_err() {
+        log::warn!("[TASKS] IMAP logout did not finish in time");
+    }
+    log::info!("[TASKS] Background tasks stopped");
+}
@@ -0,0 +1,644 @@
+/// Conversation threading — messages are grouped with the JWZ algorithm
+/// (https://www.jwz.org/doc/threading.html) over Message-ID, In-Reply-To and References.
+/// Gmail thread ids and reply subjects ("Re: ...") join what the headers leave apart.
+/// A row's `thread_id` is the Message-ID at the top of its conversation; it is
+/// recomputed for the whole account once a sync, backfill or search has stored its messages.
+use tauri::{AppHandle, Manager};
+use crate::db::{DbState, Email};
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+
+/// Reply markers in various languages; "Fwd:" starts a new conversation
+const REPLY_PREFIXES: &[&str] = &["re", "aw", "sv", "vs", "antw", "ref", "rif", "res", "odp", "ynt", "回复", "答复"];
+
+/// What threading needs from a stored message
+#[derive(Debug, Clone, Default, sqlx::FromRow)]
+pub struct MessageRef {
+    pub id: String,
+    pub message_id: Option<String>,
+    pub in_reply_to: Option<String>,
+    /// References, space separated, oldest first
+    pub refs: Option<String>,
+    pub subject: Option<String>,
+    pub date: Option<String>,
+    pub gm_thrid: Option<i64>,
+    /// As last stored
+    pub thread_id: Option<String>,
+}
+
+/// One conversation: (index into the input, depth) depth-first, replies after what they answer
+#[derive(Debug, Clone)]
+pub struct ThreadTree {
+    pub id: String,
+    pub messages: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ThreadSummary {
+    pub thread_id: String,
+    /// Subject of the first message
+    pub subject: String,
+    pub message_count: usize,
+    pub unread_count: usize,
+    pub flagged: bool,
+    /// Sender names in order of their first message
+    pub participants: Vec<String>,
+    pub folders: Vec<String>,
+    pub latest: Email,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ThreadMessage {
+    #[serde(flatten)]
+    pub email: Email,
+    /// Reply depth, 0 for the top of the conversation
+    pub depth: usize,
+    /// Folders with a copy of this message
+    pub folders: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Thread {
+    pub thread_id: String,
+    pub subject: String,
+    pub messages: Vec<ThreadMessage>,
+}
+
+/// The `<...>` ids in a Message-ID, In-Reply-To or References value
+pub fn parse_message_ids(value: &str) -> Vec<String> {
+    let mut ids = Vec::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('<') {
+        let Some(len) = rest[start..].find('>') else { break };
+        let id: String = rest[start..=start + len].chars().filter(|c| !c.is_whitespace()).collect();
+        if id.len() > 2 {
+            ids.push(id);
+        }
+        rest = &rest[start + len + 1..];
+    }
+    if ids.is_empty() {
+        // Some mailers leave out the angle brackets
+        ids.extend(value.split_whitespace().filter(|t| t.contains('@')).map(|t| format!("<{}>", t.trim_matches(['<', '>']))));
+    }
+    ids
+}
+
+/// Subject without reply prefixes ("Re:", "AW:", "Re[2]:", ...), lowercased;
+/// true if there were any
+pub fn base_subject(subject: &str) -> (String, bool) {
+    let mut rest = subject.trim();
+    let mut reply = false;
+    while let Some((colon, sep)) = rest.char_indices().find(|(_, c)| matches!(c, ':' | '：')) {
+        let prefix = rest[..colon].trim();
+        let word_end = prefix.find(['[', '(']).unwrap_or(prefix.len());
+        let (word, counter) = (prefix[..word_end].trim(), prefix[word_end..].trim());
+        let digits = counter.strip_prefix(['[', '(']).and_then(|c| c.strip_suffix([']', ')']));
+        let counter_ok = counter.is_empty() || digits.is_some_and(|d| !d.is_empty() && d.chars().all(|c| c.is_ascii_digit()));
+        if !counter_ok || !REPLY_PREFIXES.iter().any(|p| p.eq_ignore_ascii_case(word)) {
+            break;
+        }
+        rest = rest[colon + sep.len_utf8()..].trim_start();
+        reply = true;
+    }
+    (rest.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase(), reply)
+}
+
+/// References oldest first, ending with the parent: In-Reply-To when References lack it
+fn references(message: &MessageRef, own: &str) -> Vec<String> {
+    let mut refs = message.refs.as_deref().map(parse_message_ids).unwrap_or_default();
+    if let Some(parent) = message.in_reply_to.as_deref().and_then(|v| parse_message_ids(v).into_iter().next()) {
+        if refs.last() != Some(&parent) {
+            refs.retain(|r| *r != parent);
+            refs.push(parent);
+        }
+    }
+    refs.retain(|r| r != own);
+    refs
+}
+
+/// A Message-ID seen in a header or on a message, which may not be stored
+#[derive(Default)]
+struct Container {
+    /// Copies of the message (the same Message-ID in several folders)
+    messages: Vec<usize>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+#[derive(Default)]
+struct IdTable {
+    keys: Vec<String>,
+    containers: Vec<Container>,
+    by_key: HashMap<String, usize>,
+}
+
+impl IdTable {
+    fn get(&mut self, key: &str) -> usize {
+        if let Some(&c) = self.by_key.get(key) {
+            return c;
+        }
+        self.keys.push(key.to_string());
+        self.containers.push(Container::default());
+        self.by_key.insert(key.to_string(), self.containers.len() - 1);
+        self.containers.len() - 1
+    }
+
+    /// Whether `ancestor` is `c` or above it
+    fn is_ancestor(&self, ancestor: usize, mut c: usize) -> bool {
+        loop {
+            if c == ancestor {
+                return true;
+            }
+            match self.containers[c].parent {
+                Some(parent) => c = parent,
+                None => return false,
+            }
+        }
+    }
+
+    fn unlink(&mut self, child: usize) {
+        if let Some(old) = self.containers[child].parent.take() {
+            self.containers[old].children.retain(|&c| c != child);
+        }
+    }
+
+    /// Make `child` a reply to `parent`, unless that would close a loop
+    fn link(&mut self, parent: usize, child: usize) {
+        if self.is_ancestor(child, parent) {
+            return;
+        }
+        self.unlink(child);
+        self.containers[child].parent = Some(parent);
+        self.containers[parent].children.push(child);
+    }
+
+    /// Drop containers of messages never seen that have no replies, and splice out
+    /// those with replies, except at the top of a conversation with several replies
+    fn prune(&mut self, c: usize, is_root: bool) -> Vec<usize> {
+        let children = std::mem::take(&mut self.containers[c].children);
+        let children: Vec<usize> = children.into_iter().flat_map(|child| self.prune(child, false)).collect();
+        for &child in &children {
+            self.containers[child].parent = Some(c);
+        }
+        self.containers[c].children = children;
+
+        let container = &self.containers[c];
+        if !container.messages.is_empty() || is_root && container.children.len() > 1 {
+            return vec![c];
+        }
+        let promoted = std::mem::take(&mut self.containers[c].children);
+        for &child in &promoted {
+            self.containers[child].parent = None;
+        }
+        promoted
+    }
+
+    fn subtree(&self, c: usize) -> Vec<usize> {
+        let mut out = vec![c];
+        let mut i = 0;
+        while i < out.len() {
+            out.extend(self.containers[out[i]].children.iter().copied());
+            i += 1;
+        }
+        out
+    }
+}
+
+/// Group messages into conversations
+pub fn build_threads(input: &[MessageRef]) -> Vec<ThreadTree> {
+    let mut table = IdTable::default();
+
+    // 1. Link every message to its References; the last one is its parent
+    for (i, message) in input.iter().enumerate() {
+        let own = message
+            .message_id
+            .as_deref()
+            .and_then(|v| parse_message_ids(v).into_iter().next())
+            .unwrap_or_else(|| format!("local:{}", message.id));
+        let this = table.get(&own);
+        table.containers[this].messages.push(i);
+
+        let refs = references(message, &own);
+        for pair in refs.windows(2) {
+            let (parent, child) = (table.get(&pair[0]), table.get(&pair[1]));
+            // Earlier guesses from other messages' References stand
+            if table.containers[child].parent.is_none() {
+                table.link(parent, child);
+            }
+        }
+        match refs.last() {
+            Some(last) => {
+                let parent = table.get(last);
+                table.link(parent, this);
+            }
+            None => table.unlink(this),
+        }
+    }
+
+    // 2-3. Conversation tops, without containers of messages that were never seen
+    let roots: Vec<usize> = (0..table.containers.len()).filter(|&c| table.containers[c].parent.is_none()).collect();
+    let mut roots: Vec<usize> = roots.into_iter().flat_map(|c| table.prune(c, true)).collect();
+
+    // Earliest date and first message of every conversation, for ordering and subjects
+    let date = |m: usize| input[m].date.clone().unwrap_or_default();
+    let earliest_message = |table: &IdTable, c: usize| {
+        table.subtree(c).into_iter().flat_map(|c| table.containers[c].messages.iter().copied()).min_by_key(|&m| date(m))
+    };
+    let root_date = |table: &IdTable, c: usize| earliest_message(table, c).map(date).unwrap_or_default();
+    roots.sort_by_key(|&c| root_date(&table, c));
+
+    // 4. Gmail knows conversations the headers don't show
+    let mut by_thrid: HashMap<i64, usize> = HashMap::new();
+    for &root in &roots {
+        let thrids: Vec<i64> = table.subtree(root)
+            .into_iter()
+            .flat_map(|c| table.containers[c].messages.iter().filter_map(|&m| input[m].gm_thrid))
+            .collect();
+        match thrids.iter().find_map(|t| by_thrid.get(t).copied()) {
+            Some(target) => {
+                table.link(target, root);
+                thrids.into_iter().for_each(|t| { by_thrid.entry(t).or_insert(target); });
+            }
+            None => thrids.into_iter().for_each(|t| { by_thrid.insert(t, root); }),
+        }
+    }
+
+    // 5. A reply without usable headers joins the latest earlier conversation with its subject
+    let mut by_subject: HashMap<String, usize> = HashMap::new();
+    for &root in &roots {
+        if table.containers[root].parent.is_some() {
+            continue;
+        }
+        let top = table.containers[root].messages.first().copied().or_else(|| earliest_message(&table, root));
+        let Some(top) = top else { continue };
+        let (subject, reply) = base_subject(input[top].subject.as_deref().unwrap_or_default());
+        if subject.is_empty() {
+            continue;
+        }
+        match by_subject.get(&subject) {
+            Some(&anchor) if reply => table.link(anchor, root),
+            _ => {
+                by_subject.insert(subject, root);
+            }
+        }
+    }
+
+    // 6. Depth-first, replies in date order
+    let mut child_order: HashMap<usize, String> = HashMap::new();
+    let mut sort_key = |table: &IdTable, c: usize| child_order.entry(c).or_insert_with(|| root_date(table, c)).clone();
+    let mut threads = Vec::new();
+    for root in roots.into_iter().filter(|&c| table.containers[c].parent.is_none()) {
+        let mut messages = Vec::new();
+        let mut stack = vec![(root, 0)];
+        while let Some((c, depth)) = stack.pop() {
+            let container = &table.containers[c];
+            let mut copies = container.messages.clone();
+            copies.sort_by_key(|&m| date(m));
+            messages.extend(copies.into_iter().map(|m| (m, depth)));
+            let child_depth = if container.messages.is_empty() { depth } else { depth + 1 };
+            let mut children = container.children.clone();
+            children.sort_by_key(|&child| sort_key(&table, child));
+            stack.extend(children.into_iter().rev().map(|child| (child, child_depth)));
+        }
+        threads.push(ThreadTree { id: table.keys[root].clone(), messages });
+    }
+    threads
+}
+
+/// Recompute `thread_id` of every message of the account
+pub async fn thread_account(pool: &SqlitePool, account_id: &str) -> Result<(), String> {
+    let messages = sqlx::query_as::<_, MessageRef>(
+        "SELECT id, message_id, in_reply_to, refs, subject, date, gm_thrid, thread_id FROM emails WHERE account_id = $1"
+    )
+    .bind(account_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+
+    let threads = build_threads(&messages);
+    let changed: Vec<(&str, &str)> = threads
+        .iter()
+        .flat_map(|thread| thread.messages.iter().map(move |&(m, _)| (m, thread.id.as_str())))
+        .filter(|&(m, id)| messages[m].thread_id.as_deref() != Some(id))
+        .map(|(m, id)| (messages[m].id.as_str(), id))
+        .collect();
+    if changed.is_empty() {
+        return Ok(());
+    }
+
+    let mut tx = pool.begin().await.map_err(|e| format!("DB error: {}", e))?;
+    for (email_id, thread_id) in &changed {
+        sqlx::query("UPDATE emails SET thread_id = $1 WHERE id = $2")
+            .bind(thread_id)
+            .bind(email_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| format!("DB error: {}", e))?;
+    }
+    tx.commit().await.map_err(|e| format!("DB error: {}", e))?;
+    log::info!("[THREADS] {} messages rethreaded for {}", changed.len(), account_id);
+    Ok(())
+}
+
+/// Every stored copy of the messages of these conversations, all folders
+async fn load_thread_emails(pool: &SqlitePool, account_id: &str, thread_ids: &[String]) -> Result<Vec<Email>, String> {
+    let mut emails = Vec::new();
+    for (chunk, placeholders) in crate::db::in_chunks(thread_ids, 2) {
+        let sql = format!(
+            "SELECT id, uid, account_id, folder, subject, sender, sender_email, to_email, date, snippet, body, body_cached, read, flagged, answered, ai_priority, ai_labels, ai_summary, gm_msgid, gm_thrid, message_id, thread_id FROM emails WHERE account_id = $1 AND thread_id IN ({}) ORDER BY date",
+            placeholders
+        );
+        let mut query = sqlx::query_as::<_, Email>(&sql).bind(account_id);
+        for thread_id in chunk {
+            query = query.bind(thread_id);
+        }
+        emails.extend(query.fetch_all(pool).await.map_err(|e| format!("DB error: {}", e))?);
+    }
+    crate::addresses::load_addresses(pool, &mut emails).await?;
+    Ok(emails)
+}
+
+/// One row per message (see `Email::message_key`), preferring a copy with its body
+/// cached, with the folders of all copies
+fn distinct_messages(emails: Vec<Email>) -> Vec<(Email, Vec<String>)> {
+    let mut out: Vec<(Email, Vec<String>)> = Vec::new();
+    let mut by_key: HashMap<String, usize> = HashMap::new();
+    for email in emails {
+        let key = email.message_key();
+        match by_key.get(&key) {
+            Some(&i) => {
+                let (kept, folders) = &mut out[i];
+                if !folders.contains(&email.folder) {
+                    folders.push(email.folder.clone());
+                }
+                if !kept.body_cached.unwrap_or(false) && email.body_cached.unwrap_or(false) {
+                    *kept = email;
+                }
+            }
+            None => {
+                by_key.insert(key, out.len());
+                let folders = vec![email.folder.clone()];
+                out.push((email, folders));
+            }
+        }
+    }
+    out
+}
+
+fn summarize(thread_id: String, messages: Vec<(Email, Vec<String>)>) -> Option<ThreadSummary> {
+    let mut participants: Vec<String> = Vec::new();
+    let mut folders: Vec<String> = Vec::new();
+    for (email, copies) in &messages {
+        let sender = email.sender.clone().unwrap_or_default();
+        if !sender.is_empty() && !participants.contains(&sender) {
+            participants.push(sender);
+        }
+        for folder in copies {
+            if !folders.contains(folder) {
+                folders.push(folder.clone());
+            }
+        }
+    }
+    let subject = messages.first().and_then(|(e, _)| e.subject.clone()).unwrap_or_default();
+    let message_count = messages.len();
+    let unread_count = messages.iter().filter(|(e, _)| !e.read.unwrap_or(false)).count();
+    let flagged = messages.iter().any(|(e, _)| e.flagged.unwrap_or(false));
+    let (latest, _) = messages.into_iter().max_by(|(a, _), (b, _)| a.date.cmp(&b.date))?;
+    Some(ThreadSummary { thread_id, subject, message_count, unread_count, flagged, participants, folders, latest })
+}
+
+/// Tauri command: conversations of the account, newest activity first. With a folder,
+/// only those with a message there, but still counting messages in every folder.
+#[tauri::command]
+pub async fn get_threads(app: AppHandle, account_id: String, folder: Option<String>, limit: Option<i64>, offset: Option<i64>) -> Result<Vec<ThreadSummary>, String> {
+    let state = app.state::<DbState>();
+    let thread_ids = sqlx::query_scalar::<_, String>(
+        r#"SELECT thread_id FROM emails
+           WHERE account_id = $1 AND thread_id IS NOT NULL
+           GROUP BY thread_id
+           HAVING $2 IS NULL OR SUM(folder = $2) > 0
+           ORDER BY MAX(date) DESC
+           LIMIT $3 OFFSET $4"#
+    )
+    .bind(&account_id)
+    .bind(&folder)
+    .bind(limit.unwrap_or(50))
+    .bind(offset.unwrap_or(0))
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+
+    let mut by_thread: HashMap<String, Vec<Email>> = HashMap::new();
+    for email in load_thread_emails(&state.pool, &account_id, &thread_ids).await? {
+        by_thread.entry(email.thread_id.clone().unwrap_or_default()).or_default().push(email);
+    }
+    Ok(thread_ids
+        .into_iter()
+        .filter_map(|id| {
+            let emails = by_thread.remove(&id)?;
+            summarize(id, distinct_messages(emails))
+        })
+        .collect())
+}
+
+/// Tauri command: a whole conversation across folders (Sent included), in reply order
+#[tauri::command]
+pub async fn get_thread(app: AppHandle, account_id: String, thread_id: String) -> Result<Thread, String> {
+    let state = app.state::<DbState>();
+    let emails = load_thread_emails(&state.pool, &account_id, std::slice::from_ref(&thread_id)).await?;
+    if emails.is_empty() {
+        return Err(format!("Unknown thread: {}", thread_id));
+    }
+    let messages = distinct_messages(emails);
+
+    let refs = sqlx::query_as::<_, MessageRef>(
+        "SELECT id, message_id, in_reply_to, refs, subject, date, gm_thrid, thread_id FROM emails WHERE account_id = $1 AND thread_id = $2"
+    )
+    .bind(&account_id)
+    .bind(&thread_id)
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| format!("DB error: {}", e))?;
+    let kept: HashSet<&str> = messages.iter().map(|(e, _)| e.id.as_str()).collect();
+    let refs: Vec<MessageRef> = refs.into_iter().filter(|r| kept.contains(r.id.as_str())).collect();
+    let depths: HashMap<String, (usize, usize)> = build_threads(&refs)
+        .into_iter()
+        .flat_map(|t| t.messages)
+        .enumerate()
+        .map(|(order, (m, depth))| (refs[m].id.clone(), (order, depth)))
+        .collect();
+
+    let mut messages: Vec<ThreadMessage> = messages
+        .into_iter()
+        .map(|(email, folders)| {
+            let depth = depths.get(&email.id).map_or(0, |&(_, depth)| depth);
+            ThreadMessage { email, depth, folders }
+        })
+        .collect();
+    messages.sort_by_key(|m| depths.get(&m.email.id).map_or(usize::MAX, |&(order, _)| order));
+    let subject = messages.first().and_then(|m| m.email.subject.clone()).unwrap_or_default();
+    Ok(Thread { thread_id, subject, messages })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, message_id: &str, in_reply_to: Option<&str>, subject: &str, date: &str) -> MessageRef {
+        MessageRef {
+            id: id.to_string(),
+            message_id: Some(message_id.to_string()).filter(|m| !m.is_empty()),
+            in_reply_to: in_reply_to.map(str::to_string),
+            subject: Some(subject.to_string()),
+            date: Some(date.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Each thread as "id:depth" entries in display order
+    fn layout(input: &[MessageRef]) -> Vec<Vec<String>> {
+        build_threads(input)
+            .into_iter()
+            .map(|t| t.messages.into_iter().map(|(m, depth)| format!("{}:{}", input[m].id, depth)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn parses_message_ids() {
+        assert_eq!(parse_message_ids("<a@x>"), vec!["<a@x>"]);
+        assert_eq!(parse_message_ids("<a@x>\r\n\t<b @x> junk <c@x>"), vec!["<a@x>", "<b@x>", "<c@x>"]);
+        // In-Reply-To with a comment, as some old mailers write it
+        assert_eq!(parse_message_ids("<a@x> (Bob's message of Monday)"), vec!["<a@x>"]);
+        // No angle brackets
+        assert_eq!(parse_message_ids("a@x"), vec!["<a@x>"]);
+        // Unterminated
+        assert_eq!(parse_message_ids("<> <a@x"), vec!["<a@x>"]);
+        assert!(parse_message_ids("").is_empty());
+    }
+
+    #[test]
+    fn strips_reply_prefixes() {
+        assert_eq!(base_subject("Re: Plan"), ("plan".to_string(), true));
+        assert_eq!(base_subject("RE[2]: AW: re :  Quarterly   Plan"), ("quarterly plan".to_string(), true));
+        assert_eq!(base_subject("Re(3): Plan"), ("plan".to_string(), true));
+        assert_eq!(base_subject("回复：会议"), ("会议".to_string(), true));
+        assert_eq!(base_subject("Fwd: Plan"), ("fwd: plan".to_string(), false));
+        assert_eq!(base_subject("Meeting: agenda"), ("meeting: agenda".to_string(), false));
+        assert_eq!(base_subject(""), (String::new(), false));
+    }
+
+    #[test]
+    fn malformed_reply_counters_are_not_prefixes() {
+        assert_eq!(base_subject("Re[é: Hallo"), ("re[é: hallo".to_string(), false));
+        assert_eq!(base_subject("Re(: Hallo"), ("re(: hallo".to_string(), false));
+        assert_eq!(base_subject("Re[]: Hallo"), ("re[]: hallo".to_string(), false));
+        assert_eq!(base_subject("Re[2x]: Hallo"), ("re[2x]: hallo".to_string(), false));
+        assert_eq!(base_subject("Re: Re[é: Hallo"), ("re[é: hallo".to_string(), true));
+    }
+
+    #[test]
+    fn link_never_closes_a_loop() {
+        let mut table = IdTable::default();
+        let (a, b, c) = (table.get("<a@x>"), table.get("<b@x>"), table.get("<c@x>"));
+        table.link(a, b);
+        table.link(b, c);
+        table.link(c, a);
+        table.link(a, a);
+        assert_eq!(table.containers[a].parent, None);
+        assert_eq!(table.containers[c].parent, Some(b));
+
+        // Messages that claim to answer each other
+        let input = vec![
+            message("1", "<i@x>", Some("<j@x>"), "One", "2024-05-01"),
+            message("2", "<j@x>", Some("<i@x>"), "Two", "2024-05-02"),
+        ];
+        assert_eq!(layout(&input), vec![vec!["2:0", "1:1"]]);
+    }
+
+    #[test]
+    fn nests_replies_by_references() {
+        let mut input = vec![
+            message("1", "<a@x>", None, "Plan", "2024-01-01"),
+            message("2", "<b@x>", Some("<a@x>"), "Re: Plan", "2024-01-02"),
+            message("3", "<c@x>", None, "Re: Plan", "2024-01-03"),
+            message("4", "<d@x>", Some("<a@x>"), "Re: Plan", "2024-01-04"),
+            // The same message in another folder
+            message("3-copy", "<c@x>", None, "Re: Plan", "2024-01-03"),
+        ];
+        input[2].refs = Some("<a@x> <b@x>".to_string());
+        input[4].refs = Some("<a@x> <b@x>".to_string());
+        assert_eq!(layout(&input), vec![vec!["1:0", "2:1", "3:2", "3-copy:2", "4:1"]]);
+        assert_eq!(build_threads(&input)[0].id, "<a@x>");
+    }
+
+    #[test]
+    fn prunes_containers_of_missing_messages() {
+        let mut input = vec![
+            // Two replies to a message that was never stored: kept together under it
+            message("1", "<e@x>", Some("<gone@x>"), "Re: Gone", "2024-02-01"),
+            message("2", "<f@x>", None, "Re: Gone", "2024-02-02"),
+            // One reply to a missing message, whose own parent is missing too
+            message("3", "<k@x>", Some("<missing-2@x>"), "Lonely", "2024-03-01"),
+        ];
+        input[1].refs = Some("<gone@x>".to_string());
+        input[2].refs = Some("<missing-1@x> <missing-2@x>".to_string());
+        let threads = build_threads(&input);
+        assert_eq!(threads.len(), 2);
+        assert_eq!(threads[0].id, "<gone@x>");
+        assert_eq!(layout(&input), vec![vec!["1:0", "2:0"], vec!["3:0"]]);
+        assert_eq!(threads[1].id, "<k@x>");
+    }
+
+    #[test]
+    fn merges_gmail_threads() {
+        let mut input = vec![
+            message("1", "<g@x>", None, "Hi", "2024-04-01"),
+            message("2", "<h@x>", None, "Something else", "2024-04-02"),
+            message("3", "<l@x>", None, "Unrelated", "2024-04-03"),
+        ];
+        input[0].gm_thrid = Some(7);
+        input[1].gm_thrid = Some(7);
+        input[2].gm_thrid = Some(8);
+        assert_eq!(layout(&input), vec![vec!["1:0", "2:1"], vec!["3:0"]]);
+    }
+
+    #[test]
+    fn joins_replies_by_subject() {
+        let input = vec![
+            message("1", "", None, "Status", "2024-03-01"),
+            message("2", "", None, "Re: status", "2024-03-02"),
+            // A new report with the same subject starts its own conversation...
+            message("3", "", None, "Status", "2024-03-08"),
+            // ...and later replies join that one
+            message("4", "", None, "AW: Status", "2024-03-09"),
+            // A reply with nothing to join stays alone
+            message("5", "", None, "Re: Budget", "2024-03-10"),
+        ];
+        assert_eq!(layout(&input), vec![vec!["1:0", "2:1"], vec!["3:0", "4:1"], vec!["5:0"]]);
+        assert_eq!(build_threads(&input)[0].id, "local:1");
+    }
+
+    #[test]
+    fn collapses_gmail_copies_by_msgid() {
+        let copy = |id: &str, folder: &str, gm_msgid: Option<i64>, message_id: &str, cached: bool| Email {
+            id: id.to_string(),
+            folder: folder.to_string(),
+            gm_msgid,
+            message_id: Some(message_id.to_string()),
+            body_cached: Some(cached),
+            ..Default::default()
+        };
+        let messages = distinct_messages(vec![
+            copy("a", "[Gmail]/All Mail", Some(42), "<m@x>", false),
+            // Same X-GM-MSGID, Message-ID rewritten by a client
+            copy("b", "Work", Some(42), "<other@x>", true),
+            // Same Message-ID, but a different Gmail message
+            copy("c", "INBOX", Some(43), "<m@x>", false),
+        ]);
+        let layout: Vec<(&str, Vec<String>)> = messages.iter().map(|(e, f)| (e.id.as_str(), f.clone())).collect();
+        assert_eq!(layout, vec![
+            ("b", vec!["[Gmail]/All Mail".to_string(), "Work".to_string()]),
+            ("c", vec!["INBOX".to_string()]),
+        ]);
+    }
+}
@@ -0,0 +1,258 @@
+/// Connection security for IMAP and SMTP — implicit TLS, STARTTLS or plaintext,
+/// per-account custom CA certificates, and trust-on-first-use certificate pinning.
+/// Pins are stored per server (`tls_pins`), keyed by host and port.
+use tauri::{AppHandle, Manager};
+use crate::db::{Account, DbState};
+use sqlx::SqlitePool;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// How a connection is protected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Security {
+    /// TLS from the first byte (IMAPS 993, SMTPS 465)
+    Tls,
+    /// Plaintext greeting upgraded with STARTTLS (IMAP 143, submission 587)
+    StartTls,
+    /// No encryption at all — local test servers only
+    None,
+}
+
+impl Security {
+    /// Parse an account setting. Unset means implicit TLS, the historic behaviour.
+    pub fn parse(value: Option<&str>) -> Result<Self, String> {
+        match value.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+            None | Some("") | Some("tls") | Some("ssl") => Ok(Security::Tls),
+            Some("starttls") => Ok(Security::StartTls),
+            Some("none") | Some("plain") => Ok(Security::None),
+            Some(other) => Err(format!("Unknown connection security: {}", other)),
+        }
+    }
+
+    /// Well-known IMAP port for this mode, used when the account has none
+    pub fn default_imap_port(self) -> u16 {
+        match self {
+            Security::Tls => 993,
+            _ => 143,
+        }
+    }
+
+    /// Well-known SMTP submission port for this mode, used when the account has none
+    pub fn default_smtp_port(self) -> u16 {
+        match self {
+            Security::Tls => 465,
+            Security::StartTls => 587,
+            Security::None => 25,
+        }
+    }
+}
+
+/// Certificate trust settings of an account, shared by its IMAP and SMTP connections
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlsSettings {
+    /// Extra trusted root certificates, PEM (may hold several)
+    pub ca_cert: Option<String>,
+    /// Trust the first certificate a server presents and only that one afterwards
+    pub pinning: bool,
+}
+
+impl TlsSettings {
+    pub fn from_account(account: &Account) -> Self {
+        TlsSettings {
+            ca_cert: account.ca_cert.clone().filter(|c| !c.trim().is_empty()),
+            pinning: account.tls_pinning.unwrap_or(false),
+        }
+    }
+
+    /// Each `-----BEGIN CERTIFICATE-----` block of the custom CA bundle, without
+    /// the text around it (bundles often carry subject lines between certificates)
+    fn ca_blocks(&self) -> Vec<String> {
+        let Some(pem) = &self.ca_cert else { return vec![] };
+        pem.split_inclusive("-----END CERTIFICATE-----")
+            .filter_map(|block| block.find("-----BEGIN CERTIFICATE-----").map(|start| &block[start..]))
+            .filter(|block| block.ends_with("-----END CERTIFICATE-----"))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Connector for IMAP. With pinning, the pinned fingerprint is the trust anchor,
+    /// so chain validation is skipped (self-signed servers are the main use).
+    pub fn connector(&self) -> Result<async_native_tls::TlsConnector, String> {
+        let mut connector = async_native_tls::TlsConnector::new();
+        for block in self.ca_blocks() {
+            let cert = async_native_tls::Certificate::from_pem(block.as_bytes())
+                .map_err(|e| format!("Invalid CA certificate: {}", e))?;
+            connector = connector.add_root_certificate(cert);
+        }
+        if self.pinning {
+            connector = connector.danger_accept_invalid_certs(true);
+        }
+        Ok(connector)
+    }
+
+    /// Same trust rules for lettre's SMTP client
+    pub fn smtp_parameters(&self, host: &str) -> Result<lettre::transport::smtp::client::TlsParameters, String> {
+        use lettre::transport::smtp::client::{Certificate, TlsParameters};
+
+        let mut builder = TlsParameters::builder(host.to_string());
+        for block in self.ca_blocks() {
+            let cert = Certificate::from_pem(block.as_bytes())
+                .map_err(|e| format!("Invalid CA certificate: {}", e))?;
+            builder = builder.add_root_certificate(cert);
+        }
+        if self.pinning {
+            builder = builder.dangerous_accept_invalid_certs(true);
+        }
+        builder.build_native().map_err(|e| format!("TLS error: {}", e))
+    }
+}
+
+/// SHA-256 of a DER certificate as colon-separated hex, the way browsers show it
+pub fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// A pinned server certificate
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct TlsPin {
+    pub host: String,
+    pub port: i64,
+    pub fingerprint: String,
+    pub pinned_at: String,
+}
+
+/// Check a presented certificate against the pin of `host:port`, pinning it on first use.
+/// A changed certificate is refused before any credentials are sent.
+pub async fn verify_pin(pool: &SqlitePool, host: &str, port: u16, der: Option<Vec<u8>>) -> Result<(), String> {
+    let der = der.ok_or_else(|| format!("{}:{} presented no certificate", host, port))?;
+    let presented = fingerprint(&der);
+
+    let pinned = sqlx::query_scalar::<_, String>("SELECT fingerprint FROM tls_pins WHERE host = $1 AND port = $2")
+        .bind(host)
+        .bind(port as i64)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+
+    match pinned {
+        Some(pinned) if pinned == presented => Ok(()),
+        Some(pinned) => Err(format!(
+            "Certificate of {}:{} has changed (pinned SHA-256 {}, server presented {}). \
+             If the server's certificate was legitimately replaced, forget the pin for this server and reconnect.",
+            host, port, pinned, presented
+        )),
+        None => {
+            sqlx::query("INSERT OR REPLACE INTO tls_pins (host, port, fingerprint, pinned_at) VALUES ($1, $2, $3, $4)")
+                .bind(host)
+                .bind(port as i64)
+                .bind(&presented)
+                .bind(chrono::Utc::now().to_rfc3339())
+                .execute(pool)
+                .await
+                .map_err(|e| format!("DB error: {}", e))?;
+            log::info!("[TLS] Pinned certificate of {}:{} ({})", host, port, presented);
+            Ok(())
+        }
+    }
+}
+
+/// Tauri command: every pinned server certificate
+#[tauri::command]
+pub async fn get_tls_pins(app: AppHandle) -> Result<Vec<TlsPin>, String> {
+    let state = app.state::<DbState>();
+    sqlx::query_as::<_, TlsPin>("SELECT host, port, fingerprint, pinned_at FROM tls_pins ORDER BY host, port")
+        .fetch_all(&state.pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))
+}
+
+/// Tauri command: drop the pin of a server so its next certificate is trusted again
+#[tauri::command]
+pub async fn forget_tls_pin(app: AppHandle, host: String, port: i64) -> Result<(), String> {
+    let state = app.state::<DbState>();
+    sqlx::query("DELETE FROM tls_pins WHERE host = $1 AND port = $2")
+        .bind(&host)
+        .bind(port)
+        .execute(&state.pool)
+        .await
+        .map_err(|e| format!("DB error: {}", e))?;
+    log::info!("[TLS] Forgot pinned certificate of {}:{}", host, port);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    const CERT_A: &str = "-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----";
+    const CERT_B: &str = "-----BEGIN CERTIFICATE-----\nBBBB\n-----END CERTIFICATE-----";
+
+    async fn pin_db() -> SqlitePool {
+        let pool = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE tls_pins (host TEXT NOT NULL, port INTEGER NOT NULL, fingerprint TEXT NOT NULL, pinned_at TEXT NOT NULL, PRIMARY KEY (host, port))")
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool
+    }
+
+    #[test]
+    fn parses_security_settings() {
+        assert_eq!(Security::parse(None).unwrap(), Security::Tls);
+        assert_eq!(Security::parse(Some("")).unwrap(), Security::Tls);
+        assert_eq!(Security::parse(Some(" SSL ")).unwrap(), Security::Tls);
+        assert_eq!(Security::parse(Some("tls")).unwrap(), Security::Tls);
+        assert_eq!(Security::parse(Some("STARTTLS")).unwrap(), Security::StartTls);
+        assert_eq!(Security::parse(Some("plain")).unwrap(), Security::None);
+        assert_eq!(Security::parse(Some("none")).unwrap(), Security::None);
+        assert_eq!(Security::parse(Some("Auto")).unwrap_err(), "Unknown connection security: auto");
+    }
+
+    #[test]
+    fn splits_ca_bundles() {
+        let bundle = format!("Subject: CN=A\n{}\n\nissuer text\n{}\ntrailing junk\n", CERT_A, CERT_B);
+        let settings = TlsSettings { ca_cert: Some(bundle), pinning: false };
+        assert_eq!(settings.ca_blocks(), vec![CERT_A.to_string(), CERT_B.to_string()]);
+
+        let stray = TlsSettings { ca_cert: Some("not a certificate".to_string()), pinning: false };
+        assert!(stray.ca_blocks().is_empty());
+        assert!(TlsSettings::default().ca_blocks().is_empty());
+    }
+
+    #[test]
+    fn fingerprints_as_colon_separated_hex() {
+        assert_eq!(
+            fingerprint(b"abc"),
+            "BA:78:16:BF:8F:01:CF:EA:41:41:40:DE:5D:AE:22:23:B0:03:61:A3:96:17:7A:9C:B4:10:FF:61:F2:00:15:AD"
+        );
+    }
+
+    #[tokio::test]
+    async fn pins_on_first_use() {
+        let pool = pin_db().await;
+        verify_pin(&pool, "mail.example.com", 993, Some(b"cert-1".to_vec())).await.unwrap();
+        let stored: String = sqlx::query_scalar("SELECT fingerprint FROM tls_pins WHERE host = 'mail.example.com' AND port = 993")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, fingerprint(b"cert-1"));
+
+        // The same certificate keeps passing; other ports are pinned separately
+        verify_pin(&pool, "mail.example.com", 993, Some(b"cert-1".to_vec())).await.unwrap();
+        verify_pin(&pool, "mail.example.com", 465, Some(b"cert-2".to_vec())).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_a_changed_certificate() {
+        let pool = pin_db().await;
+        verify_pin(&pool, "mail.example.com", 993, Some(b"cert-1".to_vec())).await.unwrap();
+        let error = verify_pin(&pool, "mail.example.com", 993, Some(b"cert-2".to_vec())).await.unwrap_err();
+        assert!(error.contains("has changed"), "{}", error);
+        assert!(verify_pin(&pool, "mail.example.com", 993, None).await.is_err());
+    }
+}
@@ -15,11 +15,17 @@ interface EmailItem {
   sender: string;
   sender_email?: string;
   to_email?: string;
+  addresses?: { role: string; name?: string; address: string }[];
+  message_id?: string;
+  thread_id?: string;
   date: string;
   snippet: string;
   body: string;
+  body_cached?: boolean;
   folder: string;
   read?: boolean;
+  flagged?: boolean;
+  answered?: boolean;
   is_html?: boolean;
   priority?: string;
   ai_priority?: string;
@@ -36,6 +42,11 @@ interface AccountData {
   imap_port?: number;
   smtp_host?: string;
   smtp_port?: number;
+  imap_security?: string;
+  smtp_security?: string;
+  ca_cert?: string;
+  tls_pinning?: boolean;
+  auth_method?: string;
 }
 
 function SidebarItem({ icon, label, badge, active, onClick }: { icon: React.ReactNode; label: string; badge?: string; active?: boolean; onClick?: () => void }) {
@@ -67,6 +78,7 @@ export default function MailApp() {
   const [currentFolder, setCurrentFolder] = useState<FolderType>('INBOX');
   const [isComposing, setIsComposing] = useState(false);
   const [statusMsg, setStatusMsg] = useState('');
+  const [searchTerm, setSearchTerm] = useState('');
   const [account, setAccount] = useState<AccountData | null>(null);
   const [emails, setEmails] = useState<EmailItem[]>([]);
   const [isSyncing, setIsSyncing] = useState(false);
@@ -112,6 +124,8 @@ export default function MailApp() {
   const [agentLog, setAgentLog] = useState<Array<{ action: string; email: string; time: string }>>([]);
   const processedByAgent = useRef<Set<string>>(new Set(typeof window !== 'undefined' ? JSON.parse(localStorage.getItem('zero_agent_processed') || '[]') : []));
   const handleSyncRef = useRef<(() => Promise<void>) | undefined>(undefined);
+  const currentFolderRef = useRef<FolderType>('INBOX');
+  currentFolderRef.current = currentFolder;
   const [chatMessages, setChatMessages] = useState<Array<{ role: 'user' | 'ai'; text: string }>>([]);
   const [chatInput, setChatInput] = useState('');
   const [chatOpen, setChatOpen] = useState(false);
@@ -305,6 +319,24 @@ export default function MailApp() {
     }
   }, [account, currentFolder, currentView, loadEmails]);
 
+  // -- Search the server, including mail older than the local cache --
+  const handleServerSearch = async () => {
+    if (!account) return;
+    const term = searchTerm.trim();
+    if (!term) {
+      await loadEmails(currentFolder);
+      return;
+    }
+    setStatusMsg(`🔎 Buscando "${term}" en el servidor...`);
+    try {
+      const result = await invoke("search_server", { accountId: account.id, folder: currentFolder, query: { text: term } }) as EmailItem[];
+      setEmails(result || []);
+      setStatusMsg(`🔎 ${result.length} resultado${result.length !== 1 ? 's' : ''} para "${term}"`);
+    } catch (e) {
+      setStatusMsg(`⚠️ Error en la búsqueda: ${e}`);
+    }
+  };
+
   // -- Sync from IMAP server --
   const handleSync = async () => {
     if (!account) return;
@@ -402,12 +434,54 @@ export default function MailApp() {
     let unlistenClassified: (() => void) | undefined;
     let unlistenImportant: (() => void) | undefined;
 
-    listen<{ account_id: string; folder: string }>('new-mail', (event) => {
-      if (event.payload.folder === 'INBOX' && currentFolder === 'INBOX') {
+    let unlistenExpunged: (() => void) | undefined;
+    let unlistenFlags: (() => void) | undefined;
+    let unlistenConflict: (() => void) | undefined;
+    let unlistenDeleted: (() => void) | undefined;
+
+    listen<{ account_id: string; folder: string; uids: number[] }>('new-mail', (event) => {
+      // One IDLE watcher per account and folder; only refresh what is on screen
+      if (event.payload.account_id === account.id && event.payload.folder === currentFolderRef.current) {
         handleSyncRef.current?.();
       }
     }).then((fn) => { unlisten = fn; });
 
+    // Deleted elsewhere: drop the rows (the backend already removed them)
+    listen<{ account_id: string; email_ids: string[] }>('mail-expunged', (event) => {
+      if (event.payload.account_id !== account.id) return;
+      const gone = new Set(event.payload.email_ids);
+      setEmails(prev => prev.filter(m => !gone.has(m.id)));
+    }).then((fn) => { unlistenExpunged = fn; });
+
+    // Read/flagged/answered changed in another client
+    listen<{ account_id: string; changes: { email_id: string; read: boolean; flagged: boolean; answered: boolean }[] }>('flags-changed', (event) => {
+      if (event.payload.account_id !== account.id) return;
+      const byId = new Map(event.payload.changes.map(c => [c.email_id, c]));
+      setEmails(prev => prev.map(m => {
+        const c = byId.get(m.id);
+        return c ? { ...m, read: c.read, flagged: c.flagged, answered: c.answered } : m;
+      }));
+    }).then((fn) => { unlistenFlags = fn; });
+
+    // A change made offline could not be applied on the server: drop the affected rows
+    listen<{ account_id: string; operation: string; email_ids: string[]; reason: string }>('pending-conflict', (event) => {
+      if (event.payload.account_id !== account.id) return;
+      const gone = new Set(event.payload.email_ids);
+      setEmails(prev => prev.filter(m => !gone.has(m.id)));
+      setStatusMsg(`⚠️ No se pudo aplicar un cambio sin conexión: ${event.payload.reason}`);
+      setTimeout(() => setStatusMsg(''), 6000);
+    }).then((fn) => { unlistenConflict = fn; });
+
+    // Without a Trash folder on the server, deleting removes mail for good
+    listen<{ account_id: string; moved_to_trash: string[]; deleted_permanently: string[]; no_trash: boolean }>('emails-deleted', (event) => {
+      if (event.payload.account_id !== account.id) return;
+      const permanent = event.payload.deleted_permanently.length;
+      if (event.payload.no_trash && permanent > 0) {
+        setStatusMsg(`🗑️ El servidor no tiene papelera: ${permanent} correo(s) eliminado(s) definitivamente`);
+        setTimeout(() => setStatusMsg(''), 6000);
+      }
+    }).then((fn) => { unlistenDeleted = fn; });
+
     // 🧠 Triage: update importance badge when backend classifies an email
     listen<{ email_id: string; importance: string; reason: string }>('email-classified', (event) => {
       const { email_id, importance } = event.payload;
@@ -458,6 +532,10 @@ export default function MailApp() {
     return () => {
       clearInterval(interval);
       unlisten?.();
+      unlistenExpunged?.();
+      unlistenFlags?.();
+      unlistenConflict?.();
+      unlistenDeleted?.();
       unlistenClassified?.();
       unlistenImportant?.();
       unlistenTriageProgress?.();
@@ -867,9 +945,15 @@ export default function MailApp() {
       email: formData.get("email") as string,
       password: formData.get("password") as string,
       imap_host: formData.get("imapHost") as string,
-      imap_port: 993,
+      // Empty port: the backend uses the standard port of the security mode
+      imap_port: Number(formData.get("imapPort")) || undefined,
       smtp_host: formData.get("smtpHost") as string,
-      smtp_port: 465,
+      smtp_port: Number(formData.get("smtpPort")) || undefined,
+      imap_security: formData.get("imapSecurity") as string,
+      smtp_security: formData.get("smtpSecurity") as string,
+      ca_cert: (formData.get("caCert") as string) || undefined,
+      tls_pinning: formData.get("tlsPinning") === "on",
+      auth_method: account?.auth_method,
     };
 
     try {
@@ -915,6 +999,42 @@ export default function MailApp() {
     }
   };
 
+  // -- OAuth2 sign-in (authorization code + PKCE, opens the browser) --
+  const handleOAuthSignIn = async (e: React.MouseEvent<HTMLButtonElement>) => {
+    const form = e.currentTarget.form;
+    if (!account || !form) return;
+    const formData = new FormData(form);
+    setStatusMsg("Completa el inicio de sesión en el navegador...");
+    try {
+      await invoke("oauth_authorize", {
+        accountId: account.id,
+        config: {
+          auth_url: formData.get("oauthAuthUrl") as string,
+          token_url: formData.get("oauthTokenUrl") as string,
+          client_id: formData.get("oauthClientId") as string,
+          client_secret: (formData.get("oauthClientSecret") as string) || null,
+          scope: formData.get("oauthScope") as string,
+          mechanism: formData.get("oauthMechanism") as string,
+        },
+      });
+      setAccount({ ...account, auth_method: "oauth2" });
+      setStatusMsg("Sesión OAuth2 iniciada.");
+    } catch (err) {
+      setStatusMsg("OAuth2 error: " + err);
+    }
+  };
+
+  const handleOAuthSignOut = async () => {
+    if (!account) return;
+    try {
+      await invoke("oauth_sign_out", { accountId: account.id });
+      setAccount({ ...account, auth_method: "password" });
+      setStatusMsg("Sesión OAuth2 cerrada; se usará la contraseña.");
+    } catch (err) {
+      setStatusMsg("OAuth2 error: " + err);
+    }
+  };
+
   const selectedEmail = emails.find(m => m.id === selectedMail);
 
   // ======== RENDER: ONBOARDING ========
@@ -1130,6 +1250,12 @@ export default function MailApp() {
             <input
               type="text"
               placeholder="Buscar..."
+              value={searchTerm}
+              onChange={(e) => setSearchTerm(e.target.value)}
+              onKeyDown={(e) => {
+                if (e.key === 'Enter') handleServerSearch();
+                if (e.key === 'Escape') { setSearchTerm(''); loadEmails(currentFolder); }
+              }}
               className="w-full bg-muted/50 border-none rounded-md pl-9 pr-3 py-1.5 text-sm outline-none focus:ring-1 focus:ring-primary/50 transition-shadow"
             />
           </div>
@@ -1261,6 +1387,17 @@ export default function MailApp() {
                 key={mail.id}
                 onClick={() => {
                   setSelectedMail(mail.id);
+                  // Bodies are fetched lazily — pull it from the server on first open
+                  if (!mail.body_cached) {
+                    invoke<string>('fetch_email_body', { emailId: mail.id })
+                      .then(body => setEmails(prev => prev.map(e => e.id === mail.id ? { ...e, body, body_cached: true } : e)))
+                      .catch(() => { });
+                  }
+                  if (!mail.read) {
+                    invoke('mark_read', { accountId: mail.account_id, emailIds: [mail.id], read: true })
+                      .then(() => setEmails(prev => prev.map(e => e.id === mail.id ? { ...e, read: true } : e)))
+                      .catch(() => { });
+                  }
                   if (!readEmails.has(mail.id)) {
                     setReadEmails(prev => {
                       const next = new Set(prev);
@@ -1825,10 +1962,68 @@ Usa los IDs exactos del contexto. No inventes IDs.`,
                       <input type="text" name="smtpHost" defaultValue={account?.smtp_host || ""} className="bg-muted/50 border border-border rounded-md px-3 py-2 text-sm w-full" placeholder="smtp.gmail.com" />
                     </div>
                   </div>
+                  <div className="flex gap-4">
+                    <div className="flex-1 flex flex-col gap-2">
+                      <label className="text-sm font-medium">Seguridad IMAP</label>
+                      <div className="flex gap-2">
+                        <select name="imapSecurity" defaultValue={account?.imap_security || "tls"} className="bg-muted/50 border border-border rounded-md px-3 py-2 text-sm flex-1">
+                          <option value="tls">SSL/TLS</option>
+                          <option value="starttls">STARTTLS</option>
+                          <option value="none">Ninguna</option>
+                        </select>
+                        <input type="number" name="imapPort" defaultValue={account?.imap_port || ""} className="bg-muted/50 border border-border rounded-md px-3 py-2 text-sm w-24" placeholder="993" />
+                      </div>
+                    </div>
+                    <div className="flex-1 flex flex-col gap-2">
+                      <label className="text-sm font-medium">Seguridad SMTP</label>
+                      <div className="flex gap-2">
+                        <select name="smtpSecurity" defaultValue={account?.smtp_security || "tls"} className="bg-muted/50 border border-border rounded-md px-3 py-2 text-sm flex-1">
+                          <option value="tls">SSL/TLS</option>
+                          <option value="starttls">STARTTLS</option>
+                          <option value="none">Ninguna</option>
+                        </select>
+                        <input type="number" name="smtpPort" defaultValue={account?.smtp_port || ""} className="bg-muted/50 border border-border rounded-md px-3 py-2 text-sm w-24" placeholder="465" />
+                      </div>
+                    </div>
+                  </div>
+                  <div className="grid gap-2">
+                    <label className="text-sm font-medium">Certificado CA (PEM, opcional)</label>
+                    <textarea name="caCert" defaultValue={account?.ca_cert || ""} rows={3} className="bg-muted/50 border border-border rounded-md px-3 py-2 text-xs font-mono" placeholder="-----BEGIN CERTIFICATE-----" />
+                  </div>
+                  <label className="flex items-center gap-2 text-sm">
+                    <input type="checkbox" name="tlsPinning" defaultChecked={account?.tls_pinning || false} />
+                    Fijar el certificado del servidor en la primera conexión
+                  </label>
                   <div className="grid gap-2">
                     <label className="text-sm font-medium">Contraseña App</label>
                     <input type="password" name="password" defaultValue={account?.password || ""} className="bg-muted/50 border border-border rounded-md px-3 py-2 text-sm" placeholder="••••••••••••" />
                   </div>
+                  <details className="border border-border rounded-md px-3 py-2">
+                    <summary className="text-sm font-medium cursor-pointer">
+                      OAuth2 {account?.auth_method === "oauth2" ? "(activo)" : ""}
+                    </summary>
+                    <div className="grid gap-2 pt-3">
+                      <input type="text" name="oauthAuthUrl" defaultValue="https://accounts.google.com/o/oauth2/v2/auth" className="bg-muted/50 border border-border rounded-md px-3 py-2 text-sm" placeholder="Authorization URL" />
+                      <input type="text" name="oauthTokenUrl" defaultValue="https://oauth2.googleapis.com/token" className="bg-muted/50 border border-border rounded-md px-3 py-2 text-sm" placeholder="Token URL" />
+                      <input type="text" name="oauthClientId" className="bg-muted/50 border border-border rounded-md px-3 py-2 text-sm" placeholder="Client ID" />
+                      <input type="password" name="oauthClientSecret" className="bg-muted/50 border border-border rounded-md px-3 py-2 text-sm" placeholder="Client secret (opcional)" />
+                      <input type="text" name="oauthScope" defaultValue="https://mail.google.com/" className="bg-muted/50 border border-border rounded-md px-3 py-2 text-sm" placeholder="Scope" />
+                      <select name="oauthMechanism" defaultValue="XOAUTH2" className="bg-muted/50 border border-border rounded-md px-3 py-2 text-sm">
+                        <option value="XOAUTH2">XOAUTH2</option>
+                        <option value="OAUTHBEARER">OAUTHBEARER</option>
+                      </select>
+                      <div className="flex gap-3">
+                        <button type="button" onClick={handleOAuthSignIn} className="bg-secondary text-secondary-foreground hover:bg-secondary/80 py-2 rounded-md text-sm font-medium transition-colors px-4">
+                          Iniciar sesión con OAuth2
+                        </button>
+                        {account?.auth_method === "oauth2" && (
+                          <button type="button" onClick={handleOAuthSignOut} className="bg-secondary text-secondary-foreground hover:bg-secondary/80 py-2 rounded-md text-sm font-medium transition-colors px-4">
+                            Usar contraseña
+                          </button>
+                        )}
+                      </div>
+                    </div>
+                  </details>
                   <div className="flex items-center gap-3 pt-2">
                     <button type="submit" className="bg-primary text-primary-foreground hover:bg-primary/90 py-2 rounded-md text-sm font-medium transition-colors px-4">
                       Guardar Cuenta